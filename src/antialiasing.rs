@@ -0,0 +1,222 @@
+use crate::{memory, renderer};
+
+// Samples per pixel with AntiAliasing::Msaa
+pub const MSAA_SAMPLE_COUNT: u32 = 4;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum AntiAliasing {
+    #[default]
+    None,
+    // The scene is drawn into a multisampled target and resolved into the
+    // scene texture
+    Msaa,
+    // A post process pass that blurs along edges after tone mapping.
+    // Cheaper than MSAA, but softens textures a little.
+    Fxaa,
+}
+
+impl AntiAliasing {
+    // Sample count the scene pipelines and depth texture need
+    pub fn sample_count(self) -> u32 {
+        match self {
+            AntiAliasing::Msaa => MSAA_SAMPLE_COUNT,
+            AntiAliasing::None | AntiAliasing::Fxaa => 1,
+        }
+    }
+}
+
+// The multisampled color target the scene is drawn into with MSAA
+pub struct MsaaTarget {
+    pub texture: memory::Tracked<wgpu::Texture>,
+    pub view: wgpu::TextureView,
+}
+
+impl MsaaTarget {
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        (width, height): (u32, u32),
+        sample_count: u32,
+    ) -> Self {
+        let texture = memory::create_texture(
+            device,
+            &wgpu::TextureDescriptor {
+                label: Some("msaa_texture"),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            },
+        );
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        Self { texture, view }
+    }
+}
+
+// Tone mapping writes into texture instead of the swapchain, then process
+// smooths the edges on the way to the swapchain
+pub struct Fxaa {
+    pub texture: memory::Tracked<wgpu::Texture>,
+    pub view: wgpu::TextureView,
+    sampler: wgpu::Sampler,
+    layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    render_pipeline: wgpu::RenderPipeline,
+}
+
+impl Fxaa {
+    pub fn new(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> Self {
+        let (texture, view) = Self::create_texture(device, config);
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        // The blend relies on filtering between pixels
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("fxaa_bind_group_layout"),
+        });
+        let bind_group = Self::create_bind_group(device, &layout, &view, &sampler);
+
+        let shader = wgpu::ShaderModuleDescriptor {
+            label: Some("FXAA Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/fxaa.wgsl").into()),
+        };
+        let pipeline_layout = renderer::RenderPipeline::create_pipeline_layout(device, &[&layout]);
+        let render_pipeline = renderer::RenderPipeline::new(
+            device,
+            &pipeline_layout,
+            config.format,
+            None,
+            &[],
+            shader,
+            renderer::PipelineOptions::default(),
+        )
+        .render_pipeline;
+
+        Self {
+            texture,
+            view,
+            sampler,
+            layout,
+            bind_group,
+            render_pipeline,
+        }
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        let (texture, view) = Self::create_texture(device, config);
+        self.bind_group = Self::create_bind_group(device, &self.layout, &view, &self.sampler);
+        self.texture = texture;
+        self.view = view;
+    }
+
+    // Writes the anti-aliased texture into output, overwriting it. The
+    // viewport limits what gets drawn to part of output, see
+    // State::target_aspect.
+    pub fn process(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        output: &wgpu::TextureView,
+        viewport: Option<&renderer::Viewport>,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("FXAA Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        if let Some(viewport) = viewport {
+            viewport.apply(&mut render_pass);
+        }
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    // Matches the surface, since it sits between tone mapping and the
+    // swapchain
+    fn create_texture(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> (memory::Tracked<wgpu::Texture>, wgpu::TextureView) {
+        let texture = memory::create_texture(
+            device,
+            &wgpu::TextureDescriptor {
+                label: Some("fxaa_texture"),
+                size: wgpu::Extent3d {
+                    width: config.width,
+                    height: config.height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: config.format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+            },
+        );
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        (texture, view)
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        view: &wgpu::TextureView,
+        sampler: &wgpu::Sampler,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+            label: Some("fxaa_bind_group"),
+        })
+    }
+}
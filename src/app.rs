@@ -0,0 +1,437 @@
+use std::time::Duration;
+
+use cgmath::Rotation3;
+use winit::{
+    event::*,
+    event_loop::{ControlFlow, EventLoop, EventLoopWindowTarget},
+    window::{Window, WindowBuilder},
+};
+
+use crate::{camera, display, instance, primitives, recording, State};
+
+type Callback = Box<dyn FnMut(&mut State, Duration)>;
+
+// Owns the window and event loop for anyone embedding the engine. Game logic
+// goes in on_update, which runs before the state's own update each frame, so
+// anything it moves is drawn that same frame. on_ui runs after, right before
+// rendering, with the camera and lights where they'll be drawn.
+//
+//     App::new()
+//         .with_title("Game")
+//         .on_update(|state, dt| { .. })
+//         .run()
+//         .await;
+pub struct App {
+    window: WindowBuilder,
+    extra_windows: Vec<WindowBuilder>,
+    camera: camera::CameraConfig,
+    demo_scene: bool,
+    on_demand: bool,
+    power_saving: bool,
+    record_input: Option<String>,
+    replay_input: Option<String>,
+    update: Option<Callback>,
+    ui: Option<Callback>,
+}
+
+impl App {
+    pub fn new() -> Self {
+        Self {
+            window: WindowBuilder::new().with_title(env!("CARGO_PKG_NAME")),
+            extra_windows: Vec::new(),
+            camera: camera::CameraConfig::default(),
+            demo_scene: false,
+            on_demand: false,
+            power_saving: false,
+            record_input: None,
+            replay_input: None,
+            update: None,
+            ui: None,
+        }
+    }
+
+    // Replaces the whole window setup, title included
+    pub fn with_window(mut self, window: WindowBuilder) -> Self {
+        self.window = window;
+        self
+    }
+
+    pub fn with_title(mut self, title: &str) -> Self {
+        self.window = self.window.with_title(title);
+        self
+    }
+
+    // Opens another window showing the same scene, see State::add_window.
+    // It can be resized and closed on its own, closing the main window
+    // still ends the process.
+    pub fn with_extra_window(mut self, window: WindowBuilder) -> Self {
+        self.extra_windows.push(window);
+        self
+    }
+
+    // Also where State::reset_camera goes back to
+    pub fn with_camera(mut self, camera: camera::CameraConfig) -> Self {
+        self.camera = camera;
+        self
+    }
+
+    // Adds the models crate::run shows on top of the default scene
+    pub fn with_demo_scene(mut self, demo_scene: bool) -> Self {
+        self.demo_scene = demo_scene;
+        self
+    }
+
+    // Sleeps until there's input instead of drawing continuously, for
+    // viewers of a still scene. A frame is drawn after every window event,
+    // and for as long as State::needs_redraw says so. on_update can keep
+    // frames coming with State::request_redraw.
+    pub fn with_on_demand(mut self, on_demand: bool) -> Self {
+        self.on_demand = on_demand;
+        self
+    }
+
+    // Sleeps between frames instead of spinning, waking up in time for the
+    // monitor's next refresh, see display::refresh_interval. Input still
+    // wakes the loop and is handled straight away, only drawing waits, and
+    // dt is measured the same way. Does nothing with on_demand, which sleeps
+    // anyway.
+    pub fn with_power_saving(mut self, power_saving: bool) -> Self {
+        self.power_saving = power_saving;
+        self
+    }
+
+    // Records the camera input while running and saves it to path when the
+    // window closes, see recording::InputRecording
+    pub fn with_input_recording(mut self, path: &str) -> Self {
+        self.record_input = Some(path.to_string());
+        self
+    }
+
+    // Drives the camera from a recording made with with_input_recording,
+    // frame by frame with the recorded dt, for fly-throughs that come out
+    // the same every run. Live camera input still gets through, so leave
+    // the controls alone during a replay.
+    pub fn with_input_replay(mut self, path: &str) -> Self {
+        self.replay_input = Some(path.to_string());
+        self
+    }
+
+    pub fn on_update(mut self, update: impl FnMut(&mut State, Duration) + 'static) -> Self {
+        self.update = Some(Box::new(update));
+        self
+    }
+
+    pub fn on_ui(mut self, ui: impl FnMut(&mut State, Duration) + 'static) -> Self {
+        self.ui = Some(Box::new(ui));
+        self
+    }
+
+    // Opens the window and runs until it is closed, which also ends the
+    // process. The only way to run on the web, see run_return otherwise.
+    pub async fn run(self) {
+        let event_loop = EventLoop::new();
+        if let Some(handler) = self.start(&event_loop).await {
+            event_loop.run(handler)
+        }
+    }
+
+    // Like run, but returns once the main window is closed, with the
+    // windows and the State dropped, so the caller can clean up or start
+    // another App. Only on the desktop platforms and Android, winit can't
+    // hand control back from the browser's or iOS's event loop. Some
+    // platforms, macOS among them, can't make a second event loop on the
+    // same thread either, so keep the first one around there.
+    #[cfg(any(
+        target_os = "windows",
+        target_os = "macos",
+        target_os = "android",
+        target_os = "linux",
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "openbsd"
+    ))]
+    pub async fn run_return(self) {
+        use winit::platform::run_return::EventLoopExtRunReturn;
+
+        let mut event_loop = EventLoop::new();
+        if let Some(handler) = self.start(&event_loop).await {
+            event_loop.run_return(handler);
+        }
+    }
+
+    // Opens the windows and sets up the State, then returns the event
+    // handler that drives them. None if the State couldn't be created.
+    async fn start(
+        self,
+        event_loop: &EventLoop<()>,
+    ) -> Option<impl FnMut(Event<'_, ()>, &EventLoopWindowTarget<()>, &mut ControlFlow) + 'static>
+    {
+        // The embedding application may have set up logging already
+        let _ = env_logger::try_init();
+        let window = self.window.build(event_loop).unwrap();
+
+        // State::new uses async code, so we're going to wait for it to finish
+        let mut state = match State::new(&window, self.camera).await {
+            Ok(state) => state,
+            Err(e) => {
+                log::error!("{:?}", e);
+                return None;
+            }
+        };
+        if self.demo_scene {
+            add_demo_scene(&mut state).await;
+        }
+        let mut extra_windows = Vec::new();
+        for builder in self.extra_windows {
+            let extra_window = builder.build(event_loop).unwrap();
+            match state.add_window(&extra_window) {
+                Ok(()) => extra_windows.push(extra_window),
+                Err(e) => log::error!("{:?}", e),
+            }
+        }
+
+        let record_input = self.record_input;
+        if record_input.is_some() {
+            state.camera_controller_mut().start_recording();
+        }
+        let mut replay = self.replay_input.and_then(|path| {
+            recording::InputRecording::load(&path)
+                .map_err(|e| log::error!("{:?}", e))
+                .ok()
+                .map(recording::InputReplay::new)
+        });
+
+        let mut update = self.update;
+        let mut ui = self.ui;
+        let mut last_render_time = instant::Instant::now();
+        // Dragging a window edge fires a burst of resize events, so only the
+        // latest size is kept and applied once at the next redraw
+        let mut pending_size: Option<winit::dpi::PhysicalSize<u32>> = None;
+        let on_demand = self.on_demand;
+        // Set by input until the next redraw, in on demand mode
+        let mut dirty = true;
+        // Whether the loop slept since the last frame. Whatever woke it
+        // happened just now, so the sleep mustn't count towards dt.
+        let mut idle = false;
+        let power_saving = self.power_saving && !on_demand;
+        // Kept up to date as the window moves between monitors
+        let mut frame_interval = display::refresh_interval(&window);
+        let mut running = Running {
+            state,
+            extra_windows,
+            window,
+        };
+        Some(
+            move |event: Event<'_, ()>,
+                  _: &EventLoopWindowTarget<()>,
+                  control_flow: &mut ControlFlow| {
+                let Running {
+                    state,
+                    extra_windows,
+                    window,
+                } = &mut running;
+                *control_flow = if on_demand {
+                    ControlFlow::Wait
+                } else if power_saving {
+                    ControlFlow::WaitUntil(last_render_time + frame_interval)
+                } else {
+                    ControlFlow::Poll
+                };
+                if on_demand {
+                    match &event {
+                        Event::WindowEvent { .. } => dirty = true,
+                        Event::DeviceEvent {
+                            event: DeviceEvent::MouseMotion { .. },
+                            ..
+                        } if state.mouse_pressed => dirty = true,
+                        _ => {}
+                    }
+                    if dirty && idle {
+                        idle = false;
+                        last_render_time = instant::Instant::now();
+                    }
+                }
+                match event {
+                Event::MainEventsCleared => {
+                    if on_demand && !dirty && !state.needs_redraw() {
+                        idle = true;
+                    } else if !power_saving
+                        || instant::Instant::now() >= last_render_time + frame_interval
+                    {
+                        window.request_redraw();
+                    }
+                }
+
+                Event::DeviceEvent {
+                    event: DeviceEvent::MouseMotion { delta },
+                    .. // We're not using device_id currently
+                } if state.mouse_pressed && !state.camera_frozen => {
+                    state.camera_controller.process_mouse(delta.0, delta.1)
+                }
+
+                // Only the main window takes input. The others just present,
+                // so their resizes are cheap enough to apply straight away.
+                Event::WindowEvent { event, window_id } if window_id != window.id() => {
+                    match event {
+                        WindowEvent::CloseRequested => {
+                            // The surface has to go before its window
+                            state.remove_window(window_id);
+                            extra_windows.retain(|extra_window| extra_window.id() != window_id);
+                        }
+                        WindowEvent::Resized(physical_size) => {
+                            state.resize_window(window_id, physical_size)
+                        }
+                        WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                            state.resize_window(window_id, *new_inner_size)
+                        }
+                        _ => {}
+                    }
+                }
+
+                Event::WindowEvent {
+                    ref event,
+                    window_id,
+                } if window_id == window.id() && !state.input(event) => match event {
+                    #[cfg(not(target_arch = "wasm32"))]
+                    WindowEvent::CloseRequested
+                    | WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::Escape),
+                                ..
+                            },
+                        ..
+                    } => *control_flow = ControlFlow::Exit,
+                    WindowEvent::Resized(physical_size) => {
+                        pending_size = Some(*physical_size);
+                    }
+                    WindowEvent::ScaleFactorChanged {
+                        scale_factor,
+                        new_inner_size,
+                    } => {
+                        state.set_scale_factor(*scale_factor);
+                        state.set_monitors(display::monitors(window));
+                        frame_interval = display::refresh_interval(window);
+                        pending_size = Some(**new_inner_size);
+                    }
+                    _ => {}
+                },
+
+                Event::RedrawRequested(window_id) if window_id == window.id() => {
+                    // Exclusive fullscreen is resized up front, the Resized
+                    // event that follows can lag behind the mode switch
+                    if let Some(mode) = state.take_display_mode_change() {
+                        window.set_fullscreen(mode.fullscreen());
+                        if let display::DisplayMode::Exclusive(video_mode) = &mode {
+                            pending_size = Some(video_mode.size());
+                        }
+                    }
+                    // The event loop keeps polling, so this runs even after the
+                    // resize events stop and the final size always gets applied
+                    if let Some(size) = pending_size.take() {
+                        state.resize(size);
+                    }
+                    dirty = false;
+                    // Moves on every redraw, paused or not, so unpausing
+                    // doesn't see the whole pause as one long frame
+                    let now = instant::Instant::now();
+                    let mut dt = now - last_render_time;
+                    last_render_time = now;
+                    if let Some(frame_replay) = &mut replay {
+                        match frame_replay.next_frame(state.camera_controller_mut()) {
+                            Some(recorded) => dt = recorded,
+                            None => {
+                                log::info!("Input replay finished");
+                                replay = None;
+                            }
+                        }
+                    }
+                    if let Some(update) = &mut update {
+                        update(state, dt);
+                    }
+                    state.update(dt);
+                    if let Some(ui) = &mut ui {
+                        ui(state, dt);
+                    }
+                    match state.render() {
+                        Ok(_) => {}
+                        // Reconfigure the surface if it's lost or outdated
+                        Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                            state.resize(state.context.size)
+                        }
+                        // The system is out of memory, we should probably quit
+                        Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
+                        // We're ignoring timeouts
+                        Err(wgpu::SurfaceError::Timeout) => log::warn!("Surface timeout"),
+                    }
+                }
+                Event::LoopDestroyed => {
+                    let recorded = state.camera_controller_mut().stop_recording();
+                    if let (Some(path), Some(recorded)) = (&record_input, recorded) {
+                        match recorded.save(path) {
+                            Ok(()) => log::info!("Saved input recording to {}", path),
+                            Err(e) => log::error!("{:?}", e),
+                        }
+                    }
+                }
+                _ => {}
+            }
+            },
+        )
+    }
+}
+
+// What the event handler owns. Fields drop in order, so the surfaces in
+// state go before the windows they present to, which run_return relies on.
+struct Running {
+    state: State,
+    extra_windows: Vec<Window>,
+    window: Window,
+}
+
+impl Default for App {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+async fn add_demo_scene(state: &mut State) {
+    // A row of pyramids behind the cubes, drawn from their own instance buffer
+    let pyramids = (0..5)
+        .map(|i| instance::Instance::new(i as f32 * 6.0 - 12.0, -20.0))
+        .collect::<Vec<_>>();
+    state
+        .add_model("pyramids", "pyramid.obj", &pyramids)
+        .await
+        .unwrap();
+
+    // A few generated shapes off to the side, batched into one draw each
+    let sphere = state
+        .create_primitive(primitives::sphere(0.5, 32), "sphere")
+        .unwrap();
+    let sphere = state.add_batched_model(sphere);
+    let cylinder = state
+        .create_primitive(primitives::cylinder(0.5, 1.0, 24), "cylinder")
+        .unwrap();
+    let cylinder = state.add_batched_model(cylinder);
+    let upright = cgmath::Quaternion::from_angle_y(cgmath::Deg(0.0));
+    for i in 0..3 {
+        let z = i as f32 * 3.0 - 3.0;
+        let at = |x: f32| instance::Instance::with_rotation((x, 0.0, z).into(), upright);
+        state.add_object(sphere, at(-16.0)).unwrap();
+        state.add_object(cylinder, at(-19.0)).unwrap();
+    }
+
+    // A standing quad lit from both sides, see model::Material::set_two_sided
+    let mut leaf = state
+        .create_primitive(primitives::plane(2.0, 0), "leaf")
+        .unwrap();
+    for material in &mut leaf.materials {
+        material.set_two_sided(&state.context.queue, true);
+    }
+    let leaf_transform = cgmath::Matrix4::from_translation((-17.5, 1.0, 6.0).into())
+        * cgmath::Matrix4::from_angle_x(cgmath::Deg(90.0));
+    state.add_static_model(leaf, leaf_transform);
+}
@@ -0,0 +1,141 @@
+use crate::{memory, renderer, texture};
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct BackgroundUniform {
+    top: [f32; 4],
+    bottom: [f32; 4],
+}
+
+// A vertical gradient behind the scene, from the top of the scene target to
+// its bottom, instead of the flat clear color. Drawn at the far plane without
+// writing depth, so everything in the scene ends up in front of it.
+pub struct Background {
+    // Linear top and bottom colors, None for the flat clear
+    gradient: Option<([f32; 3], [f32; 3])>,
+    render_pipeline: wgpu::RenderPipeline,
+    pipeline_layout: wgpu::PipelineLayout,
+    uniform_buffer: memory::Tracked<wgpu::Buffer>,
+    bind_group: wgpu::BindGroup,
+}
+
+impl Background {
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let uniform_buffer = memory::create_buffer(
+            device,
+            &wgpu::BufferDescriptor {
+                label: Some("Background Buffer"),
+                size: std::mem::size_of::<BackgroundUniform>() as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            },
+        );
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("background_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+            label: Some("background_bind_group"),
+        });
+
+        let pipeline_layout = renderer::RenderPipeline::create_pipeline_layout(device, &[&layout]);
+        let render_pipeline =
+            Self::create_pipeline(device, color_format, sample_count, &pipeline_layout);
+
+        Self {
+            gradient: None,
+            render_pipeline,
+            pipeline_layout,
+            uniform_buffer,
+            bind_group,
+        }
+    }
+
+    // For when the target the background is drawn into changes
+    pub fn recreate_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) {
+        self.render_pipeline =
+            Self::create_pipeline(device, color_format, sample_count, &self.pipeline_layout);
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        pipeline_layout: &wgpu::PipelineLayout,
+    ) -> wgpu::RenderPipeline {
+        let shader = wgpu::ShaderModuleDescriptor {
+            label: Some("Background Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/background.wgsl").into()),
+        };
+        renderer::RenderPipeline::new(
+            device,
+            pipeline_layout,
+            color_format,
+            Some(texture::Texture::DEPTH_FORMAT),
+            &[],
+            shader,
+            // The depth buffer is cleared to the far plane already
+            renderer::PipelineOptions {
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Always,
+                cull_mode: None,
+                sample_count,
+                ..Default::default()
+            },
+        )
+        .render_pipeline
+    }
+
+    pub fn gradient(&self) -> Option<([f32; 3], [f32; 3])> {
+        self.gradient
+    }
+
+    pub fn set_gradient(&mut self, queue: &wgpu::Queue, gradient: Option<([f32; 3], [f32; 3])>) {
+        self.gradient = gradient;
+        if let Some((top, bottom)) = gradient {
+            let rgba = |[r, g, b]: [f32; 3]| [r, g, b, 1.0];
+            queue.write_buffer(
+                &self.uniform_buffer,
+                0,
+                bytemuck::cast_slice(&[BackgroundUniform {
+                    top: rgba(top),
+                    bottom: rgba(bottom),
+                }]),
+            );
+        }
+    }
+
+    // Draws nothing without a gradient
+    pub fn render<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if self.gradient.is_none() {
+            return;
+        }
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
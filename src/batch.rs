@@ -0,0 +1,168 @@
+use std::ops::Range;
+
+use crate::{instance, memory, model};
+
+// The layer everything outside the batcher is drawn in, see
+// State::set_object_layer
+pub const WORLD_LAYER: i32 = 0;
+
+// Refers to an object added with Batcher::add_object
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ObjectId {
+    pub model: usize,
+    index: usize,
+}
+
+struct Batch {
+    model: model::Model,
+    objects: Vec<instance::Instance>,
+    // Parallel to objects
+    layers: Vec<i32>,
+    // None until the first object is uploaded
+    instance_buffer: Option<memory::Tracked<wgpu::Buffer>>,
+    capacity: u32,
+    // Instances of each layer in instance_buffer as of the last prepare,
+    // which uploads the objects sorted by layer
+    runs: Vec<(i32, Range<u32>)>,
+    // Set when objects changed since the last upload
+    dirty: bool,
+}
+
+// Groups objects by model so every model is drawn with a single instanced
+// draw, however few objects use it. Instance buffers are created and grown
+// as objects are added.
+#[derive(Default)]
+pub struct Batcher {
+    batches: Vec<Batch>,
+}
+
+impl Batcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Returns the id to add objects of this model with
+    pub fn add_model(&mut self, model: model::Model) -> usize {
+        self.batches.push(Batch {
+            model,
+            objects: Vec::new(),
+            layers: Vec::new(),
+            instance_buffer: None,
+            capacity: 0,
+            runs: Vec::new(),
+            dirty: false,
+        });
+        self.batches.len() - 1
+    }
+
+    pub fn add_object(
+        &mut self,
+        model_id: usize,
+        transform: instance::Instance,
+    ) -> anyhow::Result<ObjectId> {
+        let batch = self
+            .batches
+            .get_mut(model_id)
+            .ok_or_else(|| anyhow::anyhow!("No batched model with id {}", model_id))?;
+        batch.objects.push(transform);
+        batch.layers.push(WORLD_LAYER);
+        batch.dirty = true;
+
+        Ok(ObjectId {
+            model: model_id,
+            index: batch.objects.len() - 1,
+        })
+    }
+
+    pub fn set_transform(&mut self, id: ObjectId, transform: instance::Instance) {
+        let batch = &mut self.batches[id.model];
+        batch.objects[id.index] = transform;
+        batch.dirty = true;
+    }
+
+    pub fn transform(&self, id: ObjectId) -> instance::Instance {
+        self.batches[id.model].objects[id.index]
+    }
+
+    pub fn set_layer(&mut self, id: ObjectId, layer: i32) {
+        let batch = &mut self.batches[id.model];
+        if batch.layers[id.index] != layer {
+            batch.layers[id.index] = layer;
+            batch.dirty = true;
+        }
+    }
+
+    pub fn layer(&self, id: ObjectId) -> i32 {
+        self.batches[id.model].layers[id.index]
+    }
+
+    // Every layer with objects in it as of the last prepare, lowest first
+    pub fn layers(&self) -> Vec<i32> {
+        let mut layers = self
+            .batches
+            .iter()
+            .flat_map(|batch| batch.runs.iter().map(|(layer, _)| *layer))
+            .collect::<Vec<_>>();
+        layers.sort_unstable();
+        layers.dedup();
+        layers
+    }
+
+    pub fn model(&self, model_id: usize) -> &model::Model {
+        &self.batches[model_id].model
+    }
+
+    // Uploads the instances of every batch that changed. Buffers that are
+    // too small are replaced with ones sized to the next power of two.
+    pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+        for batch in self.batches.iter_mut().filter(|b| b.dirty) {
+            // Stable, so objects keep the order they were added in within a
+            // layer
+            let mut order = (0..batch.objects.len()).collect::<Vec<_>>();
+            order.sort_by_key(|&i| batch.layers[i]);
+            let instance_data = order
+                .iter()
+                .map(|&i| batch.objects[i].to_raw())
+                .collect::<Vec<_>>();
+            batch.runs.clear();
+            for (slot, &i) in order.iter().enumerate() {
+                let slot = slot as u32;
+                match batch.runs.last_mut() {
+                    Some((layer, run)) if *layer == batch.layers[i] => run.end = slot + 1,
+                    _ => batch.runs.push((batch.layers[i], slot..slot + 1)),
+                }
+            }
+            let count = instance_data.len() as u32;
+            match &batch.instance_buffer {
+                Some(buffer) if count <= batch.capacity => {
+                    queue.write_buffer(buffer, 0, bytemuck::cast_slice(&instance_data));
+                }
+                _ => {
+                    let capacity = count.next_power_of_two();
+                    batch.instance_buffer = Some(
+                        instance::InstanceBufferBuilder::new(instance_data)
+                            .max_instances(capacity)
+                            .build(device)?,
+                    );
+                    batch.capacity = capacity;
+                }
+            }
+            batch.dirty = false;
+        }
+
+        Ok(())
+    }
+
+    // Model, instance buffer and instance range of every batch with objects
+    // in the layer, as of the last prepare
+    pub fn draws(
+        &self,
+        layer: i32,
+    ) -> impl Iterator<Item = (&model::Model, &wgpu::Buffer, Range<u32>)> {
+        self.batches.iter().filter_map(move |batch| {
+            let buffer = batch.instance_buffer.as_ref()?;
+            let (_, instances) = batch.runs.iter().find(|(l, _)| *l == layer)?;
+            Some((&batch.model, &**buffer, instances.clone()))
+        })
+    }
+}
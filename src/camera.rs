@@ -1,10 +1,11 @@
 use cgmath::*;
-use std::f32::consts::FRAC_PI_2;
+use std::f32::consts::{FRAC_PI_2, PI};
 use std::time::Duration;
-use wgpu::util::DeviceExt;
 use winit::dpi::PhysicalPosition;
 use winit::event::*;
 
+use crate::{memory, recording};
+
 #[rustfmt::skip]
 pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
     1.0, 0.0, 0.0, 0.0,
@@ -14,8 +15,13 @@ pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
 );
 
 const SAFE_FRAC_PI_2: f32 = FRAC_PI_2 - 0.0001;
+// Past this CameraController::smoothing stops feeling cinematic and just lags
+pub const MAX_SMOOTHING: f32 = 0.5;
+const SMOOTHING_EPSILON: f32 = 1e-4;
+// Closest scrolling gets the camera to the target in ControllerMode::Orbit
+const MIN_ORBIT_RADIUS: f32 = 0.1;
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Camera {
     pub position: Point3<f32>,
     yaw: Rad<f32>,
@@ -35,40 +41,127 @@ impl Camera {
         }
     }
 
-    pub fn calc_matrix(&self) -> Matrix4<f32> {
+    // Where the camera is, same as position
+    pub fn eye(&self) -> Point3<f32> {
+        self.position
+    }
+
+    pub fn set_position<V: Into<Point3<f32>>>(&mut self, position: V) {
+        self.position = position.into();
+    }
+
+    pub fn yaw(&self) -> Rad<f32> {
+        self.yaw
+    }
+
+    pub fn pitch(&self) -> Rad<f32> {
+        self.pitch
+    }
+
+    // Pitch is kept just short of straight up or down, like the controller
+    // keeps it, where the view matrix would break down
+    pub fn set_yaw_pitch<Y: Into<Rad<f32>>, P: Into<Rad<f32>>>(&mut self, yaw: Y, pitch: P) {
+        self.yaw = yaw.into();
+        self.pitch = Rad(pitch.into().0.clamp(-SAFE_FRAC_PI_2, SAFE_FRAC_PI_2));
+    }
+
+    // Unit vector the camera looks along
+    pub fn forward(&self) -> Vector3<f32> {
         let (sin_pitch, cos_pitch) = self.pitch.0.sin_cos();
         let (sin_yaw, cos_yaw) = self.yaw.0.sin_cos();
 
-        Matrix4::look_to_rh(
-            self.position,
-            Vector3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw).normalize(),
-            Vector3::unit_y(),
-        )
+        Vector3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw).normalize()
+    }
+
+    pub fn calc_matrix(&self) -> Matrix4<f32> {
+        Matrix4::look_to_rh(self.position, self.forward(), Vector3::unit_y())
+    }
+
+    // World space ray through the pixel at (x, y), measured from the top left
+    // of a viewport_size sized viewport. Returns the point on the near plane
+    // and the normalized direction away from the camera.
+    pub fn screen_ray(
+        &self,
+        projection: &Projection,
+        x: f32,
+        y: f32,
+        viewport_size: (f32, f32),
+    ) -> (Point3<f32>, Vector3<f32>) {
+        let ndc_x = 2.0 * x / viewport_size.0 - 1.0;
+        let ndc_y = 1.0 - 2.0 * y / viewport_size.1;
+
+        // calc_matrix already includes OPENGL_TO_WGPU_MATRIX, so the near and
+        // far planes sit at a depth of 0 and 1
+        let view_proj = projection.calc_matrix() * self.calc_matrix();
+        let inverse = match view_proj.invert() {
+            Some(inverse) => inverse,
+            None => return (self.position, self.forward()),
+        };
+        let unproject = |z: f32| {
+            let point = inverse * Vector4::new(ndc_x, ndc_y, z, 1.0);
+            Point3::from_homogeneous(point)
+        };
+        let near = unproject(0.0);
+        let far = unproject(1.0);
+
+        (near, (far - near).normalize())
+    }
+
+    // What the camera sees through projection, for culling
+    pub fn frustum(&self, projection: &Projection) -> Frustum {
+        Frustum::from_view_proj(projection.calc_matrix() * self.calc_matrix())
     }
 
     pub fn create_buffer_init(
         device: &wgpu::Device,
         camera_uniform: CameraUniform,
-    ) -> wgpu::Buffer {
-        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Camera Buffer"),
-            contents: bytemuck::cast_slice(&[camera_uniform]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        })
+    ) -> memory::Tracked<wgpu::Buffer> {
+        memory::create_buffer_init(
+            device,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Camera Buffer"),
+                contents: bytemuck::cast_slice(&[camera_uniform]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            },
+        )
     }
 
     pub fn camera_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
         device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Per-view render settings (see settings::SettingsUniform)
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
                 },
-                count: None,
-            }],
+                // Time and resolution (see settings::GlobalsUniform)
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
             label: Some("camera_bind_group_layout"),
         })
     }
@@ -77,21 +170,113 @@ impl Camera {
         device: &wgpu::Device,
         camera_bind_group_layout: &wgpu::BindGroupLayout,
         camera_buffer: &wgpu::Buffer,
+        settings_buffer: &wgpu::Buffer,
+        globals_buffer: &wgpu::Buffer,
     ) -> wgpu::BindGroup {
         device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: camera_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: camera_buffer.as_entire_binding(),
-            }],
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: camera_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: settings_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: globals_buffer.as_entire_binding(),
+                },
+            ],
             label: Some("camera_bind_group"),
         })
     }
 }
 
+// Where the camera starts and how it moves, see App::with_camera
+#[derive(Debug, Copy, Clone)]
+pub struct CameraConfig {
+    pub position: Point3<f32>,
+    pub yaw: Deg<f32>,
+    pub pitch: Deg<f32>,
+    // Vertical field of view
+    pub fovy: Deg<f32>,
+    pub znear: f32,
+    pub zfar: f32,
+    // Units per second
+    pub speed: f32,
+    pub sensitivity: f32,
+}
+
+impl CameraConfig {
+    pub fn camera(&self) -> Camera {
+        Camera::new(self.position, self.yaw, self.pitch)
+    }
+
+    pub fn projection(&self, width: u32, height: u32) -> Projection {
+        Projection::new(width, height, self.fovy, self.znear, self.zfar)
+    }
+
+    // Shows the world origin at the size projection does, seen from
+    // position
+    pub fn orthographic_projection(&self, width: u32, height: u32) -> Projection {
+        let half_height = self.position.to_vec().magnitude() * (Rad::from(self.fovy) / 2.0).tan();
+        let half_width = half_height * width as f32 / height as f32;
+        Projection::new_orthographic(
+            width,
+            height,
+            -half_width,
+            half_width,
+            -half_height,
+            half_height,
+            self.znear,
+            self.zfar,
+        )
+    }
+
+    pub fn controller(&self) -> CameraController {
+        CameraController::new(self.speed, self.sensitivity)
+    }
+}
+
+impl Default for CameraConfig {
+    fn default() -> Self {
+        Self {
+            position: Point3::new(0.0, 5.0, 10.0),
+            yaw: Deg(-90.0),
+            pitch: Deg(-20.0),
+            fovy: Deg(45.0),
+            znear: 0.1,
+            zfar: 100.0,
+            speed: 4.0,
+            sensitivity: 0.4,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum ProjectionKind {
+    Perspective {
+        aspect: f32,
+        fovy: Rad<f32>,
+    },
+    // The bounds as given for a width by height viewport, which resize scales
+    // from so nothing drifts
+    Orthographic {
+        width: f32,
+        height: f32,
+        left: f32,
+        right: f32,
+        bottom: f32,
+        top: f32,
+        scale: Vector2<f32>,
+    },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Projection {
-    aspect: f32,
-    fovy: Rad<f32>,
+    kind: ProjectionKind,
     znear: f32,
     zfar: f32,
 }
@@ -99,22 +284,160 @@ pub struct Projection {
 impl Projection {
     pub fn new<F: Into<Rad<f32>>>(width: u32, height: u32, fovy: F, znear: f32, zfar: f32) -> Self {
         Self {
-            aspect: width as f32 / height as f32,
-            fovy: fovy.into(),
+            kind: ProjectionKind::Perspective {
+                aspect: width as f32 / height as f32,
+                fovy: fovy.into(),
+            },
             znear,
             zfar,
         }
     }
 
-    pub fn resize(&mut self, width: u32, height: u32) {
-        self.aspect = width as f32 / height as f32;
+    // A parallel projection of the view space box from left to right, bottom
+    // to top and znear to zfar in front of the camera, for a width by height
+    // viewport. Resizing keeps the units per pixel, so a box of 0 to width
+    // and 0 to height stays in pixels. Depth stays linear under
+    // settings::DepthMode::Logarithmic, which assumes perspective.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_orthographic(
+        width: u32,
+        height: u32,
+        left: f32,
+        right: f32,
+        bottom: f32,
+        top: f32,
+        znear: f32,
+        zfar: f32,
+    ) -> Self {
+        Self {
+            kind: ProjectionKind::Orthographic {
+                width: width as f32,
+                height: height as f32,
+                left,
+                right,
+                bottom,
+                top,
+                scale: Vector2::new(1.0, 1.0),
+            },
+            znear,
+            zfar,
+        }
+    }
+
+    pub fn resize(&mut self, new_width: u32, new_height: u32) {
+        match &mut self.kind {
+            ProjectionKind::Perspective { aspect, .. } => {
+                *aspect = new_width as f32 / new_height as f32;
+            }
+            ProjectionKind::Orthographic {
+                width,
+                height,
+                scale,
+                ..
+            } => {
+                *scale = Vector2::new(new_width as f32 / *width, new_height as f32 / *height);
+            }
+        }
+    }
+
+    pub fn is_orthographic(&self) -> bool {
+        matches!(self.kind, ProjectionKind::Orthographic { .. })
+    }
+
+    pub fn znear(&self) -> f32 {
+        self.znear
+    }
+
+    pub fn zfar(&self) -> f32 {
+        self.zfar
     }
 
     pub fn calc_matrix(&self) -> Matrix4<f32> {
-        OPENGL_TO_WGPU_MATRIX * perspective(self.fovy, self.aspect, self.znear, self.zfar)
+        let projection = match self.kind {
+            ProjectionKind::Perspective { aspect, fovy } => {
+                perspective(fovy, aspect, self.znear, self.zfar)
+            }
+            ProjectionKind::Orthographic {
+                left,
+                right,
+                bottom,
+                top,
+                scale,
+                ..
+            } => ortho(
+                left * scale.x,
+                right * scale.x,
+                bottom * scale.y,
+                top * scale.y,
+                self.znear,
+                self.zfar,
+            ),
+        };
+        OPENGL_TO_WGPU_MATRIX * projection
     }
 }
 
+// The six planes bounding what a view projection matrix shows, as
+// dot(plane.xyz, point) + plane.w with the normals pointing inwards and
+// normalized, so that gives the distance into the frustum. In the order left,
+// right, bottom, top, near and far.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Frustum {
+    planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    // view_proj has to include OPENGL_TO_WGPU_MATRIX, as
+    // Projection::calc_matrix does, so the near plane is at a depth of 0
+    // rather than OpenGL's -1 and the planes line up with what is drawn
+    pub fn from_view_proj(view_proj: Matrix4<f32>) -> Self {
+        let row = |i| view_proj.row(i);
+        let planes = [
+            row(3) + row(0),
+            row(3) - row(0),
+            row(3) + row(1),
+            row(3) - row(1),
+            row(2),
+            row(3) - row(2),
+        ]
+        .map(|plane| {
+            let length = plane.truncate().magnitude();
+            if length > 0.0 {
+                plane / length
+            } else {
+                plane
+            }
+        });
+        Self { planes }
+    }
+
+    pub fn planes(&self) -> [Vector4<f32>; 6] {
+        self.planes
+    }
+
+    // False only when the sphere is entirely outside one of the planes, so
+    // some spheres near the corners pass without being in view
+    pub fn intersects_sphere(&self, center: Point3<f32>, radius: f32) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.truncate().dot(center.to_vec()) + plane.w >= -radius)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum ControllerMode {
+    // Mouse looks around, the keys move along the view and scroll moves
+    // forwards and backwards
+    #[default]
+    Fly,
+    // The camera circles target looking at it. Mouse turns it around the
+    // target, scroll changes the distance and the keys move the target,
+    // which the camera follows.
+    Orbit {
+        target: Point3<f32>,
+    },
+}
+
 #[derive(Debug)]
 pub struct CameraController {
     amount_left: f32,
@@ -128,6 +451,17 @@ pub struct CameraController {
     scroll: f32,
     speed: f32,
     sensitivity: f32,
+    mode: ControllerMode,
+    // Seconds the camera takes to cover most of the way to where the input
+    // moved it, capped at MAX_SMOOTHING. 0 follows the input directly.
+    pub smoothing: f32,
+    // Where the input has moved the camera to, and where update_camera left
+    // the camera last. Anything else moving the camera in between resets
+    // the target, so reset_camera and friends don't get pulled back.
+    target: Option<(Camera, Camera)>,
+    // Whether the last update_camera changed the camera
+    moved: bool,
+    recorder: Option<recording::InputRecorder>,
 }
 
 impl CameraController {
@@ -144,10 +478,66 @@ impl CameraController {
             scroll: 0.0,
             speed,
             sensitivity,
+            mode: ControllerMode::default(),
+            smoothing: 0.0,
+            target: None,
+            moved: false,
+            recorder: None,
+        }
+    }
+
+    pub fn mode(&self) -> ControllerMode {
+        self.mode
+    }
+
+    // Switching to Orbit turns the camera towards the target on the next
+    // update, at the distance it is from it
+    pub fn set_mode(&mut self, mode: ControllerMode) {
+        self.mode = mode;
+    }
+
+    // Drops any held keys and pending mouse/scroll movement
+    pub fn reset(&mut self) {
+        self.amount_left = 0.0;
+        self.amount_right = 0.0;
+        self.amount_forward = 0.0;
+        self.amount_backward = 0.0;
+        self.amount_up = 0.0;
+        self.amount_down = 0.0;
+        self.rotate_horizontal = 0.0;
+        self.rotate_vertical = 0.0;
+        self.scroll = 0.0;
+        self.target = None;
+    }
+
+    // Records every input from here on, along with each update's dt, until
+    // stop_recording. Restarting drops what was recorded so far.
+    pub fn start_recording(&mut self) {
+        self.recorder = Some(recording::InputRecorder::new());
+    }
+
+    pub fn stop_recording(&mut self) -> Option<recording::InputRecording> {
+        self.recorder.take().map(recording::InputRecorder::finish)
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    fn record(&mut self, event: recording::InputEvent) {
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record(event);
         }
     }
 
     pub fn process_keyboard(&mut self, key: VirtualKeyCode, state: ElementState) -> bool {
+        if let Some(key) = recording::CameraKey::from_key(key) {
+            self.record(recording::InputEvent::Key {
+                key,
+                pressed: state == ElementState::Pressed,
+            });
+        }
+
         let amount = if state == ElementState::Pressed {
             1.0
         } else {
@@ -183,11 +573,21 @@ impl CameraController {
     }
 
     pub fn process_mouse(&mut self, mouse_dx: f64, mouse_dy: f64) {
+        self.record(recording::InputEvent::MouseMotion {
+            dx: mouse_dx,
+            dy: mouse_dy,
+        });
         self.rotate_horizontal = mouse_dx as f32;
         self.rotate_vertical = mouse_dy as f32;
     }
 
     pub fn process_scroll(&mut self, delta: &MouseScrollDelta) {
+        self.record(match delta {
+            MouseScrollDelta::LineDelta(_, lines) => recording::InputEvent::ScrollLines(*lines),
+            MouseScrollDelta::PixelDelta(position) => {
+                recording::InputEvent::ScrollPixels(position.y)
+            }
+        });
         self.scroll = match delta {
             // I'm assuming a line is about 100 pixels
             MouseScrollDelta::LineDelta(_, scroll) => -scroll * 4.0,
@@ -195,8 +595,71 @@ impl CameraController {
         };
     }
 
+    // Whether the last update_camera moved the camera. With is_active, this
+    // tells on demand rendering whether another frame is needed.
+    pub fn moved(&self) -> bool {
+        self.moved
+    }
+
+    // Whether a key is held, so the camera will move again next update
+    pub fn is_active(&self) -> bool {
+        [
+            self.amount_left,
+            self.amount_right,
+            self.amount_forward,
+            self.amount_backward,
+            self.amount_up,
+            self.amount_down,
+        ]
+        .iter()
+        .any(|&amount| amount != 0.0)
+    }
+
     pub fn update_camera(&mut self, camera: &mut Camera, dt: Duration) {
-        let dt = dt.as_secs_f32();
+        if let Some(recorder) = &mut self.recorder {
+            recorder.end_frame(dt);
+        }
+        let before = *camera;
+        self.smooth_camera(camera, dt.as_secs_f32());
+        self.moved = *camera != before;
+    }
+
+    fn smooth_camera(&mut self, camera: &mut Camera, dt: f32) {
+        let smoothing = self.smoothing.clamp(0.0, MAX_SMOOTHING);
+        if smoothing == 0.0 {
+            self.target = None;
+            self.move_camera(camera, dt);
+            return;
+        }
+
+        let mut target = match self.target {
+            Some((target, last)) if last == *camera => target,
+            _ => *camera,
+        };
+        self.move_camera(&mut target, dt);
+
+        // Exponential, so the result doesn't depend on the frame rate
+        let t = 1.0 - (-dt / smoothing).exp();
+        camera.position += (target.position - camera.position) * t;
+        camera.yaw += (target.yaw - camera.yaw) * t;
+        camera.pitch += (target.pitch - camera.pitch) * t;
+        // The gap shrinks without ever reaching zero, so it's closed once
+        // too small to see, which lets moved go back to false
+        if (target.position - camera.position).magnitude() < SMOOTHING_EPSILON
+            && (target.yaw - camera.yaw).0.abs() < SMOOTHING_EPSILON
+            && (target.pitch - camera.pitch).0.abs() < SMOOTHING_EPSILON
+        {
+            *camera = target;
+        }
+        self.target = Some((target, *camera));
+    }
+
+    fn move_camera(&mut self, camera: &mut Camera, dt: f32) {
+        if let ControllerMode::Orbit { target } = self.mode {
+            let target = self.orbit_camera(camera, target, dt);
+            self.mode = ControllerMode::Orbit { target };
+            return;
+        }
 
         // Move forward/backward and left/right
         let (yaw_sin, yaw_cos) = camera.yaw.0.sin_cos();
@@ -219,6 +682,46 @@ impl CameraController {
         // modify the y coordinate directly.
         camera.position.y += (self.amount_up - self.amount_down) * self.speed * dt;
 
+        self.rotate_camera(camera, dt);
+    }
+
+    // Returns where the keys moved the target to
+    fn orbit_camera(&mut self, camera: &mut Camera, target: Point3<f32>, dt: f32) -> Point3<f32> {
+        let to_target = target - camera.position;
+        // Scrolling forward moves in, the same way it does in fly mode
+        let radius = (to_target.magnitude() - self.scroll * self.speed * self.sensitivity * dt)
+            .max(MIN_ORBIT_RADIUS);
+        self.scroll = 0.0;
+
+        // Face the target first, in case something else turned the camera.
+        // Yaw only moves by the wrapped difference, so smoothing doesn't
+        // spin the long way around.
+        if to_target.magnitude() > SMOOTHING_EPSILON {
+            let direction = to_target.normalize();
+            let yaw = direction.z.atan2(direction.x);
+            let turn = (yaw - camera.yaw.0 + PI).rem_euclid(2.0 * PI) - PI;
+            camera.yaw += Rad(turn);
+            camera.pitch = Rad(direction.y.asin());
+        }
+
+        // The target moves like the fly camera would, along the ground and
+        // straight up and down
+        let (yaw_sin, yaw_cos) = camera.yaw.0.sin_cos();
+        let forward = Vector3::new(yaw_cos, 0.0, yaw_sin);
+        let right = Vector3::new(-yaw_sin, 0.0, yaw_cos);
+        let target = target
+            + (forward * (self.amount_forward - self.amount_backward)
+                + right * (self.amount_right - self.amount_left)
+                + Vector3::unit_y() * (self.amount_up - self.amount_down))
+                * self.speed
+                * dt;
+
+        self.rotate_camera(camera, dt);
+        camera.position = target - camera.forward() * radius;
+        target
+    }
+
+    fn rotate_camera(&mut self, camera: &mut Camera, dt: f32) {
         // Rotate
         camera.yaw += Rad(self.rotate_horizontal) * self.sensitivity * dt;
         camera.pitch += Rad(-self.rotate_vertical) * self.sensitivity * dt;
@@ -258,3 +761,35 @@ impl CameraUniform {
         self.view_proj = (projection.calc_matrix() * camera.calc_matrix()).into();
     }
 }
+
+impl Default for CameraUniform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn screen_center_ray_points_forward() {
+        let projection = Projection::new(800, 600, Deg(45.0), 0.1, 100.0);
+        let cameras = [
+            Camera::new((0.0, 5.0, 10.0), Deg(-90.0), Deg(-20.0)),
+            Camera::new((3.0, -1.0, 2.0), Deg(30.0), Deg(60.0)),
+            Camera::new((0.0, 0.0, 0.0), Deg(180.0), Deg(0.0)),
+        ];
+        for camera in cameras {
+            let (origin, direction) = camera.screen_ray(&projection, 400.0, 300.0, (800.0, 600.0));
+            assert!(
+                direction.dot(camera.forward()) > 1.0 - 1e-4,
+                "{:?} isn't along {:?}",
+                direction,
+                camera.forward()
+            );
+            // The ray starts on the near plane in front of the camera
+            assert!((origin - camera.position).dot(camera.forward()) > 0.0);
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use crate::memory;
+
+// Matches the workgroup_size in sine_instances.wgsl
+const WORKGROUP_SIZE: u32 = 64;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ParamsUniform {
+    time: f32,
+    spacing: f32,
+    instances_per_row: u32,
+    instance_count: u32,
+}
+
+// Writes InstanceRaw entries straight into the instance buffer from a
+// compute pass, laying the instances out on an animated sine surface. The
+// instance buffer has to be created with STORAGE usage, see
+// instance::InstanceBufferBuilder::storage.
+pub struct InstanceCompute {
+    compute_pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    params_buffer: memory::Tracked<wgpu::Buffer>,
+    params: ParamsUniform,
+}
+
+impl InstanceCompute {
+    // WebGL and some older adapters can't run compute shaders at all, and
+    // the WebGL2 limits allow no storage buffers even where they could
+    pub fn is_supported(adapter: &wgpu::Adapter, device: &wgpu::Device) -> bool {
+        adapter
+            .get_downlevel_properties()
+            .flags
+            .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS)
+            && device.limits().max_storage_buffers_per_shader_stage > 0
+    }
+
+    pub fn new(
+        device: &wgpu::Device,
+        instance_buffer: &wgpu::Buffer,
+        instances_per_row: u32,
+        spacing: f32,
+    ) -> Self {
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Instance Compute Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/sine_instances.wgsl").into()),
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("instance_compute_bind_group_layout"),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Instance Compute Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Instance Compute Pipeline"),
+            layout: Some(&layout),
+            module: &shader,
+            entry_point: "cs_main",
+        });
+
+        let params = ParamsUniform {
+            time: 0.0,
+            spacing,
+            instances_per_row,
+            instance_count: instances_per_row * instances_per_row,
+        };
+        let params_buffer = memory::create_buffer_init(
+            device,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Instance Compute Params Buffer"),
+                contents: bytemuck::cast_slice(&[params]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            },
+        );
+
+        let bind_group =
+            Self::create_bind_group(device, &bind_group_layout, &params_buffer, instance_buffer);
+
+        Self {
+            compute_pipeline,
+            bind_group_layout,
+            bind_group,
+            params_buffer,
+            params,
+        }
+    }
+
+    // For when the instance buffer is reallocated, see State::spawn_instance
+    pub fn set_instance_buffer(&mut self, device: &wgpu::Device, instance_buffer: &wgpu::Buffer) {
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.bind_group_layout,
+            &self.params_buffer,
+            instance_buffer,
+        );
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        params_buffer: &wgpu::Buffer,
+        instance_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: instance_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("instance_compute_bind_group"),
+        })
+    }
+
+    pub fn instance_count(&self) -> u32 {
+        self.params.instance_count
+    }
+
+    // Starts the simulation over from time 0 at the next update
+    pub fn reset_time(&mut self) {
+        self.params.time = 0.0;
+    }
+
+    // Advances the simulation
+    pub fn update(&mut self, queue: &wgpu::Queue, dt: std::time::Duration) {
+        self.params.time += dt.as_secs_f32();
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::cast_slice(&[self.params]));
+    }
+
+    // Must be recorded before any pass that reads the instance buffer
+    pub fn dispatch(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Instance Compute Pass"),
+        });
+        compute_pass.set_pipeline(&self.compute_pipeline);
+        compute_pass.set_bind_group(0, &self.bind_group, &[]);
+        let workgroups = self.params.instance_count.div_ceil(WORKGROUP_SIZE);
+        compute_pass.dispatch(workgroups, 1, 1);
+    }
+}
@@ -1,17 +1,73 @@
-use winit::window::Window;
+// Surfaces are limited by wgpu 0.12, which can't list what a surface
+// supports or configure its color space, alpha mode or frame latency:
+// - The preferred format is picked from 8 bit formats, so output is SDR
+//   and tone mapped, see hdr::HdrPipeline. There's no HDR format to opt into.
+// - Formats can only be checked against the adapter, see surface_format.
+//   One it renders to might still not be presentable.
+// - Present modes can't be checked up front, see set_present_mode. wgpu
+//   itself swaps modes the surface doesn't list for Fifo, with a warning,
+//   and present_mode can't tell.
+// - desired_maximum_frame_latency is held to by wait_for_frame_latency,
+//   whatever the surface keeps queued.
+// - Surfaces are presented opaque.
+// These go once wgpu is upgraded.
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context as TaskContext, Waker};
+use winit::window::{Window, WindowId};
+
+use crate::error::EngineError;
+
+type WorkDone = Pin<Box<dyn Future<Output = ()> + Send>>;
 
 pub struct Context {
     pub size: winit::dpi::PhysicalSize<u32>,
     pub instance: wgpu::Instance,
-    pub surface: wgpu::Surface,
+    // None for a headless context, see new_headless
+    pub surface: Option<wgpu::Surface>,
     pub adapter: wgpu::Adapter,
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
     pub config: wgpu::SurfaceConfiguration,
+    // Frames the CPU may queue up before waiting on the GPU. None leaves it
+    // to the driver and present mode, usually 2 or 3 frames. A lower limit
+    // shows input sooner, at the cost of the GPU sitting idle while the CPU
+    // prepares the next frame, which can lower the frame rate.
+    pub desired_maximum_frame_latency: Option<u32>,
+    frames_in_flight: VecDeque<WorkDone>,
+}
+
+// The surface of a window besides the main one, presenting with the main
+// window's device, see State::add_window
+pub struct WindowContext {
+    pub window_id: WindowId,
+    pub size: winit::dpi::PhysicalSize<u32>,
+    pub surface: wgpu::Surface,
+    pub config: wgpu::SurfaceConfiguration,
+}
+
+impl WindowContext {
+    // Ignores minimized windows, which have no size to present at
+    pub fn resize(&mut self, device: &wgpu::Device, size: winit::dpi::PhysicalSize<u32>) {
+        if size.width == 0 || size.height == 0 {
+            return;
+        }
+        let size = clamp_size(size, device);
+        self.size = size;
+        self.config.width = size.width;
+        self.config.height = size.height;
+        self.configure_surface(device);
+    }
+
+    pub fn configure_surface(&self, device: &wgpu::Device) {
+        self.surface.configure(device, &self.config);
+    }
 }
 
 impl Context {
-    pub async fn new(window: &Window) -> Self {
+    pub async fn new(window: &Window) -> anyhow::Result<Self> {
         let size = window.inner_size();
 
         // The instance is a handle to our GPU
@@ -28,44 +84,298 @@ impl Context {
             .await
             .unwrap();
         log::warn!("device and queue");
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    label: None,
-                    features: wgpu::Features::empty(),
-                    // WebGL doesn't support all of wgpu's features, so if
-                    // we're building for the web we'll have to disable some.
-                    limits: if cfg!(target_arch = "wasm32") {
-                        wgpu::Limits::downlevel_webgl2_defaults()
-                    } else {
-                        wgpu::Limits::default()
-                    },
-                },
-                // Some(&std::path::Path::new("trace")), // Trace path
-                None, // Trace path
-            )
-            .await
-            .unwrap();
+        let (device, queue) = request_device(&adapter).await?;
 
         log::warn!("Surface");
+        // Surfaces can't be larger than the biggest texture
+        let size = clamp_size(size, &device);
+        let format = surface_format(&surface, &adapter)?;
+        log::info!("Presenting {:?} in {}", format, color_space(format));
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: surface.get_preferred_format(&adapter).unwrap(),
+            format,
             width: size.width,
             height: size.height,
             present_mode: wgpu::PresentMode::Fifo,
         };
 
-        surface.configure(&device, &config);
+        let context = Self {
+            size,
+            instance,
+            surface: Some(surface),
+            adapter,
+            device,
+            queue,
+            config,
+            desired_maximum_frame_latency: None,
+            frames_in_flight: VecDeque::new(),
+        };
+        context.configure_surface();
+
+        Ok(context)
+    }
+
+    // A context without a window or surface, for rendering offscreen with
+    // State::render_to_image, in tests for example. config describes the
+    // frames as if there were a surface, in an 8 bit sRGB format. Fails
+    // when there's no adapter at all.
+    pub async fn new_headless(width: u32, height: u32) -> anyhow::Result<Self> {
+        let instance = wgpu::Instance::new(wgpu::Backends::all());
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::default(),
+                compatible_surface: None,
+                force_fallback_adapter: false,
+            })
+            .await
+            .ok_or_else(|| anyhow::anyhow!("No adapter to render headless with"))?;
+        let (device, queue) = request_device(&adapter).await?;
 
-        Self {
+        let size = clamp_size(winit::dpi::PhysicalSize::new(width, height), &device);
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            width: size.width.max(1),
+            height: size.height.max(1),
+            present_mode: wgpu::PresentMode::Fifo,
+        };
+
+        Ok(Self {
             size,
             instance,
-            surface,
+            surface: None,
             adapter,
             device,
             queue,
-            config
+            config,
+            desired_maximum_frame_latency: None,
+            frames_in_flight: VecDeque::new(),
+        })
+    }
+
+    pub fn is_headless(&self) -> bool {
+        self.surface.is_none()
+    }
+
+    // Creates a surface for another window on the same device. The adapter
+    // was picked for the main window's surface, so this fails for windows it
+    // can't present to, and for surfaces that prefer another format than the
+    // main one, which the tone mapping pipeline is built for. The window has
+    // to outlive the surface.
+    pub fn create_window_context(&self, window: &Window) -> anyhow::Result<WindowContext> {
+        let surface = unsafe { self.instance.create_surface(window) };
+        if !self.adapter.is_surface_supported(&surface) {
+            anyhow::bail!("The adapter can't present to window {:?}", window.id());
+        }
+        let format = surface_format(&surface, &self.adapter)?;
+        if format != self.config.format {
+            anyhow::bail!(
+                "Window {:?} wants {:?}, but the main window presents {:?}",
+                window.id(),
+                format,
+                self.config.format
+            );
+        }
+
+        let size = self.clamp_size(window.inner_size());
+        let window_context = WindowContext {
+            window_id: window.id(),
+            size,
+            surface,
+            config: wgpu::SurfaceConfiguration {
+                width: size.width,
+                height: size.height,
+                ..self.config.clone()
+            },
+        };
+        window_context.configure_surface(&self.device);
+        Ok(window_context)
+    }
+
+    // The largest surface the device can present, see clamp_size
+    pub fn clamp_size(&self, size: winit::dpi::PhysicalSize<u32>) -> winit::dpi::PhysicalSize<u32> {
+        clamp_size(size, &self.device)
+    }
+
+    pub fn present_mode(&self) -> wgpu::PresentMode {
+        self.config.present_mode
+    }
+
+    // Reconfigures the surface to present with mode from the next frame on,
+    // for example Immediate to measure uncapped frame rates. Falls back to
+    // Fifo, which every surface supports, where configuring with mode fails.
+    // The web only has Fifo.
+    pub fn set_present_mode(&mut self, mode: wgpu::PresentMode) {
+        let mode = if cfg!(target_arch = "wasm32") && mode != wgpu::PresentMode::Fifo {
+            log::warn!("{:?} isn't supported on the web, presenting with Fifo", mode);
+            wgpu::PresentMode::Fifo
+        } else {
+            mode
+        };
+        self.config.present_mode = mode;
+        // wgpu 0.12 panics on configuration errors instead of returning them
+        let configured = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            self.configure_surface()
+        }));
+        if let Err(panic) = configured {
+            if mode == wgpu::PresentMode::Fifo {
+                std::panic::resume_unwind(panic);
+            }
+            log::warn!("Can't present with {:?}, falling back to Fifo", mode);
+            self.config.present_mode = wgpu::PresentMode::Fifo;
+            self.configure_surface();
         }
     }
+
+    // Does nothing headless
+    pub fn configure_surface(&self) {
+        if let Some(surface) = &self.surface {
+            surface.configure(&self.device, &self.config);
+        }
+    }
+
+    // Blocks until fewer than desired_maximum_frame_latency frames are still
+    // being worked on by the GPU. Call before acquiring the next frame.
+    pub fn wait_for_frame_latency(&mut self) {
+        // Submitted work only completes from the browser's event loop
+        if cfg!(target_arch = "wasm32") {
+            return;
+        }
+        let max = match self.desired_maximum_frame_latency {
+            Some(max) => max.max(1) as usize,
+            None => return,
+        };
+
+        self.device.poll(wgpu::Maintain::Poll);
+        let mut task_context = TaskContext::from_waker(Waker::noop());
+        self.frames_in_flight
+            .retain_mut(|done| done.as_mut().poll(&mut task_context).is_pending());
+        if self.frames_in_flight.len() >= max {
+            // Maintain::Wait can't wait for one submission in wgpu 0.12, so
+            // this waits for all of them
+            self.device.poll(wgpu::Maintain::Wait);
+            self.frames_in_flight.clear();
+        }
+    }
+
+    // Counts the work submitted so far as a frame for
+    // wait_for_frame_latency. Call after the last submit of a frame.
+    pub fn frame_submitted(&mut self) {
+        if self.desired_maximum_frame_latency.is_some() {
+            self.frames_in_flight
+                .push_back(Box::pin(self.queue.on_submitted_work_done()));
+        }
+    }
+}
+
+async fn request_device(adapter: &wgpu::Adapter) -> anyhow::Result<(wgpu::Device, wgpu::Queue)> {
+    let limits = required_limits(adapter);
+    let device = adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                // Timestamp and statistics queries are optional, see
+                // profiler::GpuTimer, and so are line polygons, see
+                // wireframe::Wireframe and State::set_use_wireframe
+                features: adapter.features()
+                    & (wgpu::Features::TIMESTAMP_QUERY
+                        | wgpu::Features::PIPELINE_STATISTICS_QUERY
+                        | wgpu::Features::POLYGON_MODE_LINE),
+                limits,
+            },
+            // Some(&std::path::Path::new("trace")), // Trace path
+            None, // Trace path
+        )
+        .await?;
+    Ok(device)
+}
+
+// WebGL and older GPUs can't meet the default limits, and requesting a device
+// with limits the adapter doesn't have fails. Those get the downlevel sets
+// instead, which among other things have no compute shaders or storage
+// buffers on WebGL2 (see compute::InstanceCompute::is_supported) and keep
+// textures to 2048 pixels unless the adapter allows more.
+fn required_limits(adapter: &wgpu::Adapter) -> wgpu::Limits {
+    let (limits, name) =
+        if cfg!(target_arch = "wasm32") || adapter.get_info().backend == wgpu::Backend::Gl {
+            (
+                wgpu::Limits::downlevel_webgl2_defaults(),
+                "WebGL2 downlevel",
+            )
+        } else if !adapter.get_downlevel_properties().is_webgpu_compliant() {
+            (wgpu::Limits::downlevel_defaults(), "downlevel")
+        } else {
+            (wgpu::Limits::default(), "default")
+        };
+    log::info!("Requesting the {} limits", name);
+
+    // The window can still get as large as the adapter allows
+    limits.using_resolution(adapter.limits())
+}
+
+// The surface's preferred format, or the sRGB formats the shaders expect to
+// write to without one, whichever of them the adapter reports it can render
+// to first
+fn surface_format(
+    surface: &wgpu::Surface,
+    adapter: &wgpu::Adapter,
+) -> Result<wgpu::TextureFormat, EngineError> {
+    let requested = surface.get_preferred_format(adapter);
+    let mut supported = Vec::new();
+    for format in requested.into_iter().chain([
+        wgpu::TextureFormat::Bgra8UnormSrgb,
+        wgpu::TextureFormat::Rgba8UnormSrgb,
+    ]) {
+        let renderable = adapter
+            .get_texture_format_features(format)
+            .allowed_usages
+            .contains(wgpu::TextureUsages::RENDER_ATTACHMENT);
+        if renderable && !supported.contains(&format) {
+            supported.push(format);
+        }
+    }
+
+    match (requested, supported.first()) {
+        (Some(requested), Some(&format)) if requested == format => {
+            log::info!("Using the surface's preferred format {:?}", format);
+            Ok(format)
+        }
+        (Some(requested), Some(&format)) => {
+            log::warn!(
+                "The adapter can't render to the preferred format {:?}, falling back to {:?}",
+                requested,
+                format
+            );
+            Ok(format)
+        }
+        (None, Some(&format)) => {
+            log::info!(
+                "The surface has no preferred format, falling back to {:?}",
+                format
+            );
+            Ok(format)
+        }
+        (_, None) => Err(EngineError::UnsupportedSurfaceFormat {
+            requested,
+            supported,
+        }),
+    }
+}
+
+// What the compositor takes format's values to mean, which is what each
+// backend uses for the format, see the note at the top
+fn color_space(format: wgpu::TextureFormat) -> &'static str {
+    match format {
+        wgpu::TextureFormat::Rgba16Float => "extended linear sRGB",
+        wgpu::TextureFormat::Rgb10a2Unorm => "HDR10",
+        _ => "sRGB",
+    }
+}
+
+fn clamp_size(
+    size: winit::dpi::PhysicalSize<u32>,
+    device: &wgpu::Device,
+) -> winit::dpi::PhysicalSize<u32> {
+    let max = device.limits().max_texture_dimension_2d;
+    winit::dpi::PhysicalSize::new(size.width.min(max), size.height.min(max))
 }
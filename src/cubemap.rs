@@ -0,0 +1,178 @@
+use anyhow::bail;
+
+use crate::{memory, texture};
+
+// Matches the workgroup_size in equirect_to_cubemap.wgsl
+const WORKGROUP_SIZE: u32 = 8;
+
+// Turns equirectangular environment maps, like the ones
+// texture::Texture::load_hdr reads, into cubemaps with a compute pass. Needs
+// compute shaders, see compute::InstanceCompute::is_supported.
+pub struct EquirectToCubemap {
+    compute_pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+}
+
+impl EquirectToCubemap {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Equirect To Cubemap Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                include_str!("shaders/equirect_to_cubemap.wgsl").into(),
+            ),
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: texture::Texture::HDR_FORMAT,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("equirect_to_cubemap_bind_group_layout"),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Equirect To Cubemap Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Equirect To Cubemap Pipeline"),
+            layout: Some(&layout),
+            module: &shader,
+            entry_point: "main",
+        });
+
+        Self {
+            compute_pipeline,
+            bind_group_layout,
+        }
+    }
+
+    // A cubemap with face_size square faces. The returned texture's view is
+    // a cube view, ready to sample with a direction.
+    pub fn convert(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        equirect: &texture::Texture,
+        face_size: u32,
+    ) -> anyhow::Result<texture::Texture> {
+        let size = equirect.size;
+        if size.width != size.height * 2 {
+            bail!(
+                "Equirectangular images are twice as wide as they are tall, got {}x{}",
+                size.width,
+                size.height
+            );
+        }
+        if face_size == 0 || face_size > device.limits().max_texture_dimension_2d {
+            bail!("Cubemap faces can't be {} pixels", face_size);
+        }
+
+        let size = wgpu::Extent3d {
+            width: face_size,
+            height: face_size,
+            depth_or_array_layers: 6,
+        };
+        let texture = memory::create_texture(
+            device,
+            &wgpu::TextureDescriptor {
+                label: Some("cubemap_texture"),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: texture::Texture::HDR_FORMAT,
+                usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+            },
+        );
+        let storage_view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&equirect.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&equirect.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&storage_view),
+                },
+            ],
+            label: Some("equirect_to_cubemap_bind_group"),
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Equirect To Cubemap Encoder"),
+        });
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Equirect To Cubemap Pass"),
+            });
+            compute_pass.set_pipeline(&self.compute_pipeline);
+            compute_pass.set_bind_group(0, &bind_group, &[]);
+            let workgroups = face_size.div_ceil(WORKGROUP_SIZE);
+            compute_pass.dispatch(workgroups, workgroups, 6);
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
+        let address_modes = texture::AddressModes::clamp();
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: address_modes.u,
+            address_mode_v: address_modes.v,
+            address_mode_w: address_modes.w,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Ok(texture::Texture {
+            texture,
+            size,
+            format: texture::Texture::HDR_FORMAT,
+            mip_level_count: 1,
+            view,
+            sampler,
+            alpha_mode: texture::AlphaMode::Straight,
+            address_modes,
+        })
+    }
+}
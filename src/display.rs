@@ -0,0 +1,66 @@
+use std::time::Duration;
+
+use winit::{
+    monitor::{MonitorHandle, VideoMode},
+    window::{Fullscreen, Window},
+};
+
+// Every monitor the window could go fullscreen on, the primary one first.
+// Empty on platforms that can't list them, like the web.
+pub fn monitors(window: &Window) -> Vec<MonitorHandle> {
+    let primary = window.primary_monitor();
+    let mut monitors: Vec<_> = window.available_monitors().collect();
+    monitors.sort_by_key(|monitor| Some(monitor) != primary.as_ref());
+    monitors
+}
+
+// The modes monitor supports for exclusive fullscreen, largest and fastest
+// first
+pub fn video_modes(monitor: &MonitorHandle) -> Vec<VideoMode> {
+    let mut modes: Vec<_> = monitor.video_modes().collect();
+    modes.sort_by_key(|mode| {
+        let size = mode.size();
+        std::cmp::Reverse((
+            size.width * size.height,
+            mode.refresh_rate(),
+            mode.bit_depth(),
+        ))
+    });
+    modes
+}
+
+// How long the window's monitor shows each frame for. winit only gives
+// refresh rates per video mode, so this goes by the fastest mode at the
+// monitor's current size, or 60Hz when that isn't known.
+pub fn refresh_interval(window: &Window) -> Duration {
+    let refresh_rate = window
+        .current_monitor()
+        .and_then(|monitor| {
+            let size = monitor.size();
+            monitor
+                .video_modes()
+                .filter(|mode| mode.size() == size)
+                .map(|mode| mode.refresh_rate())
+                .max()
+        })
+        .filter(|&rate| rate > 0)
+        .unwrap_or(60);
+    Duration::from_secs_f64(1.0 / refresh_rate as f64)
+}
+
+// What the window should switch to, see State::set_display_mode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DisplayMode {
+    Windowed,
+    // Takes over the mode's monitor and switches it to the mode's resolution
+    Exclusive(VideoMode),
+}
+
+impl DisplayMode {
+    pub fn fullscreen(&self) -> Option<Fullscreen> {
+        match self {
+            DisplayMode::Windowed => None,
+            DisplayMode::Exclusive(mode) => Some(Fullscreen::Exclusive(mode.clone())),
+        }
+    }
+}
@@ -0,0 +1,323 @@
+use crate::{memory, renderer, texture};
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct DofUniform {
+    direction: [f32; 2],
+    focus_distance: f32,
+    aperture: f32,
+    znear: f32,
+    zfar: f32,
+    log_depth_coef: f32,
+    max_radius: f32,
+}
+
+// Camera and depth settings the blur is worked out from each frame
+#[derive(Debug, Copy, Clone)]
+pub struct DofSettings {
+    pub focus_distance: f32,
+    pub aperture: f32,
+    pub znear: f32,
+    pub zfar: f32,
+    pub log_depth_coef: f32,
+}
+
+// Blurs the scene texture by how far each pixel is from the focus distance,
+// read back from the depth texture. The blur is split into a horizontal
+// pass into a texture of its own and a vertical pass back into the scene
+// texture, so tone mapping picks up the result without knowing about it.
+pub struct DepthOfField {
+    // Blur radius in pixels of anything fully out of focus
+    pub max_radius: f32,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+    texture: memory::Tracked<wgpu::Texture>,
+    view: wgpu::TextureView,
+    sampler: wgpu::Sampler,
+    layout: wgpu::BindGroupLayout,
+    horizontal_buffer: memory::Tracked<wgpu::Buffer>,
+    vertical_buffer: memory::Tracked<wgpu::Buffer>,
+    // Scene texture to the intermediate one, then back
+    horizontal_bind_group: wgpu::BindGroup,
+    vertical_bind_group: wgpu::BindGroup,
+    render_pipeline: wgpu::RenderPipeline,
+}
+
+impl DepthOfField {
+    // scene_view and depth_texture are the targets the scene was drawn into,
+    // at size. sample_count has to match the depth texture.
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        scene_view: &wgpu::TextureView,
+        depth_texture: &texture::Texture,
+        size: (u32, u32),
+    ) -> Self {
+        let (texture, view) = Self::create_texture(device, format, size);
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: sample_count > 1,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        // Read as a plain float texture, see dof.wgsl
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("dof_bind_group_layout"),
+        });
+
+        let create_buffer = || {
+            memory::create_buffer(device, &wgpu::BufferDescriptor {
+                label: Some("Depth Of Field Buffer"),
+                size: std::mem::size_of::<DofUniform>() as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+        };
+        let horizontal_buffer = create_buffer();
+        let vertical_buffer = create_buffer();
+
+        // The shader reads the depth with textureLoad, which takes a sample
+        // index in place of a mip level on multisampled textures
+        let mut source = include_str!("shaders/dof.wgsl").to_string();
+        if sample_count > 1 {
+            source = source.replace(
+                "t_depth: texture_2d<f32>;",
+                "t_depth: texture_multisampled_2d<f32>;",
+            );
+        }
+        let shader = wgpu::ShaderModuleDescriptor {
+            label: Some("Depth Of Field Shader"),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        };
+        let pipeline_layout = renderer::RenderPipeline::create_pipeline_layout(device, &[&layout]);
+        let render_pipeline = renderer::RenderPipeline::new(
+            device,
+            &pipeline_layout,
+            format,
+            None,
+            &[],
+            shader,
+            renderer::PipelineOptions::default(),
+        )
+        .render_pipeline;
+
+        let depth_view = Self::depth_view(depth_texture);
+        let horizontal_bind_group = Self::create_bind_group(
+            device,
+            &layout,
+            scene_view,
+            &sampler,
+            &depth_view,
+            &horizontal_buffer,
+        );
+        let vertical_bind_group =
+            Self::create_bind_group(device, &layout, &view, &sampler, &depth_view, &vertical_buffer);
+
+        Self {
+            max_radius: 12.0,
+            format,
+            sample_count,
+            texture,
+            view,
+            sampler,
+            layout,
+            horizontal_buffer,
+            vertical_buffer,
+            horizontal_bind_group,
+            vertical_bind_group,
+            render_pipeline,
+        }
+    }
+
+    // Also takes care of the depth texture's sample count changing, which
+    // needs a new pipeline
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        sample_count: u32,
+        scene_view: &wgpu::TextureView,
+        depth_texture: &texture::Texture,
+        size: (u32, u32),
+    ) {
+        if sample_count != self.sample_count {
+            let max_radius = self.max_radius;
+            *self = Self::new(device, self.format, sample_count, scene_view, depth_texture, size);
+            self.max_radius = max_radius;
+            return;
+        }
+
+        let (texture, view) = Self::create_texture(device, self.format, size);
+        let depth_view = Self::depth_view(depth_texture);
+        self.horizontal_bind_group = Self::create_bind_group(
+            device,
+            &self.layout,
+            scene_view,
+            &self.sampler,
+            &depth_view,
+            &self.horizontal_buffer,
+        );
+        self.vertical_bind_group = Self::create_bind_group(
+            device,
+            &self.layout,
+            &view,
+            &self.sampler,
+            &depth_view,
+            &self.vertical_buffer,
+        );
+        self.texture = texture;
+        self.view = view;
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue, settings: DofSettings) {
+        let uniform = |direction| DofUniform {
+            direction,
+            focus_distance: settings.focus_distance,
+            aperture: settings.aperture,
+            znear: settings.znear,
+            zfar: settings.zfar,
+            log_depth_coef: settings.log_depth_coef,
+            max_radius: self.max_radius,
+        };
+        queue.write_buffer(
+            &self.horizontal_buffer,
+            0,
+            bytemuck::cast_slice(&[uniform([1.0, 0.0])]),
+        );
+        queue.write_buffer(
+            &self.vertical_buffer,
+            0,
+            bytemuck::cast_slice(&[uniform([0.0, 1.0])]),
+        );
+    }
+
+    // Blurs scene_view in place. It has to be the view passed to new or
+    // resize.
+    pub fn process(&self, encoder: &mut wgpu::CommandEncoder, scene_view: &wgpu::TextureView) {
+        for (label, output, bind_group) in [
+            ("Depth Of Field Horizontal Pass", &self.view, &self.horizontal_bind_group),
+            ("Depth Of Field Vertical Pass", scene_view, &self.vertical_bind_group),
+        ] {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some(label),
+                color_attachments: &[wgpu::RenderPassColorAttachment {
+                    view: output,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: None,
+            });
+            render_pass.set_pipeline(&self.render_pipeline);
+            render_pass.set_bind_group(0, bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+    }
+
+    // Only the depth aspect can be sampled
+    fn depth_view(depth_texture: &texture::Texture) -> wgpu::TextureView {
+        depth_texture.texture.create_view(&wgpu::TextureViewDescriptor {
+            aspect: wgpu::TextureAspect::DepthOnly,
+            ..Default::default()
+        })
+    }
+
+    fn create_texture(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        (width, height): (u32, u32),
+    ) -> (memory::Tracked<wgpu::Texture>, wgpu::TextureView) {
+        let texture = memory::create_texture(device, &wgpu::TextureDescriptor {
+            label: Some("dof_texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        (texture, view)
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        color_view: &wgpu::TextureView,
+        sampler: &wgpu::Sampler,
+        depth_view: &wgpu::TextureView,
+        uniform_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(color_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(depth_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("dof_bind_group"),
+        })
+    }
+}
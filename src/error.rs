@@ -0,0 +1,28 @@
+use std::fmt;
+
+// Errors callers may want to tell apart, the rest are plain anyhow errors
+#[derive(Debug, Clone, PartialEq)]
+pub enum EngineError {
+    // requested is None when the surface has no preferred format
+    UnsupportedSurfaceFormat {
+        requested: Option<wgpu::TextureFormat>,
+        supported: Vec<wgpu::TextureFormat>,
+    },
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineError::UnsupportedSurfaceFormat {
+                requested,
+                supported,
+            } => write!(
+                f,
+                "Surface format {:?} is not supported, supported formats are {:?}",
+                requested, supported
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EngineError {}
@@ -0,0 +1,181 @@
+use crate::{camera, memory, model, model::Vertex, renderer};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ColorVertex {
+    pub position: [f32; 3],
+    pub color: [f32; 3],
+}
+
+impl model::Vertex for ColorVertex {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<ColorVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+            ],
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+const AXES: [([f32; 3], [f32; 3]); 3] = [
+    ([1.0, 0.0, 0.0], [1.0, 0.0, 0.0]),
+    ([0.0, 1.0, 0.0], [0.0, 1.0, 0.0]),
+    ([0.0, 0.0, 1.0], [0.0, 0.0, 1.0]),
+];
+// Leaves a little room around the axes inside the viewport
+const AXIS_LENGTH: f32 = 0.8;
+
+// An XYZ axis indicator drawn into a small square viewport in a corner of
+// the screen. The axes only follow the camera's rotation, so they show which
+// way the world axes point from the current view.
+pub struct Gizmo {
+    pub corner: Corner,
+    // Width and height of the viewport in logical pixels, so the gizmo
+    // keeps its apparent size across displays
+    pub size: u32,
+    // Physical pixels per logical pixel of the window being drawn to
+    pub scale_factor: f64,
+    pub visible: bool,
+    render_pipeline: wgpu::RenderPipeline,
+    vertex_buffer: memory::Tracked<wgpu::Buffer>,
+}
+
+impl Gizmo {
+    pub fn new(device: &wgpu::Device, color_format: wgpu::TextureFormat) -> Self {
+        // shader_b passes positions straight through as clip space, so the
+        // axes are projected on the CPU in update
+        let shader = wgpu::ShaderModuleDescriptor {
+            label: Some("Gizmo Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/shader_b.wgsl").into()),
+        };
+        let layout = renderer::RenderPipeline::create_pipeline_layout(device, &[]);
+        let render_pipeline = renderer::RenderPipeline::new(
+            device,
+            &layout,
+            color_format,
+            None,
+            &[ColorVertex::desc()],
+            shader,
+            renderer::PipelineOptions {
+                topology: wgpu::PrimitiveTopology::LineList,
+                ..Default::default()
+            },
+        )
+        .render_pipeline;
+
+        // Filled in by update every frame
+        let vertex_buffer = memory::create_buffer(
+            device,
+            &wgpu::BufferDescriptor {
+                label: Some("Gizmo Vertex Buffer"),
+                size: (std::mem::size_of::<ColorVertex>() * AXES.len() * 2) as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            },
+        );
+
+        Self {
+            corner: Corner::BottomLeft,
+            size: 100,
+            scale_factor: 1.0,
+            visible: true,
+            render_pipeline,
+            vertex_buffer,
+        }
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue, camera: &camera::Camera) {
+        // Drop the translation so only the camera's orientation remains
+        let view = camera.calc_matrix();
+        let rotation =
+            cgmath::Matrix3::from_cols(view.x.truncate(), view.y.truncate(), view.z.truncate());
+
+        let vertices = AXES
+            .iter()
+            .flat_map(|&(axis, color)| {
+                let end = rotation * cgmath::Vector3::from(axis) * AXIS_LENGTH;
+                [
+                    ColorVertex {
+                        position: [0.0, 0.0, 0.5],
+                        color,
+                    },
+                    ColorVertex {
+                        position: [end.x, end.y, 0.5],
+                        color,
+                    },
+                ]
+            })
+            .collect::<Vec<_>>();
+
+        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&vertices));
+    }
+
+    // Viewport as (x, y, width, height) in physical pixels
+    pub fn viewport(&self, width: u32, height: u32) -> (f32, f32, f32, f32) {
+        let size = ((self.size as f64 * self.scale_factor).round() as u32)
+            .min(width)
+            .min(height);
+        let x = match self.corner {
+            Corner::TopLeft | Corner::BottomLeft => 0,
+            Corner::TopRight | Corner::BottomRight => width - size,
+        };
+        let y = match self.corner {
+            Corner::TopLeft | Corner::TopRight => 0,
+            Corner::BottomLeft | Corner::BottomRight => height - size,
+        };
+
+        (x as f32, y as f32, size as f32, size as f32)
+    }
+
+    // Draws the gizmo over whatever is already in the target view
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) {
+        if !self.visible {
+            return;
+        }
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Gizmo Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+
+        let (x, y, w, h) = self.viewport(width, height);
+        render_pass.set_viewport(x, y, w, h, 0.0, 1.0);
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.draw(0..(AXES.len() * 2) as u32, 0..1);
+    }
+}
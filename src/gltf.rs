@@ -0,0 +1,892 @@
+use std::collections::HashMap;
+
+use anyhow::Context;
+use base64::Engine as _;
+use cgmath::{InnerSpace, Matrix4, Quaternion, SquareMatrix, Vector3};
+use serde::Deserialize;
+
+use crate::{memory, model, resources, skinning, texture};
+
+// Just the parts of a glTF 2.0 document skinned models need. Field names
+// follow the spec, see https://registry.khronos.org/glTF/specs/2.0/glTF-2.0.html
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct Document {
+    accessors: Vec<Accessor>,
+    animations: Vec<Animation>,
+    buffers: Vec<Buffer>,
+    buffer_views: Vec<BufferView>,
+    images: Vec<Image>,
+    materials: Vec<Material>,
+    meshes: Vec<Mesh>,
+    nodes: Vec<Node>,
+    skins: Vec<Skin>,
+    textures: Vec<Texture>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Accessor {
+    buffer_view: Option<usize>,
+    #[serde(default)]
+    byte_offset: usize,
+    component_type: u32,
+    #[serde(default)]
+    normalized: bool,
+    count: usize,
+    #[serde(rename = "type")]
+    kind: String,
+    sparse: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Animation {
+    #[serde(default)]
+    name: String,
+    channels: Vec<AnimationChannel>,
+    samplers: Vec<AnimationSampler>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AnimationChannel {
+    sampler: usize,
+    target: AnimationTarget,
+}
+
+#[derive(Debug, Deserialize)]
+struct AnimationTarget {
+    node: Option<usize>,
+    path: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct AnimationSampler {
+    input: usize,
+    output: usize,
+    #[serde(default = "default_interpolation")]
+    interpolation: String,
+}
+
+fn default_interpolation() -> String {
+    "LINEAR".to_string()
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Buffer {
+    uri: Option<String>,
+    byte_length: usize,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BufferView {
+    buffer: usize,
+    #[serde(default)]
+    byte_offset: usize,
+    byte_length: usize,
+    byte_stride: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Image {
+    uri: Option<String>,
+    buffer_view: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct Material {
+    name: String,
+    pbr_metallic_roughness: Option<PbrMetallicRoughness>,
+    normal_texture: Option<TextureInfo>,
+    double_sided: bool,
+    alpha_mode: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct PbrMetallicRoughness {
+    base_color_factor: [f32; 4],
+    base_color_texture: Option<TextureInfo>,
+}
+
+impl Default for PbrMetallicRoughness {
+    fn default() -> Self {
+        Self {
+            base_color_factor: [1.0; 4],
+            base_color_texture: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TextureInfo {
+    index: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct Texture {
+    source: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Mesh {
+    #[serde(default)]
+    name: String,
+    primitives: Vec<Primitive>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Primitive {
+    attributes: HashMap<String, usize>,
+    indices: Option<usize>,
+    material: Option<usize>,
+    // Triangles when left out
+    mode: Option<u32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Node {
+    name: String,
+    children: Vec<usize>,
+    mesh: Option<usize>,
+    skin: Option<usize>,
+    matrix: Option<[f32; 16]>,
+    translation: Option<[f32; 3]>,
+    rotation: Option<[f32; 4]>,
+    scale: Option<[f32; 3]>,
+}
+
+impl Node {
+    // matrix and TRS are exclusive in the spec, a matrix is decomposed
+    // assuming it has no shear
+    fn transform(&self) -> skinning::JointTransform {
+        if let Some(m) = self.matrix {
+            let m = Matrix4::from(m_to_cols(m));
+            let scale = Vector3::new(
+                m.x.truncate().magnitude(),
+                m.y.truncate().magnitude(),
+                m.z.truncate().magnitude(),
+            );
+            let rotation = cgmath::Matrix3::from_cols(
+                m.x.truncate() / scale.x,
+                m.y.truncate() / scale.y,
+                m.z.truncate() / scale.z,
+            );
+            return skinning::JointTransform {
+                translation: m.w.truncate(),
+                rotation: Quaternion::from(rotation),
+                scale,
+            };
+        }
+        let [x, y, z, w] = self.rotation.unwrap_or([0.0, 0.0, 0.0, 1.0]);
+        skinning::JointTransform {
+            translation: self.translation.unwrap_or([0.0; 3]).into(),
+            rotation: Quaternion::new(w, x, y, z),
+            scale: self.scale.unwrap_or([1.0; 3]).into(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Skin {
+    joints: Vec<usize>,
+    inverse_bind_matrices: Option<usize>,
+}
+
+// glTF matrices are column major, 16 floats in a row
+fn m_to_cols(m: [f32; 16]) -> [[f32; 4]; 4] {
+    [
+        [m[0], m[1], m[2], m[3]],
+        [m[4], m[5], m[6], m[7]],
+        [m[8], m[9], m[10], m[11]],
+        [m[12], m[13], m[14], m[15]],
+    ]
+}
+
+// The JSON and the buffers it refers to
+struct Gltf {
+    document: Document,
+    buffers: Vec<Vec<u8>>,
+}
+
+impl Gltf {
+    async fn load(file_name: &str) -> anyhow::Result<Self> {
+        let data = resources::load_binary(file_name).await?;
+        let (json, mut binary_chunk) = if data.starts_with(b"glTF") {
+            parse_glb(&data)?
+        } else {
+            (&data[..], None)
+        };
+        let document: Document = serde_json::from_slice(json)
+            .with_context(|| format!("Failed to parse glTF {}", file_name))?;
+
+        let mut buffers = Vec::new();
+        for buffer in &document.buffers {
+            let data = match &buffer.uri {
+                Some(uri) if uri.starts_with("data:") => decode_data_uri(uri)
+                    .with_context(|| format!("Failed to decode a buffer of {}", file_name))?,
+                Some(uri) => resources::load_binary(&resources::resolve_relative(file_name, uri))
+                    .await
+                    .with_context(|| format!("Failed to load buffer {} of {}", uri, file_name))?,
+                // Only the first buffer can be without a URI, it's the GLB's
+                // binary chunk
+                None => binary_chunk
+                    .take()
+                    .ok_or_else(|| anyhow::anyhow!("{} has a buffer without data", file_name))?
+                    .to_vec(),
+            };
+            if data.len() < buffer.byte_length {
+                anyhow::bail!("A buffer of {} is shorter than its byte length", file_name);
+            }
+            buffers.push(data);
+        }
+
+        Ok(Self { document, buffers })
+    }
+
+    fn buffer_view(&self, index: usize) -> anyhow::Result<(&[u8], Option<usize>)> {
+        let view = self
+            .document
+            .buffer_views
+            .get(index)
+            .ok_or_else(|| anyhow::anyhow!("No buffer view {}", index))?;
+        let bytes = self
+            .buffers
+            .get(view.buffer)
+            .and_then(|buffer| buffer.get(view.byte_offset..view.byte_offset + view.byte_length))
+            .ok_or_else(|| anyhow::anyhow!("Buffer view {} is out of bounds", index))?;
+        Ok((bytes, view.byte_stride))
+    }
+
+    // Every component of the accessor as f32, normalized integers mapped to
+    // [0, 1] or [-1, 1], along with the number of components per element
+    fn read(&self, index: usize) -> anyhow::Result<(Vec<f32>, usize)> {
+        let accessor = self
+            .document
+            .accessors
+            .get(index)
+            .ok_or_else(|| anyhow::anyhow!("No accessor {}", index))?;
+        if accessor.sparse.is_some() {
+            anyhow::bail!("Sparse accessors aren't supported");
+        }
+        let components = match accessor.kind.as_str() {
+            "SCALAR" => 1,
+            "VEC2" => 2,
+            "VEC3" => 3,
+            "VEC4" => 4,
+            "MAT4" => 16,
+            kind => anyhow::bail!("Accessors of type {} aren't supported", kind),
+        };
+        let (size, read): (usize, fn(&[u8]) -> f32) = match accessor.component_type {
+            5120 => (1, |b| b[0] as i8 as f32),
+            5121 => (1, |b| b[0] as f32),
+            5122 => (2, |b| i16::from_le_bytes([b[0], b[1]]) as f32),
+            5123 => (2, |b| u16::from_le_bytes([b[0], b[1]]) as f32),
+            5125 => (4, |b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32),
+            5126 => (4, |b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+            other => anyhow::bail!("Unknown accessor component type {}", other),
+        };
+        let scale = match (accessor.normalized, accessor.component_type) {
+            (false, _) => None,
+            (true, 5120) => Some(127.0),
+            (true, 5121) => Some(255.0),
+            (true, 5122) => Some(32767.0),
+            (true, 5123) => Some(65535.0),
+            (true, _) => None,
+        };
+
+        let mut values = Vec::with_capacity(accessor.count * components);
+        let view = match accessor.buffer_view {
+            Some(view) => view,
+            // No view means all zeros
+            None => {
+                values.resize(accessor.count * components, 0.0);
+                return Ok((values, components));
+            }
+        };
+        let (bytes, stride) = self.buffer_view(view)?;
+        let stride = stride.unwrap_or(size * components);
+        for element in 0..accessor.count {
+            let start = accessor.byte_offset + element * stride;
+            for component in 0..components {
+                let offset = start + component * size;
+                let bytes = bytes
+                    .get(offset..offset + size)
+                    .ok_or_else(|| anyhow::anyhow!("Accessor {} is out of bounds", index))?;
+                let value = read(bytes);
+                values.push(match scale {
+                    // Signed values can go one past -1
+                    Some(scale) => (value / scale).max(-1.0),
+                    None => value,
+                });
+            }
+        }
+        Ok((values, components))
+    }
+
+    fn read_vec<const N: usize>(&self, index: usize) -> anyhow::Result<Vec<[f32; N]>> {
+        let (values, components) = self.read(index)?;
+        if components != N {
+            anyhow::bail!(
+                "Accessor {} has {} components, not {}",
+                index,
+                components,
+                N
+            );
+        }
+        Ok(values
+            .chunks_exact(N)
+            .map(|chunk| {
+                let mut array = [0.0; N];
+                array.copy_from_slice(chunk);
+                array
+            })
+            .collect())
+    }
+
+    fn image_bytes(&self, file_name: &str, image: usize) -> anyhow::Result<ImageSource> {
+        let image = self
+            .document
+            .images
+            .get(image)
+            .ok_or_else(|| anyhow::anyhow!("No image {}", image))?;
+        match (&image.uri, image.buffer_view) {
+            (Some(uri), _) if uri.starts_with("data:") => Ok(ImageSource::Bytes(
+                decode_data_uri(uri)
+                    .with_context(|| format!("Failed to decode an image of {}", file_name))?,
+            )),
+            (Some(uri), _) => Ok(ImageSource::File(resources::resolve_relative(
+                file_name, uri,
+            ))),
+            (None, Some(view)) => Ok(ImageSource::Bytes(self.buffer_view(view)?.0.to_vec())),
+            (None, None) => anyhow::bail!("An image of {} has no data", file_name),
+        }
+    }
+}
+
+enum ImageSource {
+    File(String),
+    Bytes(Vec<u8>),
+}
+
+// The bytes of a data URI, which is how a .gltf embeds its buffers and images
+// without a .bin or image files next to it. Exporters always base64 encode
+// them, so that's the only encoding handled.
+fn decode_data_uri(uri: &str) -> anyhow::Result<Vec<u8>> {
+    let (header, data) = uri
+        .strip_prefix("data:")
+        .and_then(|rest| rest.split_once(','))
+        .ok_or_else(|| anyhow::anyhow!("Malformed data URI"))?;
+    if !header.ends_with(";base64") {
+        anyhow::bail!("Only base64 data URIs are supported, not {:?}", header);
+    }
+    base64::engine::general_purpose::STANDARD
+        .decode(data)
+        .context("Invalid base64 in a data URI")
+}
+
+// The JSON chunk and, if there is one, the binary chunk of a .glb
+fn parse_glb(data: &[u8]) -> anyhow::Result<(&[u8], Option<&[u8]>)> {
+    let word = |offset: usize| -> anyhow::Result<usize> {
+        let bytes = data
+            .get(offset..offset + 4)
+            .ok_or_else(|| anyhow::anyhow!("Truncated GLB"))?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+    };
+    if word(4)? != 2 {
+        anyhow::bail!("Only glTF 2.0 GLB files are supported");
+    }
+
+    let mut json = None;
+    let mut binary = None;
+    let mut offset = 12;
+    while offset + 8 <= data.len() {
+        let length = word(offset)?;
+        let kind = word(offset + 4)?;
+        let chunk = data
+            .get(offset + 8..offset + 8 + length)
+            .ok_or_else(|| anyhow::anyhow!("Truncated GLB chunk"))?;
+        match kind {
+            0x4E4F534A => json = Some(chunk),
+            0x004E4942 => binary = Some(chunk),
+            // Unknown chunks are to be skipped
+            _ => {}
+        }
+        offset += 8 + length;
+    }
+    let json = json.ok_or_else(|| anyhow::anyhow!("GLB without a JSON chunk"))?;
+    Ok((json, binary))
+}
+
+// Loads the first skin of a .gltf or .glb file, every mesh bound to it and
+// the animations that move its joints. Other meshes are left out, as are
+// morph targets. Textures are taken from the base color and normal maps,
+// without a map the base color factor or a flat normal is used.
+pub async fn load_skinned_model(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture_layout: &wgpu::BindGroupLayout,
+    skinning: &skinning::Skinning,
+) -> anyhow::Result<skinning::SkinnedModel> {
+    let gltf = Gltf::load(file_name).await?;
+    let document = &gltf.document;
+    let skin = document
+        .skins
+        .first()
+        .ok_or_else(|| anyhow::anyhow!("{} has no skin", file_name))?;
+
+    let mut parents = vec![None; document.nodes.len()];
+    for (i, node) in document.nodes.iter().enumerate() {
+        for &child in &node.children {
+            parents[child] = Some(i);
+        }
+    }
+    let joint_of_node = skin
+        .joints
+        .iter()
+        .enumerate()
+        .map(|(joint, &node)| (node, joint))
+        .collect::<HashMap<_, _>>();
+    let inverse_binds = match skin.inverse_bind_matrices {
+        Some(accessor) => gltf
+            .read_vec::<16>(accessor)?
+            .into_iter()
+            .map(|m| Matrix4::from(m_to_cols(m)))
+            .collect(),
+        None => vec![Matrix4::identity(); skin.joints.len()],
+    };
+    if inverse_binds.len() < skin.joints.len() {
+        anyhow::bail!("{} has fewer inverse bind matrices than joints", file_name);
+    }
+    let joints = skin
+        .joints
+        .iter()
+        .zip(inverse_binds)
+        .map(|(&node, inverse_bind)| {
+            let parent = parents[node].and_then(|parent| joint_of_node.get(&parent).copied());
+            // The nodes above a root joint don't move, so they are folded
+            // into one matrix
+            let mut base = Matrix4::identity();
+            if parent.is_none() {
+                let mut ancestor = parents[node];
+                while let Some(i) = ancestor {
+                    base = document.nodes[i].transform().matrix() * base;
+                    ancestor = parents[i];
+                }
+            }
+            skinning::Joint {
+                name: document.nodes[node].name.clone(),
+                parent,
+                bind_pose: document.nodes[node].transform(),
+                inverse_bind,
+                base,
+            }
+        })
+        .collect();
+    let skeleton = skinning::Skeleton::new(joints);
+
+    let clips = document
+        .animations
+        .iter()
+        .enumerate()
+        .map(|(i, animation)| load_clip(&gltf, i, animation, &joint_of_node))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut materials = Vec::new();
+    for material in &document.materials {
+        materials
+            .push(load_material(&gltf, file_name, material, device, queue, texture_layout).await?);
+    }
+    if materials.is_empty() {
+        materials.push(
+            load_material(
+                &gltf,
+                file_name,
+                &Material::default(),
+                device,
+                queue,
+                texture_layout,
+            )
+            .await?,
+        );
+    }
+
+    let joint_buffer = skinning::SkinnedModel::create_joint_buffer(device, &skeleton);
+    let mut meshes = Vec::new();
+    let mut skin_buffers = Vec::new();
+    for node in document.nodes.iter().filter(|node| node.skin == Some(0)) {
+        let mesh = match node.mesh.and_then(|mesh| document.meshes.get(mesh)) {
+            Some(mesh) => mesh,
+            None => continue,
+        };
+        for primitive in &mesh.primitives {
+            if primitive.mode.unwrap_or(4) != 4 {
+                log::warn!(
+                    "Skipping a primitive of {:?} that isn't triangles",
+                    mesh.name
+                );
+                continue;
+            }
+            let (vertices, skin_vertices, indices) = load_primitive(&gltf, primitive)
+                .with_context(|| format!("Failed to load mesh {:?} of {}", mesh.name, file_name))?;
+
+            let name = format!("{}:{}", file_name, mesh.name);
+            let vertex_buffer = memory::create_buffer_init(
+                device,
+                &wgpu::util::BufferInitDescriptor {
+                    label: Some(&format!("{:?} Vertex Buffer", name)),
+                    contents: bytemuck::cast_slice(&vertices),
+                    usage: wgpu::BufferUsages::VERTEX,
+                },
+            );
+            skin_buffers.push(memory::create_buffer_init(
+                device,
+                &wgpu::util::BufferInitDescriptor {
+                    label: Some(&format!("{:?} Skin Buffer", name)),
+                    contents: bytemuck::cast_slice(&skin_vertices),
+                    usage: wgpu::BufferUsages::VERTEX,
+                },
+            ));
+            let index_buffer = memory::create_buffer_init(
+                device,
+                &wgpu::util::BufferInitDescriptor {
+                    label: Some(&format!("{:?} Index Buffer", name)),
+                    contents: bytemuck::cast_slice(&indices),
+                    usage: wgpu::BufferUsages::INDEX,
+                },
+            );
+            // The spec has skinned meshes ignore their node's transform
+            let transform = Matrix4::identity();
+            let transform_buffer = model::Mesh::create_transform_buffer_init(device, transform);
+            let transform_bind_group =
+                skinning.create_bind_group(device, &transform_buffer, &joint_buffer);
+            let (edge_buffer, num_edges) =
+                model::Mesh::create_edge_buffer_init(device, &vertices, &indices);
+
+            meshes.push(model::Mesh {
+                name,
+                vertex_buffer,
+                num_vertices: vertices.len() as u32,
+                index_buffer,
+                num_elements: indices.len() as u32,
+                lods: Vec::new(),
+                bounds: model::Bounds::from_vertices(&vertices),
+                material: primitive
+                    .material
+                    .filter(|&material| material < materials.len())
+                    .unwrap_or(0),
+                transform,
+                transform_buffer,
+                transform_bind_group,
+                edge_buffer,
+                num_edges,
+            });
+        }
+    }
+    if meshes.is_empty() {
+        anyhow::bail!("{} has no meshes bound to its skin", file_name);
+    }
+
+    let model = model::Model {
+        meshes,
+        materials,
+        lod_distances: Vec::new(),
+    };
+    Ok(skinning::SkinnedModel::new(
+        model,
+        skin_buffers,
+        skeleton,
+        clips,
+        joint_buffer,
+    ))
+}
+
+fn load_primitive(
+    gltf: &Gltf,
+    primitive: &Primitive,
+) -> anyhow::Result<(Vec<model::ModelVertex>, Vec<skinning::SkinVertex>, Vec<u32>)> {
+    let attribute = |name: &str| primitive.attributes.get(name).copied();
+    let positions = gltf.read_vec::<3>(
+        attribute("POSITION").ok_or_else(|| anyhow::anyhow!("No POSITION attribute"))?,
+    )?;
+    let normals = gltf.read_vec::<3>(
+        attribute("NORMAL").ok_or_else(|| anyhow::anyhow!("No NORMAL attribute"))?,
+    )?;
+    let tex_coords = match attribute("TEXCOORD_0") {
+        Some(accessor) => gltf.read_vec::<2>(accessor)?,
+        None => vec![[0.0; 2]; positions.len()],
+    };
+    let tangents = attribute("TANGENT")
+        .map(|accessor| gltf.read_vec::<4>(accessor))
+        .transpose()?;
+    let joints = gltf.read_vec::<4>(
+        attribute("JOINTS_0").ok_or_else(|| anyhow::anyhow!("No JOINTS_0 attribute"))?,
+    )?;
+    let weights = gltf.read_vec::<4>(
+        attribute("WEIGHTS_0").ok_or_else(|| anyhow::anyhow!("No WEIGHTS_0 attribute"))?,
+    )?;
+    let count = positions.len();
+    if [normals.len(), tex_coords.len(), joints.len(), weights.len()]
+        .iter()
+        .any(|&len| len != count)
+        || tangents.as_ref().is_some_and(|t| t.len() != count)
+    {
+        anyhow::bail!("Attributes have different vertex counts");
+    }
+
+    let mut vertices = (0..count)
+        .map(|i| model::ModelVertex {
+            position: positions[i],
+            tex_coords: tex_coords[i],
+            normal: normals[i],
+            tangent: tangents.as_ref().map_or([0.0; 4], |t| t[i]),
+        })
+        .collect::<Vec<_>>();
+    let indices = match primitive.indices {
+        Some(accessor) => {
+            let (indices, _) = gltf.read(accessor)?;
+            indices.into_iter().map(|i| i as u32).collect::<Vec<_>>()
+        }
+        None => (0..count as u32).collect(),
+    };
+    if indices.iter().any(|&i| i as usize >= count) {
+        anyhow::bail!("Indices point past the vertices");
+    }
+    if tangents.is_none() {
+        resources::compute_tangents(&mut vertices, &indices);
+    }
+
+    let skin_vertices = joints
+        .into_iter()
+        .zip(weights)
+        .map(|(joints, weights)| {
+            // Exporters don't always normalize, and a vertex with no weight
+            // would collapse to the origin
+            let sum = weights.iter().sum::<f32>();
+            let weights = if sum > 0.0 {
+                weights.map(|w| w / sum)
+            } else {
+                [1.0, 0.0, 0.0, 0.0]
+            };
+            skinning::SkinVertex {
+                joints: joints.map(|j| j as u32),
+                weights,
+            }
+        })
+        .collect();
+
+    Ok((vertices, skin_vertices, indices))
+}
+
+fn load_clip(
+    gltf: &Gltf,
+    index: usize,
+    animation: &Animation,
+    joint_of_node: &HashMap<usize, usize>,
+) -> anyhow::Result<skinning::AnimationClip> {
+    let mut channels = Vec::new();
+    let mut duration = 0.0_f32;
+    for channel in &animation.channels {
+        // Morph target weights and nodes outside the skeleton don't deform
+        // the skin
+        let joint = match channel
+            .target
+            .node
+            .and_then(|node| joint_of_node.get(&node))
+        {
+            Some(&joint) => joint,
+            None => continue,
+        };
+        let sampler = animation
+            .samplers
+            .get(channel.sampler)
+            .ok_or_else(|| anyhow::anyhow!("No animation sampler {}", channel.sampler))?;
+        let times = gltf
+            .read_vec::<1>(sampler.input)?
+            .into_iter()
+            .map(|[t]| t)
+            .collect::<Vec<_>>();
+        duration = times.last().copied().unwrap_or(0.0).max(duration);
+        let interpolation = match sampler.interpolation.as_str() {
+            "STEP" => skinning::Interpolation::Step,
+            _ => skinning::Interpolation::Linear,
+        };
+        // Cubic splines have an in and out tangent around every value
+        let (stride, offset) = if sampler.interpolation == "CUBICSPLINE" {
+            (3, 1)
+        } else {
+            (1, 0)
+        };
+        let values = match channel.target.path.as_str() {
+            "translation" | "scale" => {
+                let values = gltf
+                    .read_vec::<3>(sampler.output)?
+                    .into_iter()
+                    .skip(offset)
+                    .step_by(stride)
+                    .map(Vector3::from)
+                    .collect();
+                if channel.target.path == "translation" {
+                    skinning::ChannelValues::Translation(values)
+                } else {
+                    skinning::ChannelValues::Scale(values)
+                }
+            }
+            "rotation" => skinning::ChannelValues::Rotation(
+                gltf.read_vec::<4>(sampler.output)?
+                    .into_iter()
+                    .skip(offset)
+                    .step_by(stride)
+                    .map(|[x, y, z, w]| Quaternion::new(w, x, y, z))
+                    .collect(),
+            ),
+            _ => continue,
+        };
+        let value_count = match &values {
+            skinning::ChannelValues::Translation(values)
+            | skinning::ChannelValues::Scale(values) => values.len(),
+            skinning::ChannelValues::Rotation(values) => values.len(),
+        };
+        if value_count != times.len() {
+            anyhow::bail!(
+                "An animation channel has {} keys but {} values",
+                times.len(),
+                value_count
+            );
+        }
+        channels.push(skinning::Channel {
+            joint,
+            interpolation,
+            times,
+            values,
+        });
+    }
+
+    let name = if animation.name.is_empty() {
+        format!("animation {}", index)
+    } else {
+        animation.name.clone()
+    };
+    Ok(skinning::AnimationClip {
+        name,
+        duration,
+        channels,
+    })
+}
+
+async fn load_material(
+    gltf: &Gltf,
+    file_name: &str,
+    material: &Material,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+) -> anyhow::Result<model::Material> {
+    let pbr = material.pbr_metallic_roughness.as_ref();
+    let base_color = pbr.map_or([1.0; 4], |pbr| pbr.base_color_factor);
+    let diffuse_texture = load_texture(
+        gltf,
+        file_name,
+        pbr.and_then(|pbr| pbr.base_color_texture.as_ref()),
+        base_color.map(|c| (c.clamp(0.0, 1.0) * 255.0) as u8),
+        false,
+        device,
+        queue,
+    )
+    .await?;
+    let normal_texture = load_texture(
+        gltf,
+        file_name,
+        material.normal_texture.as_ref(),
+        [128, 128, 255, 255],
+        true,
+        device,
+        queue,
+    )
+    .await?;
+
+    let mut result = model::Material::new(
+        device,
+        &material.name,
+        diffuse_texture,
+        normal_texture,
+        layout,
+    );
+    if material.double_sided {
+        result.set_two_sided(queue, true);
+    }
+    if material.alpha_mode.as_deref() == Some("BLEND") {
+        log::warn!(
+            "Material {:?} of {} is blended, skinned models are drawn opaque",
+            material.name,
+            file_name
+        );
+    }
+    Ok(result)
+}
+
+// The texture the info points to, or a single pixel of fallback
+async fn load_texture(
+    gltf: &Gltf,
+    file_name: &str,
+    info: Option<&TextureInfo>,
+    fallback: [u8; 4],
+    is_normal_map: bool,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> anyhow::Result<texture::Texture> {
+    let image = info
+        .and_then(|info| gltf.document.textures.get(info.index))
+        .and_then(|texture| texture.source);
+    let address_modes = texture::AddressModes::repeat();
+    match image
+        .map(|image| gltf.image_bytes(file_name, image))
+        .transpose()?
+    {
+        Some(ImageSource::File(path)) => {
+            resources::load_texture(&path, is_normal_map, address_modes, device, queue).await
+        }
+        Some(ImageSource::Bytes(bytes)) => texture::Texture::from_bytes(
+            device,
+            queue,
+            &bytes,
+            file_name,
+            is_normal_map,
+            texture::AlphaMode::Straight,
+            address_modes,
+        ),
+        None => texture::Texture::from_image(
+            device,
+            queue,
+            &image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+                1,
+                1,
+                image::Rgba(fallback),
+            )),
+            Some(file_name),
+            is_normal_map,
+            texture::AlphaMode::Straight,
+            address_modes,
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn data_uris() {
+        let bytes = decode_data_uri("data:application/octet-stream;base64,AAECAw==").unwrap();
+        assert_eq!(bytes, [0, 1, 2, 3]);
+        assert!(decode_data_uri("data:text/plain,hello").is_err());
+        assert!(decode_data_uri("data:application/octet-stream;base64").is_err());
+    }
+}
@@ -0,0 +1,165 @@
+use crate::{memory, renderer, settings, texture};
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct GridUniform {
+    color: [f32; 4],
+    spacing: f32,
+    height: f32,
+    extent: f32,
+    // Due to uniforms requiring 16 byte (4 float) spacing, we need to use a padding field here
+    _padding: f32,
+}
+
+// A ground grid drawn procedurally in the fragment shader from world XZ
+// coordinates. The quad follows the camera, so the grid looks endless.
+pub struct Grid {
+    // Distance between lines in world units
+    pub spacing: f32,
+    // Line color, alpha included
+    pub color: [f32; 4],
+    // World Y of the grid plane
+    pub height: f32,
+    // Lines fade out towards this distance from the camera
+    pub extent: f32,
+    render_pipeline: wgpu::RenderPipeline,
+    layout: wgpu::BindGroupLayout,
+    uniform_buffer: memory::Tracked<wgpu::Buffer>,
+    bind_group: wgpu::BindGroup,
+}
+
+impl Grid {
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let uniform_buffer = memory::create_buffer(
+            device,
+            &wgpu::BufferDescriptor {
+                label: Some("Grid Buffer"),
+                size: std::mem::size_of::<GridUniform>() as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            },
+        );
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("grid_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+            label: Some("grid_bind_group"),
+        });
+
+        let render_pipeline = Self::create_pipeline(
+            device,
+            color_format,
+            sample_count,
+            camera_bind_group_layout,
+            &layout,
+        );
+
+        Self {
+            spacing: 1.0,
+            color: [0.6, 0.6, 0.6, 0.8],
+            height: -1.0,
+            extent: 100.0,
+            render_pipeline,
+            layout,
+            uniform_buffer,
+            bind_group,
+        }
+    }
+
+    // For when the target the grid is drawn into changes
+    pub fn recreate_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) {
+        self.render_pipeline = Self::create_pipeline(
+            device,
+            color_format,
+            sample_count,
+            camera_bind_group_layout,
+            &self.layout,
+        );
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        layout: &wgpu::BindGroupLayout,
+    ) -> wgpu::RenderPipeline {
+        let shader = wgpu::ShaderModuleDescriptor {
+            label: Some("Grid Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                settings::shader_source(include_str!("shaders/grid.wgsl")).into(),
+            ),
+        };
+        let pipeline_layout = renderer::RenderPipeline::create_pipeline_layout(
+            device,
+            &[camera_bind_group_layout, layout],
+        );
+        renderer::RenderPipeline::new(
+            device,
+            &pipeline_layout,
+            color_format,
+            Some(texture::Texture::DEPTH_FORMAT),
+            &[],
+            shader,
+            // Most of the quad is transparent, so it is blended and
+            // doesn't hide anything behind it from the depth test
+            renderer::PipelineOptions {
+                sample_count,
+                ..renderer::PipelineOptions::transparent()
+            },
+        )
+        .render_pipeline
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[GridUniform {
+                color: self.color,
+                spacing: self.spacing,
+                height: self.height,
+                extent: self.extent,
+                _padding: 0.0,
+            }]),
+        );
+    }
+
+    pub fn render<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.draw(0..6, 0..1);
+    }
+}
@@ -0,0 +1,270 @@
+use crate::{memory, renderer};
+
+// Format of the offscreen target the scene is drawn into before tone mapping
+pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum ToneMapOperator {
+    // Only applies exposure, anything above 1.0 is clipped
+    None,
+    Reinhard,
+    #[default]
+    Aces,
+}
+
+impl ToneMapOperator {
+    // Matches the switch in tonemap.wgsl
+    fn id(self) -> u32 {
+        match self {
+            ToneMapOperator::None => 0,
+            ToneMapOperator::Reinhard => 1,
+            ToneMapOperator::Aces => 2,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ToneMapUniform {
+    exposure: f32,
+    operator: u32,
+    // Due to uniforms requiring 16 byte (4 float) spacing, we need to use a padding field here
+    _padding: [u32; 2],
+}
+
+// The scene is rendered into an offscreen texture, then a fullscreen pass
+// tone maps it into the swapchain. The texture can be smaller than the
+// swapchain for dynamic resolution, the pass upscales it with a linear
+// filter. Where HDR_FORMAT isn't supported the texture uses the surface
+// format and the pass only copies it over.
+pub struct HdrPipeline {
+    pub texture: memory::Tracked<wgpu::Texture>,
+    pub view: wgpu::TextureView,
+    pub format: wgpu::TextureFormat,
+    sampler: wgpu::Sampler,
+    layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    uniform_buffer: memory::Tracked<wgpu::Buffer>,
+    render_pipeline: wgpu::RenderPipeline,
+}
+
+impl HdrPipeline {
+    // Rendering to float targets isn't guaranteed on WebGL
+    pub fn is_supported() -> bool {
+        !cfg!(target_arch = "wasm32")
+    }
+
+    // config is the surface configuration, whose format the pass writes to,
+    // and size is the size of the offscreen texture
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        format: wgpu::TextureFormat,
+        size: (u32, u32),
+    ) -> Self {
+        let (texture, view) = Self::create_texture(device, format, size);
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        // Filtered for when the texture is upscaled
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("hdr_bind_group_layout"),
+        });
+
+        let uniform_buffer = memory::create_buffer_init(
+            device,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Tone Map Buffer"),
+                contents: bytemuck::cast_slice(&[ToneMapUniform {
+                    exposure: 1.0,
+                    operator: ToneMapOperator::default().id(),
+                    _padding: [0; 2],
+                }]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            },
+        );
+
+        let bind_group = Self::create_bind_group(device, &layout, &view, &sampler, &uniform_buffer);
+
+        let shader = wgpu::ShaderModuleDescriptor {
+            label: Some("Tone Map Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/tonemap.wgsl").into()),
+        };
+        let pipeline_layout = renderer::RenderPipeline::create_pipeline_layout(device, &[&layout]);
+        let render_pipeline = renderer::RenderPipeline::new(
+            device,
+            &pipeline_layout,
+            config.format,
+            None,
+            &[],
+            shader,
+            renderer::PipelineOptions::default(),
+        )
+        .render_pipeline;
+
+        Self {
+            texture,
+            view,
+            format,
+            sampler,
+            layout,
+            bind_group,
+            uniform_buffer,
+            render_pipeline,
+        }
+    }
+
+    pub fn is_hdr(&self) -> bool {
+        self.format == HDR_FORMAT
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, size: (u32, u32)) {
+        let (texture, view) = Self::create_texture(device, self.format, size);
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.layout,
+            &view,
+            &self.sampler,
+            &self.uniform_buffer,
+        );
+        self.texture = texture;
+        self.view = view;
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue, operator: ToneMapOperator, exposure: f32) {
+        // Tone mapping a texture that is already clamped to [0, 1] would
+        // only darken it
+        let operator = if self.is_hdr() {
+            operator
+        } else {
+            ToneMapOperator::None
+        };
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[ToneMapUniform {
+                exposure,
+                operator: operator.id(),
+                _padding: [0; 2],
+            }]),
+        );
+    }
+
+    // Tone maps the offscreen texture into output, overwriting it. The
+    // viewport limits what gets drawn to part of output, see
+    // State::target_aspect.
+    pub fn process(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        output: &wgpu::TextureView,
+        viewport: Option<&renderer::Viewport>,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Tone Map Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        if let Some(viewport) = viewport {
+            viewport.apply(&mut render_pass);
+        }
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    fn create_texture(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        (width, height): (u32, u32),
+    ) -> (memory::Tracked<wgpu::Texture>, wgpu::TextureView) {
+        let texture = memory::create_texture(
+            device,
+            &wgpu::TextureDescriptor {
+                label: Some("hdr_texture"),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+            },
+        );
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        (texture, view)
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        view: &wgpu::TextureView,
+        sampler: &wgpu::Sampler,
+        uniform_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("hdr_bind_group"),
+        })
+    }
+}
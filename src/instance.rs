@@ -1,11 +1,54 @@
 use cgmath::prelude::*;
-use wgpu::util::DeviceExt;
 
-use crate::model;
+use crate::{memory, model};
 
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct CullStats {
+    pub total: u32,
+    pub drawn: u32,
+    pub culled: u32,
+}
+
+// Where to place an instance, see Instance::from_transforms
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Transform {
+    pub position: cgmath::Vector3<f32>,
+    pub rotation: cgmath::Quaternion<f32>,
+    pub scale: cgmath::Vector3<f32>,
+}
+
+impl Transform {
+    pub fn from_position(position: cgmath::Vector3<f32>) -> Self {
+        Self {
+            position,
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            position: cgmath::Vector3::zero(),
+            rotation: cgmath::Quaternion::one(),
+            scale: cgmath::Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl From<Transform> for Instance {
+    fn from(transform: Transform) -> Self {
+        Instance::with_rotation(transform.position, transform.rotation).with_scale(transform.scale)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
 pub struct Instance {
     position: cgmath::Vector3<f32>,
     rotation: cgmath::Quaternion<f32>,
+    scale: cgmath::Vector3<f32>,
+    // Linear, multiplies the diffuse texture
+    color: [f32; 4],
 }
 
 impl Instance {
@@ -18,29 +61,140 @@ impl Instance {
             cgmath::Quaternion::from_axis_angle(position.normalize(), cgmath::Deg(45.0))
         };
 
-        Instance { position, rotation }
+        Self::with_rotation(position, rotation)
+    }
+
+    pub fn with_rotation(
+        position: cgmath::Vector3<f32>,
+        rotation: cgmath::Quaternion<f32>,
+    ) -> Self {
+        Instance {
+            position,
+            rotation,
+            scale: cgmath::Vector3::new(1.0, 1.0, 1.0),
+            color: [1.0; 4],
+        }
+    }
+
+    pub fn position(&self) -> cgmath::Vector3<f32> {
+        self.position
+    }
+
+    pub fn set_position(&mut self, position: cgmath::Vector3<f32>) {
+        self.position = position;
+    }
+
+    pub fn rotation(&self) -> cgmath::Quaternion<f32> {
+        self.rotation
+    }
+
+    pub fn set_rotation(&mut self, rotation: cgmath::Quaternion<f32>) {
+        self.rotation = rotation;
+    }
+
+    pub fn color(&self) -> [f32; 4] {
+        self.color
+    }
+
+    pub fn set_color(&mut self, color: [f32; 4]) {
+        self.color = color;
+    }
+
+    pub fn with_color(self, color: [f32; 4]) -> Self {
+        Instance { color, ..self }
+    }
+
+    // Negative components mirror the instance, see is_mirrored
+    pub fn with_scale(self, scale: cgmath::Vector3<f32>) -> Self {
+        Instance { scale, ..self }
     }
 
-    pub fn instance_vec(instances_per_row: u32, space_between: f32) -> Vec<Instance> {
+    // Applies rotation in the instance's own space, before its rotation
+    pub fn rotated(self, rotation: cgmath::Quaternion<f32>) -> Self {
+        Instance {
+            rotation: self.rotation * rotation,
+            ..self
+        }
+    }
+
+    // A negative determinant flips the winding of every triangle, so these
+    // have to be drawn with the opposite front face
+    pub fn is_mirrored(&self) -> bool {
+        self.scale.x * self.scale.y * self.scale.z < 0.0
+    }
+
+    // One instance per transform, in order, for layouts instance_vec's grid
+    // can't make
+    pub fn from_transforms(transforms: &[Transform]) -> Vec<Instance> {
+        transforms.iter().copied().map(Instance::from).collect()
+    }
+
+    // A square grid centered on the origin, colored from gradient[0] at the
+    // first corner to gradient[1] at the opposite one
+    pub fn instance_vec(
+        instances_per_row: u32,
+        space_between: f32,
+        gradient: [[f32; 4]; 2],
+    ) -> Vec<Instance> {
+        let last = instances_per_row.saturating_sub(1).max(1) as f32;
         (0..instances_per_row)
             .flat_map(|z| {
                 (0..instances_per_row).map(move |x| {
+                    let t = (x + z) as f32 / (2.0 * last);
+                    let mut color = [0.0; 4];
+                    for (i, channel) in color.iter_mut().enumerate() {
+                        *channel = gradient[0][i] + (gradient[1][i] - gradient[0][i]) * t;
+                    }
+
                     let x = space_between * (x as f32 - instances_per_row as f32 / 2.0);
                     let z = space_between * (z as f32 - instances_per_row as f32 / 2.0);
 
-                    Instance::new(x, z)
+                    Instance::new(x, z).with_color(color)
                 })
             })
             .collect::<Vec<_>>()
     }
 
+    // Center and radius of a sphere enclosing a model placed by this
+    // instance, scale included, for a model_radius from
+    // model::Model::bounding_radius
+    pub fn bounding_sphere(&self, model_radius: f32) -> (cgmath::Point3<f32>, f32) {
+        let scale = self
+            .scale
+            .x
+            .abs()
+            .max(self.scale.y.abs())
+            .max(self.scale.z.abs());
+        (
+            cgmath::Point3::from_vec(self.position),
+            model_radius * scale,
+        )
+    }
+
+    pub fn distance_to(&self, point: cgmath::Point3<f32>) -> f32 {
+        self.position.distance(point.to_vec())
+    }
+
+    pub fn model_matrix(&self) -> cgmath::Matrix4<f32> {
+        cgmath::Matrix4::from_translation(self.position)
+            * cgmath::Matrix4::from(self.rotation)
+            * cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+
     pub fn to_raw(&self) -> InstanceRaw {
-        let model =
-            cgmath::Matrix4::from_translation(self.position) * cgmath::Matrix4::from(self.rotation);
+        let model = self.model_matrix();
+        // The inverse transpose of rotation * scale, which for a rotation
+        // is just the rotation times the inverse scale
+        let normal = cgmath::Matrix3::from(self.rotation)
+            * cgmath::Matrix3::from_diagonal(cgmath::Vector3::new(
+                1.0 / self.scale.x,
+                1.0 / self.scale.y,
+                1.0 / self.scale.z,
+            ));
         InstanceRaw {
             model: model.into(),
-            // NEW!
-            normal: cgmath::Matrix3::from(self.rotation).into(),
+            normal: normal.into(),
+            color: self.color,
         }
     }
 }
@@ -51,6 +205,7 @@ impl Instance {
 pub struct InstanceRaw {
     model: [[f32; 4]; 4],
     normal: [[f32; 3]; 3],
+    color: [f32; 4],
 }
 
 impl model::Vertex for InstanceRaw {
@@ -102,15 +257,79 @@ impl model::Vertex for InstanceRaw {
                     shader_location: 11,
                     format: wgpu::VertexFormat::Float32x3,
                 },
+                // 12 and 13 are taken by skinning::SkinVertex
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 25]>() as wgpu::BufferAddress,
+                    shader_location: 14,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
             ],
         }
     }
 }
 
-pub fn create_buffer_init(device: &wgpu::Device, instance_data: Vec<InstanceRaw>) -> wgpu::Buffer {
-    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-        label: Some("Instance Buffer"),
-        contents: bytemuck::cast_slice(&instance_data),
-        usage: wgpu::BufferUsages::VERTEX,
-    })
+// Creates instance buffers. By default the buffer is sized to fit the
+// initial data exactly, max_instances leaves room to grow into without
+// reallocating.
+pub struct InstanceBufferBuilder {
+    instance_data: Vec<InstanceRaw>,
+    max_instances: Option<u32>,
+    storage: bool,
+}
+
+impl InstanceBufferBuilder {
+    pub fn new(instance_data: Vec<InstanceRaw>) -> Self {
+        Self {
+            instance_data,
+            max_instances: None,
+            storage: false,
+        }
+    }
+
+    pub fn max_instances(mut self, max_instances: u32) -> Self {
+        self.max_instances = Some(max_instances);
+        self
+    }
+
+    // Lets a compute pass write the buffer, see compute::InstanceCompute
+    pub fn storage(mut self, storage: bool) -> Self {
+        self.storage = storage;
+        self
+    }
+
+    // Fails if the initial data doesn't fit in max_instances
+    pub fn build(self, device: &wgpu::Device) -> anyhow::Result<memory::Tracked<wgpu::Buffer>> {
+        let count = self.instance_data.len() as u32;
+        let capacity = self.max_instances.unwrap_or(count);
+        if count > capacity {
+            anyhow::bail!(
+                "{} instances don't fit in an instance buffer with room for {}",
+                count,
+                capacity
+            );
+        }
+
+        let mut usage = wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST;
+        if self.storage {
+            usage |= wgpu::BufferUsages::STORAGE;
+        }
+        let stride = std::mem::size_of::<InstanceRaw>();
+        let buffer = memory::create_buffer(
+            device,
+            &wgpu::BufferDescriptor {
+                label: Some("Instance Buffer"),
+                // Zero sized buffers aren't allowed
+                size: (stride * capacity.max(1) as usize) as wgpu::BufferAddress,
+                usage,
+                mapped_at_creation: true,
+            },
+        );
+        {
+            let data: &[u8] = bytemuck::cast_slice(&self.instance_data);
+            buffer.slice(..).get_mapped_range_mut()[..data.len()].copy_from_slice(data);
+        }
+        buffer.unmap();
+
+        Ok(buffer)
+    }
 }
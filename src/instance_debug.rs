@@ -0,0 +1,79 @@
+use std::ops::Range;
+
+use crate::{
+    instance,
+    model::Vertex,
+    renderer,
+    settings::{self, InstanceDrawMode},
+    texture,
+};
+
+// Stands in for obj_model's meshes with a point or a box per instance, see
+// State::instance_draw_mode. Reads the instance buffer directly, so it shows
+// exactly what was uploaded after culling, GPU driven instances included.
+pub struct InstanceDebug {
+    points: wgpu::RenderPipeline,
+    bounds: wgpu::RenderPipeline,
+}
+
+impl InstanceDebug {
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let layout =
+            renderer::RenderPipeline::create_pipeline_layout(device, &[camera_bind_group_layout]);
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Instance Debug Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                settings::shader_source(include_str!("shaders/instance_debug.wgsl")).into(),
+            ),
+        });
+        let create_pipeline = |topology, vertex_entry_point| {
+            renderer::RenderPipeline::from_module(
+                device,
+                &layout,
+                color_format,
+                Some(texture::Texture::DEPTH_FORMAT),
+                &[instance::InstanceRaw::desc()],
+                &shader,
+                renderer::PipelineOptions {
+                    topology,
+                    cull_mode: None,
+                    vertex_entry_point,
+                    sample_count,
+                    ..Default::default()
+                },
+            )
+            .render_pipeline
+        };
+
+        Self {
+            points: create_pipeline(wgpu::PrimitiveTopology::PointList, "vs_point"),
+            bounds: create_pipeline(wgpu::PrimitiveTopology::LineList, "vs_bounds"),
+        }
+    }
+
+    // Draws instances from instance_buffer, which is bound to slot 0. Points
+    // are a pixel wide, wgpu has no point size.
+    pub fn render<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        mode: InstanceDrawMode,
+        instance_buffer: &'a wgpu::Buffer,
+        instances: Range<u32>,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        let (pipeline, vertices) = match mode {
+            InstanceDrawMode::Meshes => return,
+            InstanceDrawMode::Points => (&self.points, 0..1),
+            InstanceDrawMode::Bounds => (&self.bounds, 0..24),
+        };
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, instance_buffer.slice(..));
+        render_pass.draw(vertices, instances);
+    }
+}
@@ -1,101 +1,422 @@
-use cgmath::Rotation3;
-use light::LightUniform;
+use std::collections::HashSet;
+
+use anyhow::Context as _;
+use cgmath::{EuclideanSpace, MetricSpace, Rotation3, VectorSpace};
 use winit::{
     event::*,
-    event_loop::{ControlFlow, EventLoop},
-    window::Window,
+    window::{Window, WindowId},
 };
 
-mod camera;
-mod instance;
-mod light;
-mod model;
-mod context;
-mod renderer;
-mod resources;
-mod texture;
-
-use model::Vertex;
+pub mod antialiasing;
+pub mod app;
+pub mod background;
+pub mod batch;
+pub mod camera;
+pub mod compute;
+pub mod context;
+pub mod cubemap;
+pub mod display;
+pub mod dof;
+pub mod error;
+pub mod gizmo;
+pub mod gltf;
+pub mod grid;
+pub mod hdr;
+pub mod instance;
+pub mod instance_debug;
+pub mod light;
+pub mod memory;
+pub mod model;
+pub mod picking;
+pub mod primitives;
+pub mod profiler;
+pub mod recording;
+pub mod renderer;
+pub mod resources;
+pub mod scene;
+pub mod selection;
+pub mod settings;
+pub mod skinning;
+pub mod texture;
+pub mod wireframe;
 
 const NUM_INSTANCES_PER_ROW: u32 = 10;
+// Starting capacity of the instance buffer, which doubles whenever
+// State::spawn_instance outgrows it
+const INITIAL_INSTANCE_CAPACITY: u32 = 1024;
+// Side length and height of State::ground
+const GROUND_SIZE: f32 = 80.0;
+const GROUND_HEIGHT: f32 = -2.0;
+// How often the cull stats are written to the debug log
+const CULL_STATS_LOG_INTERVAL: f32 = 5.0;
+// Degrees per second the light circles the scene at
+const LIGHT_ROTATION_SPEED: f32 = 60.0;
+// Seconds the light takes to move between its orbit and the camera
+const HEADLAMP_TRANSITION_TIME: f32 = 0.5;
+// How far the section view plane moves per key press
+const SECTION_STEP: f32 = 0.25;
+// Lowest resolution scale set_resolution_scale accepts
+const MIN_RESOLUTION_SCALE: f32 = 0.1;
+// What N cycles the clear color through, starting from the default
+const CLEAR_COLORS: [wgpu::Color; 4] = [
+    renderer::CLEAR_COLOR,
+    wgpu::Color::BLACK,
+    wgpu::Color {
+        r: 0.5,
+        g: 0.5,
+        b: 0.5,
+        a: 1.0,
+    },
+    wgpu::Color {
+        r: 0.3,
+        g: 0.1,
+        b: 0.2,
+        a: 1.0,
+    },
+];
+
+// See State::set_instance_updater
+pub type InstanceUpdater = Box<dyn FnMut(&mut [instance::Instance], std::time::Duration)>;
 
 pub struct State {
     context: context::Context,
-    render_pipeline: wgpu::RenderPipeline,
+    // Built in pipelines for the scene target, see set_anti_aliasing
+    pipelines: renderer::ScenePipelines,
+    render_pipeline_layout: wgpu::PipelineLayout,
+    light_pipeline_layout: wgpu::PipelineLayout,
     obj_model: model::Model,
+    // The floor under the instances, drawn with the other static models
+    pub ground: model::StaticModel,
+    // Drawn once each before any instances, see add_static_model
+    static_models: Vec<model::StaticModel>,
+    // A single identity instance for drawing static models
+    static_instance_buffer: memory::Tracked<wgpu::Buffer>,
+    // Drawn after obj_model with the same pipeline and camera/light groups
+    models: Vec<model::InstancedModel>,
+    // None without storage buffers in vertex shaders, see
+    // skinning::Skinning::is_supported
+    skinning: Option<skinning::Skinning>,
+    // Animated and drawn after models, see load_skinned_model
+    skinned_models: Vec<skinning::SkinnedModel>,
+    // Objects added with add_object, drawn after models
+    batcher: batch::Batcher,
+    // Outlines of the batched objects' triangles, see set_object_wireframe
+    wireframe: wireframe::Wireframe,
     camera: camera::Camera,
+    // Where the camera started, restored by reset_camera
+    initial_camera: camera::Camera,
     projection: camera::Projection,
+    // Swapped with projection by O, so it's orthographic while projection is
+    // perspective and the other way around
+    alternate_projection: camera::Projection,
     camera_controller: camera::CameraController,
     camera_uniform: camera::CameraUniform,
-    camera_buffer: wgpu::Buffer,
+    camera_buffer: memory::Tracked<wgpu::Buffer>,
     camera_bind_group: wgpu::BindGroup,
+    settings_buffer: memory::Tracked<wgpu::Buffer>,
+    globals_buffer: memory::Tracked<wgpu::Buffer>,
+    // Time run so far without pauses, see settings::GlobalsUniform
+    time: std::time::Duration,
     instances: Vec<instance::Instance>,
-    #[allow(dead_code)]
-    instance_buffer: wgpu::Buffer,
+    instance_buffer: memory::Tracked<wgpu::Buffer>,
+    // Instances instance_buffer has room for, see reserve_instances
+    instance_capacity: u32,
+    // None when the adapter can't run compute shaders
+    instance_compute: Option<compute::InstanceCompute>,
+    // When set, instance_compute writes the instance buffer instead of
+    // update_instances. Distance fading, culling and LODs are skipped.
+    pub gpu_driven: bool,
+    // Spin around the y axis every obj_model instance gets on top of its own
+    // rotation, advanced by model_rotation_speed degrees a second. Not
+    // applied when gpu_driven.
+    model_rotation: cgmath::Deg<f32>,
+    pub model_rotation_speed: f32,
+    // Maps each slot of the instance buffer back to its index in instances
+    instance_order: Vec<u32>,
+    // Camera distance of each slot of the instance buffer, used to pick LODs
+    instance_distances: Vec<f32>,
+    num_opaque_instances: u32,
+    num_fading_instances: u32,
+    cull_stats: instance::CullStats,
+    cull_stats_log_timer: f32,
+    // Instances fade out between these distances from the camera and are
+    // culled beyond fade_end
+    pub fade_start: f32,
+    pub fade_end: f32,
+    // Logarithmic depth trades some near precision for far precision
+    pub depth_mode: settings::DepthMode,
+    // See set_clip_plane
+    clip_plane: Option<[f32; 4]>,
+    // See settings::RenderFeatures::near_fade
+    pub near_fade_distance: f32,
+    // Width / height the scene is letterboxed to, with black bars filling the
+    // rest of the window. The projection takes its aspect from the viewport,
+    // so nothing gets stretched. None uses the whole window.
+    pub target_aspect: Option<f32>,
+    // Follows target_aspect and the window size, see update_viewport
+    viewport: renderer::Viewport,
+    // Sized to the scene target, not the window, see set_resolution_scale
     depth_texture: texture::Texture,
-    light_uniform: LightUniform,
-    light_buffer: wgpu::Buffer,
+    // The offscreen target the scene is drawn into, HDR when supported
+    hdr: hdr::HdrPipeline,
+    resolution_scale: f32,
+    pub tone_map_operator: hdr::ToneMapOperator,
+    pub exposure: f32,
+    dof: dof::DepthOfField,
+    // See settings::RenderFeatures::depth_of_field
+    pub focus_distance: f32,
+    pub aperture: f32,
+    // Point lights, the first of which circles the scene or follows the
+    // camera, see push_light
+    lights: light::LightArray,
+    // Where the orbit animation has the light, kept moving while it follows
+    // the camera so switching back doesn't jump
+    light_orbit_position: cgmath::Vector3<f32>,
+    // Puts the light at the camera eye instead of on its orbit
+    pub light_follows_camera: bool,
+    // 0 on the orbit, 1 at the camera, eased towards light_follows_camera
+    headlamp_blend: f32,
     light_bind_group: wgpu::BindGroup,
-    light_render_pipeline: wgpu::RenderPipeline,
+    // Index into renderer::SHADER_VARIANTS that opaque draws use, set with
+    // the number keys
+    pub shader_variant: usize,
+    // Cycled with V
+    pub debug_view: settings::DebugView,
+    // Cycled with B
+    pub instance_draw_mode: settings::InstanceDrawMode,
+    // Toggled with T, see set_use_wireframe
+    use_wireframe: bool,
+    instance_debug: instance_debug::InstanceDebug,
+    anti_aliasing: antialiasing::AntiAliasing,
+    // Only exist while the matching anti-aliasing mode is on
+    msaa_target: Option<antialiasing::MsaaTarget>,
+    fxaa: Option<antialiasing::Fxaa>,
+    // Runs on instances at the start of every update
+    instance_updater: Option<InstanceUpdater>,
+    // Indices into instances of the instances drawn with an outline, see
+    // set_selected
+    selected: Vec<u32>,
+    pub outline_mode: selection::OutlineMode,
+    pub selection_outline: selection::SelectionOutline,
     #[allow(dead_code)]
     debug_material: model::Material,
+    // Indices into instances that are drawn with debug_material
+    debug_instances: HashSet<u32>,
+    // Indices into instances left out of the instance buffer, see
+    // set_instance_visible
+    hidden_instances: HashSet<u32>,
+    // Kept around so materials and user pipelines can be created after
+    // startup, see bind_group_layouts
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+    camera_bind_group_layout: wgpu::BindGroupLayout,
+    light_bind_group_layout: wgpu::BindGroupLayout,
+    mesh_bind_group_layout: wgpu::BindGroupLayout,
+    // Replaces the built in pipelines for obj_model when set
+    model_pipeline: Option<wgpu::RenderPipeline>,
+    picker: picking::Picker,
+    // None when timestamp queries aren't supported
+    gpu_timer: Option<profiler::GpuTimer>,
+    // Reading the GPU timings back waits for the frame to finish, so this is
+    // only on by default in debug builds
+    pub gpu_profiling: bool,
+    // The order opaque instances and static models are drawn in, see
+    // frame_stats for how much overdraw it saves
+    pub opaque_sort_mode: settings::OpaqueSortMode,
+    frame_stats: profiler::FrameStats,
+    memory_growth: memory::GrowthMonitor,
+    pub gizmo: gizmo::Gizmo,
+    // Set spacing, color and so on through grid directly
+    pub grid: grid::Grid,
+    background: background::Background,
+    // What the first pass of the frame clears the scene target to, see
+    // set_clear_color
+    clear_color: wgpu::Color,
+    // Everything but msaa, which anti_aliasing keeps track of, see
+    // render_features
+    features: settings::RenderFeatures,
+    // Set by request_redraw, cleared by the next render
+    redraw_requested: bool,
+    // Starts every draw layer but the first with an empty depth buffer, see
+    // set_object_layer
+    pub clear_depth_between_layers: bool,
     use_debug: bool,
+    // Stops camera input and updates while still rendering
+    pub camera_frozen: bool,
+    // Skips update entirely, so nothing moves, while frames are still
+    // rendered and events handled
+    pub paused: bool,
+    // paused as of the last update, to notice unpausing
+    was_paused: bool,
     mouse_pressed: bool,
+    cursor_position: winit::dpi::PhysicalPosition<f64>,
+    // Listed at startup and again whenever the window changes displays
+    monitors: Vec<winit::monitor::MonitorHandle>,
+    display_mode: display::DisplayMode,
+    // Set by set_display_mode until app::App applies it to the window
+    display_mode_changed: bool,
+    // Windows besides the main one, see add_window
+    windows: Vec<context::WindowContext>,
 }
 
 impl State {
-    async fn new(window: &Window) -> Self {
-        let context = context::Context::new(window).await;
+    async fn new(
+        window: &Window,
+        camera_config: camera::CameraConfig,
+    ) -> anyhow::Result<Self> {
+        let context = context::Context::new(window).await?;
+        Self::from_context(
+            context,
+            camera_config,
+            window.scale_factor(),
+            display::monitors(window),
+        )
+        .await
+    }
+
+    // A State without a window, drawing width by height frames offscreen,
+    // for render_to_image in tests and tools. No window events reach it, so
+    // it's driven by update and the setters alone. Fails without an adapter.
+    pub async fn new_headless(
+        width: u32,
+        height: u32,
+        camera_config: camera::CameraConfig,
+    ) -> anyhow::Result<Self> {
+        let context = context::Context::new_headless(width, height).await?;
+        Self::from_context(context, camera_config, 1.0, Vec::new()).await
+    }
+
+    async fn from_context(
+        context: context::Context,
+        camera_config: camera::CameraConfig,
+        scale_factor: f64,
+        monitors: Vec<winit::monitor::MonitorHandle>,
+    ) -> anyhow::Result<Self> {
 
         let texture_bind_group_layout = texture::Texture::create_bind_group_layout(&context.device);
+        let mesh_bind_group_layout = model::Mesh::create_bind_group_layout(&context.device);
 
-        let camera = camera::Camera::new((0.0, 5.0, 10.0), cgmath::Deg(-90.0), cgmath::Deg(-20.0));
-        let projection =
-            camera::Projection::new(context.config.width, context.config.height, cgmath::Deg(45.0), 0.1, 100.0);
-        let camera_controller = camera::CameraController::new(4.0, 0.4);
+        let camera = camera_config.camera();
+        let projection = camera_config.projection(context.config.width, context.config.height);
+        let alternate_projection =
+            camera_config.orthographic_projection(context.config.width, context.config.height);
+        let camera_controller = camera_config.controller();
 
         let mut camera_uniform = camera::CameraUniform::new();
         camera_uniform.update_view_proj(&camera, &projection);
 
         let camera_buffer = camera::Camera::create_buffer_init(&context.device, camera_uniform);
 
+        let fade_start = 80.0;
+        let fade_end = 100.0;
+        let settings_buffer = settings::create_buffer_init(
+            &context.device,
+            settings::SettingsUniform::new(
+                fade_start,
+                fade_end,
+                settings::DepthMode::default().log_depth_coef(&projection),
+            ),
+        );
+
         const SPACE_BETWEEN: f32 = 3.0;
-        let instances = instance::Instance::instance_vec(NUM_INSTANCES_PER_ROW, SPACE_BETWEEN);
+        // Warm to cool across the grid
+        const GRID_GRADIENT: [[f32; 4]; 2] = [[1.0, 0.55, 0.35, 1.0], [0.35, 0.6, 1.0, 1.0]];
+        let mut instances = instance::Instance::instance_vec(
+            NUM_INSTANCES_PER_ROW,
+            SPACE_BETWEEN,
+            GRID_GRADIENT,
+        );
+        // Mirror one cube to keep negative scales honest
+        instances[0] = instances[0].with_scale(cgmath::Vector3::new(-1.0, 1.0, 1.0));
 
         let instance_data = instances
             .iter()
             .map(instance::Instance::to_raw)
             .collect::<Vec<_>>();
 
-        let instance_buffer = instance::create_buffer_init(&context.device, instance_data);
+        let compute_supported =
+            compute::InstanceCompute::is_supported(&context.adapter, &context.device);
+        let instance_buffer = instance::InstanceBufferBuilder::new(instance_data)
+            .max_instances(INITIAL_INSTANCE_CAPACITY)
+            .storage(compute_supported)
+            .build(&context.device)
+            .unwrap();
+        let instance_compute = compute_supported.then(|| {
+            compute::InstanceCompute::new(
+                &context.device,
+                &instance_buffer,
+                NUM_INSTANCES_PER_ROW,
+                SPACE_BETWEEN,
+            )
+        });
 
         let camera_bind_group_layout = camera::Camera::camera_bind_group_layout(&context.device);
 
-        let camera_bind_group =
-            camera::Camera::create_bind_group(&context.device, &camera_bind_group_layout, &camera_buffer);
+        let globals_buffer = settings::create_globals_buffer_init(
+            &context.device,
+            settings::GlobalsUniform::default(),
+        );
+        let camera_bind_group = camera::Camera::create_bind_group(
+            &context.device,
+            &camera_bind_group_layout,
+            &camera_buffer,
+            &settings_buffer,
+            &globals_buffer,
+        );
 
         log::warn!("Load model");
         let obj_model =
-            resources::load_model("cube.obj", &context.device, &context.queue, &texture_bind_group_layout)
-                .await
-                .unwrap();
-
-        let light_uniform = light::LightUniform::new();
+            resources::load_model(
+                "cube.obj",
+                &context.device,
+                &context.queue,
+                &texture_bind_group_layout,
+                &mesh_bind_group_layout,
+                resources::ImportOptions {
+                    lod_levels: 2,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
 
-        let light_buffer = light::create_buffer_init(&context.device, light_uniform);
+        // WebGL2 gets a uniform with room for light::MAX_LIGHTS instead
+        let light_storage =
+            light::LightArray::is_storage_supported(&context.adapter, &context.device);
+        let lights = light::LightArray::new(
+            &context.device,
+            &[light::LightUniform::new()],
+            light_storage,
+        );
 
-        let light_bind_group_layout = light::create_bind_group_layout(&context.device);
+        let light_bind_group_layout =
+            light::create_bind_group_layout(&context.device, light_storage);
 
-        let light_bind_group =
-            light::create_bind_group(&context.device, &light_bind_group_layout, &light_buffer);
+        let light_bind_group = light::create_bind_group(
+            &context.device,
+            &light_bind_group_layout,
+            lights.buffer(),
+        );
 
+        let viewport =
+            renderer::Viewport::letterbox(context.config.width, context.config.height, None);
+        let resolution_scale = 1.0;
+        let scene_config = scaled_config(&context.config, resolution_scale);
         let depth_texture =
-            texture::Texture::create_depth_texture(&context.device, &context.config, "depth_texture");
+            texture::Texture::create_depth_texture(&context.device, &scene_config, "depth_texture");
 
-        let shader = wgpu::ShaderModuleDescriptor {
-            label: Some("Normal Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/shader_a.wgsl").into()),
+        // With HDR the scene is drawn into a float texture and tone mapped
+        // into the swapchain afterwards
+        let scene_format = if hdr::HdrPipeline::is_supported() {
+            hdr::HDR_FORMAT
+        } else {
+            context.config.format
         };
+        let hdr = hdr::HdrPipeline::new(
+            &context.device,
+            &context.config,
+            scene_format,
+            (scene_config.width, scene_config.height),
+        );
 
         let render_pipeline_layout = renderer::RenderPipeline::create_pipeline_layout(
             &context.device,
@@ -103,38 +424,106 @@ impl State {
                 &texture_bind_group_layout,
                 &camera_bind_group_layout,
                 &light_bind_group_layout,
+                &mesh_bind_group_layout,
             ],
         );
+        let light_pipeline_layout = renderer::RenderPipeline::create_pipeline_layout(
+            &context.device,
+            &[&camera_bind_group_layout, &light_bind_group_layout],
+        );
 
-        let render_pipeline = renderer::RenderPipeline::new(
+        let anti_aliasing = antialiasing::AntiAliasing::default();
+        let pipelines = renderer::ScenePipelines::new(
             &context.device,
             &render_pipeline_layout,
-            context.config.format,
-            Some(texture::Texture::DEPTH_FORMAT),
-            &[model::ModelVertex::desc(), instance::InstanceRaw::desc()],
-            shader,
-        )
-        .render_pipeline;
+            &light_pipeline_layout,
+            scene_format,
+            anti_aliasing.sample_count(),
+            false,
+            lights.is_storage(),
+        );
 
-        let light_layout = renderer::RenderPipeline::create_pipeline_layout(
+        let dof = dof::DepthOfField::new(
             &context.device,
-            &[&camera_bind_group_layout, &light_bind_group_layout],
+            scene_format,
+            anti_aliasing.sample_count(),
+            &hdr.view,
+            &depth_texture,
+            (scene_config.width, scene_config.height),
         );
 
-        let light_shader = wgpu::ShaderModuleDescriptor {
-            label: Some("Light Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/light.wgsl").into()),
-        };
+        let gpu_timer = profiler::GpuTimer::new(&context.device, &context.queue);
 
-        let light_render_pipeline = renderer::RenderPipeline::new(
+        let picker = picking::Picker::new(&context.device, &context.config, &render_pipeline_layout);
+        let selection_outline = selection::SelectionOutline::new(
             &context.device,
-            &light_layout,
-            context.config.format,
-            Some(texture::Texture::DEPTH_FORMAT),
-            &[model::ModelVertex::desc()],
-            light_shader,
-        )
-        .render_pipeline;
+            &scene_config,
+            scene_format,
+            &render_pipeline_layout,
+        );
+
+        let mut gizmo = gizmo::Gizmo::new(&context.device, context.config.format);
+        gizmo.scale_factor = scale_factor;
+
+        let background = background::Background::new(
+            &context.device,
+            scene_format,
+            anti_aliasing.sample_count(),
+        );
+        let instance_debug = instance_debug::InstanceDebug::new(
+            &context.device,
+            scene_format,
+            anti_aliasing.sample_count(),
+            &camera_bind_group_layout,
+        );
+        let grid = grid::Grid::new(
+            &context.device,
+            scene_format,
+            anti_aliasing.sample_count(),
+            &camera_bind_group_layout,
+        );
+        let wireframe = wireframe::Wireframe::new(
+            &context.device,
+            scene_format,
+            anti_aliasing.sample_count(),
+            &camera_bind_group_layout,
+            &mesh_bind_group_layout,
+        );
+        let skinning = skinning::Skinning::is_supported(&context.adapter, &context.device)
+            .then(|| {
+                skinning::Skinning::new(
+                    &context.device,
+                    scene_format,
+                    anti_aliasing.sample_count(),
+                    &texture_bind_group_layout,
+                    &camera_bind_group_layout,
+                    &light_bind_group_layout,
+                )
+            });
+
+        // Wide enough for the instance grid and the demo scene's pyramids,
+        // and low enough for the rotated cubes to clear it
+        let ground = primitives::plane(GROUND_SIZE, 1)
+            .into_model(
+                &context.device,
+                &context.queue,
+                &texture_bind_group_layout,
+                &mesh_bind_group_layout,
+                "ground",
+            )
+            .unwrap();
+        let ground = model::StaticModel::new(
+            &context.queue,
+            ground,
+            cgmath::Matrix4::from_translation(cgmath::Vector3::new(0.0, GROUND_HEIGHT, 0.0)),
+        );
+        let identity = instance::Instance::with_rotation(
+            cgmath::Vector3::new(0.0, 0.0, 0.0),
+            cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0),
+        );
+        let static_instance_buffer = instance::InstanceBufferBuilder::new(vec![identity.to_raw()])
+            .build(&context.device)
+            .unwrap();
 
         let debug_material = {
             let diffuse_bytes = include_bytes!("../res/cobble-diffuse.png");
@@ -146,6 +535,8 @@ impl State {
                 diffuse_bytes,
                 "res/alt-diffuse.png",
                 false,
+                texture::AlphaMode::Straight,
+                texture::AddressModes::default(),
             )
             .unwrap();
             let normal_texture = texture::Texture::from_bytes(
@@ -154,6 +545,8 @@ impl State {
                 normal_bytes,
                 "res/alt-normal.png",
                 true,
+                texture::AlphaMode::Straight,
+                texture::AddressModes::default(),
             )
             .unwrap();
 
@@ -166,39 +559,458 @@ impl State {
             )
         };
 
-        Self {
-            context,
-            render_pipeline,
-            obj_model,
-            camera,
-            projection,
-            camera_controller,
-            camera_buffer,
-            camera_bind_group,
-            camera_uniform,
-            instances,
-            instance_buffer,
-            depth_texture,
-            light_bind_group,
-            light_buffer,
-            light_uniform,
-            light_render_pipeline,
-            debug_material,
-            use_debug: false,
-            mouse_pressed: false,
-        }
+        Ok(Self {
+                context,
+                pipelines,
+                render_pipeline_layout,
+                light_pipeline_layout,
+                obj_model,
+                ground,
+                static_models: Vec::new(),
+                static_instance_buffer,
+                models: Vec::new(),
+                skinning,
+                skinned_models: Vec::new(),
+                batcher: batch::Batcher::new(),
+                wireframe,
+                camera,
+                initial_camera: camera,
+                projection,
+                alternate_projection,
+                camera_controller,
+                camera_buffer,
+                camera_bind_group,
+                camera_uniform,
+                settings_buffer,
+                globals_buffer,
+                time: std::time::Duration::ZERO,
+                instance_order: (0..instances.len() as u32).collect(),
+                instance_distances: vec![0.0; instances.len()],
+                num_opaque_instances: instances.len() as u32,
+                num_fading_instances: 0,
+                cull_stats: instance::CullStats::default(),
+                cull_stats_log_timer: 0.0,
+                instances,
+                instance_buffer,
+                instance_capacity: INITIAL_INSTANCE_CAPACITY,
+                instance_compute,
+                gpu_driven: false,
+                model_rotation: cgmath::Deg(0.0),
+                model_rotation_speed: 0.0,
+                fade_start,
+                fade_end,
+                depth_mode: settings::DepthMode::default(),
+                clip_plane: None,
+                near_fade_distance: 0.5,
+                depth_texture,
+                hdr,
+                resolution_scale,
+                target_aspect: None,
+                viewport,
+                tone_map_operator: hdr::ToneMapOperator::default(),
+                exposure: 1.0,
+                dof,
+                focus_distance: 10.0,
+                aperture: 0.5,
+                light_bind_group,
+                lights,
+                light_orbit_position: light::LightUniform::new().position.into(),
+                light_follows_camera: false,
+                headlamp_blend: 0.0,
+                shader_variant: 0,
+                use_wireframe: false,
+                debug_view: settings::DebugView::default(),
+                instance_draw_mode: settings::InstanceDrawMode::default(),
+                instance_debug,
+                anti_aliasing,
+                msaa_target: None,
+                fxaa: None,
+                instance_updater: None,
+                selected: Vec::new(),
+                outline_mode: selection::OutlineMode::default(),
+                selection_outline,
+                debug_material,
+                debug_instances: HashSet::new(),
+                hidden_instances: HashSet::new(),
+                texture_bind_group_layout,
+                camera_bind_group_layout,
+                light_bind_group_layout,
+                mesh_bind_group_layout,
+                model_pipeline: None,
+                picker,
+                gpu_timer,
+                gpu_profiling: cfg!(debug_assertions),
+                opaque_sort_mode: settings::OpaqueSortMode::default(),
+                frame_stats: profiler::FrameStats::default(),
+                memory_growth: memory::GrowthMonitor::default(),
+                gizmo,
+                grid,
+                background,
+                features: settings::RenderFeatures::default(),
+                redraw_requested: false,
+                clear_depth_between_layers: false,
+                clear_color: renderer::CLEAR_COLOR,
+                use_debug: false,
+                camera_frozen: false,
+                paused: false,
+                was_paused: false,
+                mouse_pressed: false,
+                cursor_position: winit::dpi::PhysicalPosition::new(0.0, 0.0),
+                monitors,
+                display_mode: display::DisplayMode::Windowed,
+                display_mode_changed: false,
+                windows: Vec::new(),
+        })
     }
 
+    // The surface and picker take the window's physical size, the scene
+    // targets and projection the viewport's, see update_viewport
     fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
         if new_size.width > 0 && new_size.height > 0 {
-            self.projection.resize(new_size.width, new_size.height);
+            let new_size = self.context.clamp_size(new_size);
             self.context.size = new_size;
             self.context.config.width = new_size.width;
             self.context.config.height = new_size.height;
-            self.context.surface.configure(&self.context.device, &self.context.config);
-            self.depth_texture =
-                texture::Texture::create_depth_texture(&self.context.device, &self.context.config, "depth_texture");
+            self.context.configure_surface();
+            self.picker.resize(&self.context.device, &self.context.config);
+            self.update_viewport(true);
+        }
+    }
+
+    // Shows the scene in another window too, on the main window's device,
+    // see context::Context::create_window_context. Each frame the scene is
+    // drawn once for the main window, then tone mapped into every other
+    // window, letterboxed to the main viewport's aspect. The gizmo and FXAA
+    // only show in the main window. Remove the window before dropping it.
+    pub fn add_window(&mut self, window: &Window) -> anyhow::Result<()> {
+        let window_context = self.context.create_window_context(window)?;
+        self.windows.push(window_context);
+        Ok(())
+    }
+
+    // Returns whether window_id was added
+    pub fn remove_window(&mut self, window_id: WindowId) -> bool {
+        let count = self.windows.len();
+        self.windows.retain(|window| window.window_id != window_id);
+        self.windows.len() != count
+    }
+
+    pub fn resize_window(&mut self, window_id: WindowId, size: winit::dpi::PhysicalSize<u32>) {
+        if let Some(window) = self.windows.iter_mut().find(|w| w.window_id == window_id) {
+            window.resize(&self.context.device, size);
+        }
+    }
+
+    // Switches the main window and the ones from add_window, see
+    // context::Context::set_present_mode
+    pub fn set_present_mode(&mut self, mode: wgpu::PresentMode) {
+        self.context.set_present_mode(mode);
+        for window in &mut self.windows {
+            window.config.present_mode = mode;
+            window.configure_surface(&self.context.device);
+        }
+    }
+
+    // The windows added with add_window, not including the main one
+    pub fn window_ids(&self) -> impl Iterator<Item = WindowId> + '_ {
+        self.windows.iter().map(|window| window.window_id)
+    }
+
+    // Recomputes the letterboxed viewport from target_aspect and resizes
+    // whatever is sized from it when it changed, or when forced to
+    fn update_viewport(&mut self, force: bool) {
+        let viewport = renderer::Viewport::letterbox(
+            self.context.config.width,
+            self.context.config.height,
+            self.target_aspect,
+        );
+        let resized = viewport.size() != self.viewport.size();
+        self.viewport = viewport;
+        if !resized && !force {
+            return;
+        }
+        let (width, height) = viewport.size();
+        self.projection.resize(width, height);
+        self.alternate_projection.resize(width, height);
+        let viewport_config = self.viewport_config();
+        if let Some(fxaa) = &mut self.fxaa {
+            fxaa.resize(&self.context.device, &viewport_config);
+        }
+        self.resize_scene_targets();
+    }
+
+    // Where the scene ends up on the surface, see target_aspect
+    pub fn viewport(&self) -> renderer::Viewport {
+        self.viewport
+    }
+
+    // The surface configuration with the viewport's size
+    fn viewport_config(&self) -> wgpu::SurfaceConfiguration {
+        let (width, height) = self.viewport.size();
+        wgpu::SurfaceConfiguration {
+            width,
+            height,
+            ..self.context.config.clone()
+        }
+    }
+
+    // Called when the window moves to a display with a different DPI. The
+    // new physical size arrives through resize.
+    fn set_scale_factor(&mut self, scale_factor: f64) {
+        self.gizmo.scale_factor = scale_factor;
+    }
+
+    // Pick a mode for set_display_mode from these with display::video_modes.
+    // The primary monitor comes first.
+    pub fn monitors(&self) -> &[winit::monitor::MonitorHandle] {
+        &self.monitors
+    }
+
+    fn set_monitors(&mut self, monitors: Vec<winit::monitor::MonitorHandle>) {
+        self.monitors = monitors;
+    }
+
+    pub fn display_mode(&self) -> &display::DisplayMode {
+        &self.display_mode
+    }
+
+    // Switches the window at the start of the next frame, and resizes the
+    // surface to the video mode's size for exclusive fullscreen. Stays
+    // windowed if the mode's monitor has gone away.
+    pub fn set_display_mode(&mut self, mode: display::DisplayMode) {
+        let mode = match mode {
+            display::DisplayMode::Exclusive(video_mode)
+                if !self.monitors.contains(&video_mode.monitor()) =>
+            {
+                log::warn!("{:?} isn't on a known monitor, staying windowed", video_mode);
+                display::DisplayMode::Windowed
+            }
+            mode => mode,
+        };
+        if mode != self.display_mode {
+            self.display_mode = mode;
+            self.display_mode_changed = true;
+        }
+    }
+
+    // Exclusive fullscreen on the primary monitor at its largest video mode,
+    // or windowed when there are no monitors or modes to pick from
+    pub fn set_fullscreen(&mut self, fullscreen: bool) {
+        let video_mode = self
+            .monitors
+            .first()
+            .and_then(|monitor| display::video_modes(monitor).into_iter().next());
+        let mode = match video_mode {
+            Some(video_mode) if fullscreen => display::DisplayMode::Exclusive(video_mode),
+            None if fullscreen => {
+                log::warn!("No monitor video modes to go fullscreen with, staying windowed");
+                display::DisplayMode::Windowed
+            }
+            _ => display::DisplayMode::Windowed,
+        };
+        self.set_display_mode(mode);
+    }
+
+    fn take_display_mode_change(&mut self) -> Option<display::DisplayMode> {
+        std::mem::take(&mut self.display_mode_changed).then(|| self.display_mode.clone())
+    }
+
+    fn resize_scene_targets(&mut self) {
+        let scene_config = scaled_config(&self.viewport_config(), self.resolution_scale);
+        let size = (scene_config.width, scene_config.height);
+        let sample_count = self.anti_aliasing.sample_count();
+        self.depth_texture = texture::Texture::create_multisampled_depth_texture(
+            &self.context.device,
+            &scene_config,
+            sample_count,
+            "depth_texture",
+        );
+        self.hdr.resize(&self.context.device, size);
+        self.dof.resize(
+            &self.context.device,
+            sample_count,
+            &self.hdr.view,
+            &self.depth_texture,
+            size,
+        );
+        self.msaa_target = (sample_count > 1).then(|| {
+            antialiasing::MsaaTarget::new(&self.context.device, self.hdr.format, size, sample_count)
+        });
+        self.selection_outline.resize(&self.context.device, &scene_config);
+        // The new targets are expected, they aren't a leak
+        self.memory_growth.rebaseline(memory::estimated().total());
+    }
+
+    // Draws the opaque world geometry twice, depth only first, so the full
+    // shading only runs once per pixel no matter how much overlaps. The
+    // image stays the same. Only pays off when fragments cost more than the
+    // extra vertex work, and is skipped while a model pipeline is set.
+    pub fn set_depth_prepass(&mut self, enabled: bool) {
+        if enabled == self.depth_prepass() {
+            return;
+        }
+        self.pipelines = renderer::ScenePipelines::new(
+            &self.context.device,
+            &self.render_pipeline_layout,
+            &self.light_pipeline_layout,
+            self.hdr.format,
+            self.anti_aliasing.sample_count(),
+            enabled,
+            self.lights.is_storage(),
+        );
+    }
+
+    pub fn depth_prepass(&self) -> bool {
+        self.pipelines.prepass.is_some()
+    }
+
+    // The prepass pipelines, if the prepass should run this frame. The
+    // wireframe lines wouldn't pass its equal depth test.
+    pub fn depth_prepass_pipelines(&self) -> Option<&renderer::PrepassPipelines> {
+        self.pipelines
+            .prepass
+            .as_ref()
+            .filter(|_| self.model_pipeline.is_none() && self.wireframe_pipeline().is_none())
+    }
+
+    pub fn use_wireframe(&self) -> bool {
+        self.use_wireframe
+    }
+
+    // Draws the models' triangle edges instead of filling them, with
+    // whatever shading the material has. Skinned models, batched objects
+    // and a pipeline set with set_model_pipeline stay filled. Needs
+    // Features::POLYGON_MODE_LINE, everything stays filled without it.
+    pub fn set_use_wireframe(&mut self, use_wireframe: bool) {
+        if use_wireframe && self.pipelines.wireframe.is_none() {
+            log::warn!("Wireframe mode needs Features::POLYGON_MODE_LINE, drawing filled instead");
+        }
+        self.use_wireframe = use_wireframe;
+    }
+
+    // The wireframe pipeline, if the models should be drawn with it this
+    // frame
+    pub fn wireframe_pipeline(&self) -> Option<&wgpu::RenderPipeline> {
+        self.pipelines
+            .wireframe
+            .as_ref()
+            .filter(|_| self.use_wireframe && self.model_pipeline.is_none())
+    }
+
+    pub fn clear_color(&self) -> wgpu::Color {
+        self.clear_color
+    }
+
+    // The flat color behind the scene, in linear color like the scene target
+    // it clears. Hidden by a background gradient while there is one.
+    pub fn set_clear_color(&mut self, color: wgpu::Color) {
+        self.clear_color = color;
+    }
+
+    // Fills the background with a vertical gradient from top to bottom, in
+    // linear color, instead of the flat clear color. Scene geometry, the
+    // background layers included, all draws over it.
+    pub fn set_background_gradient(&mut self, top: [f32; 3], bottom: [f32; 3]) {
+        self.background.set_gradient(&self.context.queue, Some((top, bottom)));
+    }
+
+    // Goes back to the flat clear color
+    pub fn clear_background_gradient(&mut self) {
+        self.background.set_gradient(&self.context.queue, None);
+    }
+
+    // The top and bottom colors, if there is a gradient
+    pub fn background_gradient(&self) -> Option<([f32; 3], [f32; 3])> {
+        self.background.gradient()
+    }
+
+    pub fn render_features(&self) -> settings::RenderFeatures {
+        settings::RenderFeatures {
+            msaa: self.anti_aliasing == antialiasing::AntiAliasing::Msaa,
+            ..self.features
+        }
+    }
+
+    // Switching msaa goes through set_anti_aliasing, with its pipeline
+    // rebuilds, the rest only changes which passes run
+    pub fn set_render_features(&mut self, features: settings::RenderFeatures) {
+        let anti_aliasing = match (features.msaa, self.anti_aliasing) {
+            (true, _) => antialiasing::AntiAliasing::Msaa,
+            (false, antialiasing::AntiAliasing::Msaa) => antialiasing::AntiAliasing::None,
+            (false, anti_aliasing) => anti_aliasing,
+        };
+        self.set_anti_aliasing(anti_aliasing);
+        self.features = features;
+    }
+
+    pub fn anti_aliasing(&self) -> antialiasing::AntiAliasing {
+        self.anti_aliasing
+    }
+
+    // Switching to or from MSAA rebuilds every pipeline that draws into the
+    // scene target. A pipeline set with set_model_pipeline can't be rebuilt
+    // here, so it is dropped and has to be created again.
+    pub fn set_anti_aliasing(&mut self, anti_aliasing: antialiasing::AntiAliasing) {
+        if anti_aliasing == self.anti_aliasing {
+            return;
+        }
+        let sample_count_changed = anti_aliasing.sample_count() != self.anti_aliasing.sample_count();
+        self.anti_aliasing = anti_aliasing;
+
+        if sample_count_changed {
+            let device = &self.context.device;
+            let sample_count = anti_aliasing.sample_count();
+            self.pipelines = renderer::ScenePipelines::new(
+                device,
+                &self.render_pipeline_layout,
+                &self.light_pipeline_layout,
+                self.hdr.format,
+                sample_count,
+                self.depth_prepass(),
+                self.lights.is_storage(),
+            );
+            self.background.recreate_pipeline(device, self.hdr.format, sample_count);
+            self.instance_debug = instance_debug::InstanceDebug::new(
+                device,
+                self.hdr.format,
+                sample_count,
+                &self.camera_bind_group_layout,
+            );
+            self.grid.recreate_pipeline(
+                device,
+                self.hdr.format,
+                sample_count,
+                &self.camera_bind_group_layout,
+            );
+            self.wireframe.recreate_pipelines(
+                device,
+                self.hdr.format,
+                sample_count,
+                &self.camera_bind_group_layout,
+                &self.mesh_bind_group_layout,
+            );
+            if let Some(skinning) = &mut self.skinning {
+                skinning.recreate_pipelines(device, self.hdr.format, sample_count);
+            }
+            if self.model_pipeline.take().is_some() {
+                log::warn!("The model pipeline doesn't match the new sample count and was dropped");
+            }
+            self.resize_scene_targets();
         }
+
+        self.fxaa = (anti_aliasing == antialiasing::AntiAliasing::Fxaa)
+            .then(|| antialiasing::Fxaa::new(&self.context.device, &self.viewport_config()));
+    }
+
+    // The multisampled target to draw the scene into, if MSAA is on. It
+    // resolves into hdr().view.
+    pub fn msaa_target(&self) -> Option<&antialiasing::MsaaTarget> {
+        self.msaa_target.as_ref()
+    }
+
+    // The pass between tone mapping and the swapchain, if FXAA is on
+    pub fn fxaa(&self) -> Option<&antialiasing::Fxaa> {
+        self.fxaa.as_ref()
     }
 
     fn input(&mut self, event: &WindowEvent) -> bool {
@@ -218,121 +1030,1238 @@ impl State {
             WindowEvent::KeyboardInput {
                 input:
                     KeyboardInput {
-                        virtual_keycode: Some(key),
-                        state,
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::F),
                         ..
                     },
                 ..
-            } => self.camera_controller.process_keyboard(*key, *state),
-            WindowEvent::MouseWheel { delta, .. } => {
-                self.camera_controller.process_scroll(delta);
+            } => {
+                self.camera_frozen = !self.camera_frozen;
+                // Forget held keys so the camera doesn't jump when unfrozen
+                self.camera_controller.reset();
                 true
             }
-            WindowEvent::MouseInput {
-                button: MouseButton::Left,
-                state,
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode:
+                            Some(
+                                key @ (VirtualKeyCode::Key1
+                                | VirtualKeyCode::Key2
+                                | VirtualKeyCode::Key3
+                                | VirtualKeyCode::Key4),
+                            ),
+                        ..
+                    },
                 ..
             } => {
-                self.mouse_pressed = *state == ElementState::Pressed;
+                self.shader_variant = match key {
+                    VirtualKeyCode::Key1 => 0,
+                    VirtualKeyCode::Key2 => 1,
+                    VirtualKeyCode::Key3 => 2,
+                    _ => 3,
+                };
                 true
             }
-            _ => false,
-        }
-    }
-
-    fn update(&mut self, dt: std::time::Duration) {
-        self.camera_controller.update_camera(&mut self.camera, dt);
-        self.camera_uniform
-            .update_view_proj(&self.camera, &self.projection);
-        self.context.queue.write_buffer(
-            &self.camera_buffer,
-            0,
-            bytemuck::cast_slice(&[self.camera_uniform]),
-        );
-
-        let old_position: cgmath::Vector3<_> = self.light_uniform.position.into();
-        self.light_uniform.position =
-            (cgmath::Quaternion::from_axis_angle((0.0, 1.0, 0.0).into(), cgmath::Deg(1.0))
-                * old_position)
-                .into();
-        self.context.queue.write_buffer(
-            &self.light_buffer,
-            0,
-            bytemuck::cast_slice(&[self.light_uniform]),
-        );
-    }
-
-    fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        renderer::render(self)
-    }
-}
-
-pub async fn run() {
-    env_logger::init();
-    let event_loop = EventLoop::new();
-    let title = env!("CARGO_PKG_NAME");
-    let window = winit::window::WindowBuilder::new()
-        .with_title(title)
-        .build(&event_loop)
-        .unwrap();
-
-    // State::new uses async code, so we're going to wait for it to finish
-    let mut state = State::new(&window).await;
-    let mut last_render_time = instant::Instant::now();
-    event_loop.run(move |event, _, control_flow| {
-        *control_flow = ControlFlow::Poll;
-        match event {
-            Event::MainEventsCleared => window.request_redraw(),
-
-            Event::DeviceEvent {
-                event: DeviceEvent::MouseMotion{ delta, },
-                .. // We're not using device_id currently
-            } => if state.mouse_pressed {
-                state.camera_controller.process_mouse(delta.0, delta.1)
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::M),
+                        ..
+                    },
+                ..
+            } => {
+                use antialiasing::AntiAliasing;
+                self.set_anti_aliasing(match self.anti_aliasing {
+                    AntiAliasing::None => AntiAliasing::Msaa,
+                    AntiAliasing::Msaa => AntiAliasing::Fxaa,
+                    AntiAliasing::Fxaa => AntiAliasing::None,
+                });
+                log::info!("Anti-aliasing: {:?}", self.anti_aliasing);
+                true
             }
-
-            Event::WindowEvent {
-                ref event,
-                window_id,
-            } if window_id == window.id() && !state.input(event) => {
-                match event {
-                    #[cfg(not(target_arch="wasm32"))]
-                    WindowEvent::CloseRequested
-                    | WindowEvent::KeyboardInput {
-                        input:
-                            KeyboardInput {
-                                state: ElementState::Pressed,
-                                virtual_keycode: Some(VirtualKeyCode::Escape),
-                                ..
-                            },
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::H),
                         ..
-                    } => *control_flow = ControlFlow::Exit,
-                    WindowEvent::Resized(physical_size) => {
-                        state.resize(*physical_size);
-                    }
-                    WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
-                        state.resize(**new_inner_size);
-                    }
-                    _ => {}
-                }
+                    },
+                ..
+            } => {
+                self.reset_camera();
+                true
             }
-
-            Event::RedrawRequested(window_id) if window_id == window.id() => {
-                let now = instant::Instant::now();
-                let dt = now - last_render_time;
-                last_render_time = now;
-                state.update(dt);
-                match state.render() {
-                    Ok(_) => {}
-                    // Reconfigure the surface if it's lost or outdated
-                    Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => state.resize(state.context.size),
-                    // The system is out of memory, we should probably quit
-                    Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
-                    // We're ignoring timeouts
-                    Err(wgpu::SurfaceError::Timeout) => log::warn!("Surface timeout"),
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::L),
+                        ..
+                    },
+                ..
+            } => {
+                self.light_follows_camera = !self.light_follows_camera;
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::G),
+                        ..
+                    },
+                ..
+            } => {
+                if self.instance_compute.is_some() {
+                    self.gpu_driven = !self.gpu_driven;
+                } else {
+                    log::warn!("GPU driven instances need compute shader support");
                 }
+                true
             }
-            _ => {}
-        }
-    });
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::C),
+                        ..
+                    },
+                ..
+            } => {
+                self.toggle_debug_columns();
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::P),
+                        ..
+                    },
+                ..
+            } => {
+                self.paused = !self.paused;
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::K),
+                        ..
+                    },
+                ..
+            } => {
+                self.features.light_debug = !self.features.light_debug;
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::V),
+                        ..
+                    },
+                ..
+            } => {
+                self.debug_view = self.debug_view.next();
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::B),
+                        ..
+                    },
+                ..
+            } => {
+                self.instance_draw_mode = self.instance_draw_mode.next();
+                true
+            }
+            // I switches between vsync and presenting as fast as possible
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::I),
+                        ..
+                    },
+                ..
+            } => {
+                let mode = match self.context.present_mode() {
+                    wgpu::PresentMode::Immediate => wgpu::PresentMode::Fifo,
+                    _ => wgpu::PresentMode::Immediate,
+                };
+                log::info!("Presenting with {:?}", mode);
+                self.set_present_mode(mode);
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::T),
+                        ..
+                    },
+                ..
+            } => {
+                self.set_use_wireframe(!self.use_wireframe);
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::N),
+                        ..
+                    },
+                ..
+            } => {
+                let next = CLEAR_COLORS
+                    .iter()
+                    .position(|&color| color == self.clear_color)
+                    .map_or(0, |index| (index + 1) % CLEAR_COLORS.len());
+                self.set_clear_color(CLEAR_COLORS[next]);
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::O),
+                        ..
+                    },
+                ..
+            } => {
+                std::mem::swap(&mut self.projection, &mut self.alternate_projection);
+                true
+            }
+            // X toggles a section view along the x axis, comma and period
+            // slide the plane
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(key @ (VirtualKeyCode::X | VirtualKeyCode::Comma | VirtualKeyCode::Period)),
+                        ..
+                    },
+                ..
+            } => {
+                let plane = match (key, self.clip_plane) {
+                    (VirtualKeyCode::X, Some(_)) => None,
+                    (VirtualKeyCode::X, None) => Some([-1.0, 0.0, 0.0, 0.0]),
+                    (VirtualKeyCode::Comma, Some([x, y, z, w])) => Some([x, y, z, w - SECTION_STEP]),
+                    (VirtualKeyCode::Period, Some([x, y, z, w])) => Some([x, y, z, w + SECTION_STEP]),
+                    (_, plane) => plane,
+                };
+                self.set_clip_plane(plane);
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(key),
+                        state,
+                        ..
+                    },
+                ..
+            } if !self.camera_frozen => self.camera_controller.process_keyboard(*key, *state),
+            WindowEvent::MouseWheel { delta, .. } if !self.camera_frozen => {
+                self.camera_controller.process_scroll(delta);
+                true
+            }
+            WindowEvent::MouseInput {
+                button: MouseButton::Left,
+                state,
+                ..
+            } => {
+                self.mouse_pressed = *state == ElementState::Pressed;
+                true
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor_position = *position;
+                true
+            }
+            WindowEvent::MouseInput {
+                button: MouseButton::Right,
+                state: ElementState::Pressed,
+                ..
+            } => {
+                let picked = self.pick_id_at(self.cursor_position.x as u32, self.cursor_position.y as u32);
+                log::info!("Picked instance {:?}", picked);
+                self.set_selected(picked.as_slice());
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn model_rotation(&self) -> cgmath::Deg<f32> {
+        self.model_rotation
+    }
+
+    pub fn set_model_rotation<A: Into<cgmath::Deg<f32>>>(&mut self, rotation: A) {
+        self.model_rotation = cgmath::Deg(rotation.into().0 % 360.0);
+    }
+
+    // For settings like CameraController::smoothing
+    pub fn camera_controller_mut(&mut self) -> &mut camera::CameraController {
+        &mut self.camera_controller
+    }
+
+    pub fn camera(&self) -> &camera::Camera {
+        &self.camera
+    }
+
+    // For moving the camera from code. The controller lets go of wherever
+    // smoothing was taking the camera, and keeps applying input on top
+    // unless camera_frozen is set. Call sync_camera to show the change
+    // without waiting for update, which skips it while frozen.
+    pub fn camera_mut(&mut self) -> &mut camera::Camera {
+        &mut self.camera
+    }
+
+    // Writes the camera and projection to the uniform the shaders read
+    pub fn sync_camera(&mut self) {
+        self.camera_uniform
+            .update_view_proj(&self.camera, &self.projection);
+        self.context.queue.write_buffer(
+            &self.camera_buffer,
+            0,
+            bytemuck::cast_slice(&[self.camera_uniform]),
+        );
+    }
+
+    // Puts the camera back where it started and drops any movement still
+    // pending in the controller. Applies even while the camera is frozen.
+    pub fn reset_camera(&mut self) {
+        self.camera = self.initial_camera;
+        self.camera_controller.reset();
+        self.sync_camera();
+    }
+
+    // Point and directional lights, in the order they're indexed
+    pub fn lights(&self) -> &[light::LightUniform] {
+        self.lights.lights()
+    }
+
+    // Adds a light and returns its index. Lights are indexed in the
+    // order they were pushed, starting with the one State starts with.
+    pub fn push_light(&mut self, light: light::LightUniform) -> usize {
+        let index = self
+            .lights
+            .push_light(&self.context.device, &self.context.queue, light);
+        // The buffer may have grown into a new one
+        self.light_bind_group = light::create_bind_group(
+            &self.context.device,
+            &self.light_bind_group_layout,
+            self.lights.buffer(),
+        );
+        index
+    }
+
+    // Removes the light at index, and the lights after it move down one.
+    // Removing light 0 hands the orbit to the next one.
+    pub fn remove_light(&mut self, index: usize) -> Option<light::LightUniform> {
+        self.lights.remove_light(&self.context.queue, index)
+    }
+
+    // Light 0's position is overwritten by update each frame, its color
+    // isn't
+    pub fn set_light(&mut self, index: usize, light: light::LightUniform) {
+        self.lights.set_light(&self.context.queue, index, light);
+    }
+
+    // The LightType::Directional entries of lights
+    pub fn directional_lights(&self) -> Vec<light::DirectionalLight> {
+        self.lights
+            .lights()
+            .iter()
+            .filter(|light| light.light_type() == light::LightType::Directional)
+            .map(|light| light::DirectionalLight {
+                direction: light.direction().into(),
+                color: light.color,
+            })
+            .collect()
+    }
+
+    // Replaces every directional light in lights, which are added after the
+    // rest. The point lights are left as they are.
+    pub fn set_directional_lights(&mut self, lights: &[light::DirectionalLight]) {
+        self.lights.retain(&self.context.queue, |light| {
+            light.light_type() != light::LightType::Directional
+        });
+        for &light in lights {
+            self.push_light(light.into());
+        }
+    }
+
+    // Sets up the preset's directional lights around where the camera looks
+    // now. They stay put when the camera moves afterwards.
+    pub fn apply_lighting_preset(&mut self, preset: light::LightingPreset) {
+        let lights = preset.lights(self.camera.forward());
+        self.set_directional_lights(&lights);
+    }
+
+    // Advances the scene by dt. Nothing here reads the clock, so calling
+    // this with a fixed dt makes rendering deterministic.
+    pub fn update(&mut self, dt: std::time::Duration) {
+        self.update_viewport(false);
+        self.memory_growth.check(self.estimated_gpu_memory().total());
+        // Written while paused too, so delta_time drops to 0 and the
+        // resolution stays current
+        let dt_running = if self.paused {
+            std::time::Duration::ZERO
+        } else {
+            dt
+        };
+        self.time += dt_running;
+        let scene_config = scaled_config(&self.viewport_config(), self.resolution_scale);
+        self.context.queue.write_buffer(
+            &self.globals_buffer,
+            0,
+            bytemuck::cast_slice(&[settings::GlobalsUniform {
+                time: self.time.as_secs_f32(),
+                delta_time: dt_running.as_secs_f32(),
+                resolution: [scene_config.width as f32, scene_config.height as f32],
+            }]),
+        );
+        if self.paused {
+            self.was_paused = true;
+            return;
+        }
+        // Mouse movement and scrolling still pile up in the controller while
+        // paused, drop them rather than jumping the camera
+        if self.was_paused {
+            self.was_paused = false;
+            self.camera_controller.reset();
+        }
+
+        if !self.camera_frozen {
+            self.camera_controller.update_camera(&mut self.camera, dt);
+            self.sync_camera();
+        }
+        self.gizmo.update(&self.context.queue, &self.camera);
+        if self.features.grid {
+            self.grid.update(&self.context.queue);
+        }
+        for skinned in &mut self.skinned_models {
+            skinned.update(&self.context.queue, dt);
+        }
+        self.hdr.update(&self.context.queue, self.tone_map_operator, self.exposure);
+        if self.features.depth_of_field {
+            self.dof.update(
+                &self.context.queue,
+                dof::DofSettings {
+                    focus_distance: self.focus_distance,
+                    aperture: self.aperture,
+                    znear: self.projection.znear(),
+                    zfar: self.projection.zfar(),
+                    log_depth_coef: self.depth_mode.log_depth_coef(&self.projection),
+                },
+            );
+        }
+        if let Err(e) = self.batcher.prepare(&self.context.device, &self.context.queue) {
+            log::warn!("Failed to upload batched objects: {:?}", e);
+        }
+        if self.features.wireframe {
+            let scene_config = scaled_config(&self.viewport_config(), self.resolution_scale);
+            self.wireframe.update(
+                &self.context.queue,
+                &self.batcher,
+                (scene_config.width, scene_config.height),
+            );
+        }
+
+        self.context.queue.write_buffer(
+            &self.settings_buffer,
+            0,
+            bytemuck::cast_slice(&[settings::SettingsUniform::new(
+                self.fade_start,
+                self.fade_end,
+                self.depth_mode.log_depth_coef(&self.projection),
+            )
+            .with_clip_plane(self.clip_plane)
+            .with_debug_view(self.debug_view, self.projection.zfar())
+            .with_near_fade(
+                self.projection.znear(),
+                self.features.near_fade.then_some(self.near_fade_distance),
+            )]),
+        );
+        self.set_model_rotation(cgmath::Deg(
+            self.model_rotation.0 + self.model_rotation_speed * dt.as_secs_f32(),
+        ));
+        if let Some(updater) = &mut self.instance_updater {
+            updater(&mut self.instances, dt);
+        }
+        match &mut self.instance_compute {
+            Some(instance_compute) if self.gpu_driven => {
+                instance_compute.update(&self.context.queue, dt);
+                self.use_gpu_instances();
+            }
+            _ => self.update_instances(),
+        }
+
+        self.cull_stats_log_timer += dt.as_secs_f32();
+        if self.cull_stats_log_timer >= CULL_STATS_LOG_INTERVAL {
+            self.cull_stats_log_timer = 0.0;
+            let stats = self.cull_stats;
+            log::debug!(
+                "Instances: {} total, {} drawn, {} culled",
+                stats.total,
+                stats.drawn,
+                stats.culled
+            );
+        }
+
+        // Driven by dt so the same sequence of dts always gives the same frames
+        self.light_orbit_position = cgmath::Quaternion::from_axis_angle(
+            (0.0, 1.0, 0.0).into(),
+            cgmath::Deg(LIGHT_ROTATION_SPEED * dt.as_secs_f32()),
+        ) * self.light_orbit_position;
+
+        let step = dt.as_secs_f32() / HEADLAMP_TRANSITION_TIME;
+        self.headlamp_blend = if self.light_follows_camera {
+            (self.headlamp_blend + step).min(1.0)
+        } else {
+            (self.headlamp_blend - step).max(0.0)
+        };
+        // Smoothstep so the light eases in and out of the move
+        let t = self.headlamp_blend * self.headlamp_blend * (3.0 - 2.0 * self.headlamp_blend);
+        let eye = cgmath::Vector3::new(
+            self.camera.position.x,
+            self.camera.position.y,
+            self.camera.position.z,
+        );
+        if let Some(mut light) = self.lights.get(0) {
+            light.position = self.light_orbit_position.lerp(eye, t).into();
+            self.lights.set_light(&self.context.queue, 0, light);
+        }
+    }
+
+    // The compute pass writes every instance in index order, so they are all
+    // drawn as opaque at LOD 0
+    fn use_gpu_instances(&mut self) {
+        let count = self
+            .instance_compute
+            .as_ref()
+            .map_or(0, compute::InstanceCompute::instance_count)
+            .min(self.instances.len() as u32);
+        self.num_opaque_instances = count;
+        self.num_fading_instances = 0;
+        self.cull_stats = instance::CullStats {
+            total: count,
+            drawn: count,
+            culled: 0,
+        };
+        self.instance_order = (0..count).collect();
+        self.instance_distances = vec![0.0; count as usize];
+    }
+
+    // How long update has run for, not counting pauses, as the shaders see
+    // it in settings::GlobalsUniform::time
+    pub fn time(&self) -> std::time::Duration {
+        self.time
+    }
+
+    // Sets the world clock back to 0 for replays and image comparisons: the
+    // time the shaders see, the light's orbit, the model rotation, the GPU
+    // driven instances and skinned animations all start over. Frames
+    // rendered after this from the same dts and input come out the same as
+    // after a fresh start. What an instance updater or on_update moved is
+    // left alone, reset that from the same place.
+    pub fn reset_time(&mut self) {
+        self.time = std::time::Duration::ZERO;
+        self.light_orbit_position = light::LightUniform::new().position.into();
+        // Settled wherever light_follows_camera has it, as after a start
+        // with the light already there
+        self.headlamp_blend = if self.light_follows_camera { 1.0 } else { 0.0 };
+        self.set_model_rotation(cgmath::Deg(0.0));
+        if let Some(instance_compute) = &mut self.instance_compute {
+            instance_compute.reset_time();
+        }
+        for skinned in &mut self.skinned_models {
+            skinned.rewind_animation();
+        }
+    }
+
+    // GPU pass timings from the last rendered frame
+    pub fn frame_stats(&self) -> profiler::FrameStats {
+        self.frame_stats
+    }
+
+    // Bytes the engine's own buffers and textures take up, see
+    // memory::estimated. Updated as they're created and dropped, and warned
+    // about when it keeps growing.
+    pub fn estimated_gpu_memory(&self) -> memory::MemoryEstimate {
+        memory::estimated()
+    }
+
+    // The timer to record pass timestamps with, if GPU profiling is on
+    pub fn gpu_timer(&self) -> Option<&profiler::GpuTimer> {
+        self.gpu_timer.as_ref().filter(|_| self.gpu_profiling)
+    }
+
+    // The scene target and the tone mapping pass that resolves it
+    pub fn hdr(&self) -> &hdr::HdrPipeline {
+        &self.hdr
+    }
+
+    // Draws the scene at a fraction of the window's resolution, which is
+    // then upscaled into the swapchain. The gizmo is still drawn at full
+    // resolution. Clamped to [MIN_RESOLUTION_SCALE, 1].
+    pub fn set_resolution_scale(&mut self, scale: f32) {
+        let scale = if scale.is_nan() {
+            1.0
+        } else {
+            scale.clamp(MIN_RESOLUTION_SCALE, 1.0)
+        };
+        if scale != self.resolution_scale {
+            self.resolution_scale = scale;
+            self.resize_scene_targets();
+        }
+    }
+
+    pub fn resolution_scale(&self) -> f32 {
+        self.resolution_scale
+    }
+
+    // The compute pass to record before drawing, if instances are GPU driven
+    pub fn gpu_instance_compute(&self) -> Option<&compute::InstanceCompute> {
+        self.instance_compute.as_ref().filter(|_| self.gpu_driven)
+    }
+
+    // Rebuilds the instance buffer so that it holds the opaque instances
+    // sorted by opaque_sort_mode followed by the fading ones sorted back to
+    // front. Anything past fade_end or outside the camera's frustum is culled.
+    fn update_instances(&mut self) {
+        let eye = self.camera.position;
+        let frustum = self.camera.frustum(&self.projection);
+        // Taken from the meshes every time, so it follows their transforms
+        let model_radius = self.obj_model.bounding_radius();
+        let mut opaque = Vec::new();
+        let mut fading = Vec::new();
+        for (i, instance) in self.instances.iter().enumerate() {
+            if self.hidden_instances.contains(&(i as u32)) {
+                continue;
+            }
+            let (center, radius) = instance.bounding_sphere(model_radius);
+            if !frustum.intersects_sphere(center, radius) {
+                continue;
+            }
+            let distance = instance.distance_to(eye);
+            if distance - radius > self.fade_end {
+                continue;
+            } else if distance + radius < self.fade_start {
+                opaque.push((distance, i as u32));
+            } else {
+                fading.push((distance, i as u32));
+            }
+        }
+        // Keeping both halves sorted by distance also keeps instances that
+        // share a LOD level next to each other in the buffer. Opaque instances
+        // don't need a strict order, so the debug flagged and mirrored ones
+        // are grouped together to keep the number of draws down.
+        let debug_instances = &self.debug_instances;
+        let instances = &self.instances;
+        let sort_mode = self.opaque_sort_mode;
+        opaque.sort_by(|a, b| {
+            let order = match sort_mode {
+                settings::OpaqueSortMode::None => a.1.cmp(&b.1),
+                settings::OpaqueSortMode::FrontToBack => a.0.total_cmp(&b.0),
+            };
+            debug_instances
+                .contains(&a.1)
+                .cmp(&debug_instances.contains(&b.1))
+                .then(
+                    instances[a.1 as usize]
+                        .is_mirrored()
+                        .cmp(&instances[b.1 as usize].is_mirrored()),
+                )
+                .then(order)
+        });
+        fading.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        self.num_opaque_instances = opaque.len() as u32;
+        self.num_fading_instances = fading.len() as u32;
+        // Hidden instances count as culled
+        let drawn = self.num_opaque_instances + self.num_fading_instances;
+        self.cull_stats = instance::CullStats {
+            total: self.instances.len() as u32,
+            drawn,
+            culled: self.instances.len() as u32 - drawn,
+        };
+        (self.instance_distances, self.instance_order) = opaque.into_iter().chain(fading).unzip();
+
+        let spin = cgmath::Quaternion::from_angle_y(self.model_rotation);
+        let instance_data = self
+            .instance_order
+            .iter()
+            .map(|&i| self.instances[i as usize].rotated(spin).to_raw())
+            .collect::<Vec<_>>();
+        self.context
+            .queue
+            .write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&instance_data));
+    }
+
+    // Adds a model drawn once with transform, before the instanced models,
+    // and returns its id. The transform is applied on top of the meshes' own.
+    pub fn add_static_model(
+        &mut self,
+        model: model::Model,
+        transform: cgmath::Matrix4<f32>,
+    ) -> usize {
+        self.static_models.push(model::StaticModel::new(
+            &self.context.queue,
+            model,
+            transform,
+        ));
+        self.static_models.len() - 1
+    }
+
+    pub fn set_static_model_transform(&mut self, id: usize, transform: cgmath::Matrix4<f32>) {
+        self.static_models[id].set_transform(&self.context.queue, transform);
+    }
+
+    // The ground when it's shown, then the static models in the order added
+    // Under OpaqueSortMode::FrontToBack these are sorted by how far their
+    // origin is from the camera, which is rough for big models like the
+    // ground but cheap
+    fn static_models(&self) -> Vec<&model::StaticModel> {
+        let mut static_models: Vec<_> = self
+            .features
+            .ground
+            .then_some(&self.ground)
+            .into_iter()
+            .chain(&self.static_models)
+            .collect();
+        if self.opaque_sort_mode == settings::OpaqueSortMode::FrontToBack {
+            let eye = self.camera.position;
+            let distance = |m: &model::StaticModel| {
+                eye.distance2(cgmath::Point3::from_vec(m.transform().w.truncate()))
+            };
+            static_models.sort_by(|a, b| distance(a).total_cmp(&distance(b)));
+        }
+        static_models
+    }
+
+    // Loads the skinned meshes, skeleton and animations of a .gltf or .glb
+    // file, see gltf::load_skinned_model, and returns the id to animate it
+    // with. Fails where vertex shaders can't read storage buffers, which
+    // includes WebGL2.
+    pub async fn load_skinned_model(
+        &mut self,
+        file_name: &str,
+        transform: cgmath::Matrix4<f32>,
+    ) -> anyhow::Result<usize> {
+        let skinning = self.skinning.as_ref().ok_or_else(|| {
+            anyhow::anyhow!("Skinning needs vertex shader storage buffers, which aren't supported")
+        })?;
+        let mut skinned = gltf::load_skinned_model(
+            file_name,
+            &self.context.device,
+            &self.context.queue,
+            &self.texture_bind_group_layout,
+            skinning,
+        )
+        .await?;
+        skinned.set_transform(&self.context.queue, transform);
+        self.skinned_models.push(skinned);
+        Ok(self.skinned_models.len() - 1)
+    }
+
+    // For playing animations and setting the transform, blend and speed
+    pub fn skinned_model_mut(&mut self, id: usize) -> &mut skinning::SkinnedModel {
+        &mut self.skinned_models[id]
+    }
+
+    pub fn skinned_model(&self, id: usize) -> &skinning::SkinnedModel {
+        &self.skinned_models[id]
+    }
+
+    // Starts the skinned model's animation with the name from the beginning
+    pub fn play_animation(&mut self, id: usize, name: &str) -> anyhow::Result<()> {
+        self.skinned_models[id].play_animation(name)
+    }
+
+    // Loads an .obj file, see resources::load_model, and draws it with
+    // instances like add_instanced_model. name is for remove_model.
+    pub async fn add_model(
+        &mut self,
+        name: &str,
+        path: &str,
+        instances: &[instance::Instance],
+    ) -> anyhow::Result<()> {
+        let model = resources::load_model(
+            path,
+            &self.context.device,
+            &self.context.queue,
+            &self.texture_bind_group_layout,
+            &self.mesh_bind_group_layout,
+            resources::ImportOptions::default(),
+        )
+        .await
+        .with_context(|| format!("Failed to load model {:?} from {}", name, path))?;
+        self.add_instanced_model(model, instances)?;
+        if let Some(entry) = self.models.last_mut() {
+            entry.name = name.to_string();
+        }
+        Ok(())
+    }
+
+    // Stops drawing every model added with add_model under name, and returns
+    // whether there were any
+    pub fn remove_model(&mut self, name: &str) -> bool {
+        let count = self.models.len();
+        self.models.retain(|entry| entry.name != name);
+        self.models.len() != count
+    }
+
+    // Adds a model to draw alongside obj_model with its own set of instances
+    pub fn add_instanced_model(
+        &mut self,
+        model: model::Model,
+        instances: &[instance::Instance],
+    ) -> anyhow::Result<()> {
+        let instance_data = instances
+            .iter()
+            .map(instance::Instance::to_raw)
+            .collect::<Vec<_>>();
+        let instance_buffer =
+            instance::InstanceBufferBuilder::new(instance_data).build(&self.context.device)?;
+        self.models.push(model::InstancedModel {
+            name: String::new(),
+            model,
+            instance_buffer,
+            instance_count: instances.len() as u32,
+        });
+
+        Ok(())
+    }
+
+    // Registers a model for add_object and returns its id
+    pub fn add_batched_model(&mut self, model: model::Model) -> usize {
+        self.batcher.add_model(model)
+    }
+
+    // Adds an object drawn with a model from add_batched_model. Objects of
+    // the same model are gathered into one instanced draw every frame.
+    pub fn add_object(
+        &mut self,
+        model_id: usize,
+        transform: instance::Instance,
+    ) -> anyhow::Result<batch::ObjectId> {
+        self.batcher.add_object(model_id, transform)
+    }
+
+    pub fn set_object_transform(&mut self, id: batch::ObjectId, transform: instance::Instance) {
+        self.batcher.set_transform(id, transform);
+    }
+
+    // Layers are drawn lowest first, each in a pass of its own, and
+    // everything not in the batcher (obj_model, add_instanced_model, the grid
+    // and the light marker) is in batch::WORLD_LAYER. Objects still depth
+    // test against each other across layers, so the order only decides what
+    // blends over what, unless clear_depth_between_layers is set. Then each
+    // layer starts with a cleared depth buffer and is drawn over all the
+    // layers below it wherever it covers them, however far away it is, which
+    // keeps a foreground layer from ever clipping into the world.
+    pub fn set_object_layer(&mut self, id: batch::ObjectId, layer: i32) {
+        self.batcher.set_layer(id, layer);
+    }
+
+    pub fn object_layer(&self, id: batch::ObjectId) -> i32 {
+        self.batcher.layer(id)
+    }
+
+    // Draws the object's triangle edges over it in the style's color and
+    // thickness, or stops with None
+    pub fn set_object_wireframe(
+        &mut self,
+        id: batch::ObjectId,
+        style: Option<wireframe::WireframeStyle>,
+    ) {
+        self.wireframe.set(&self.context.device, id, style);
+    }
+
+    pub fn object_wireframe(&self, id: batch::ObjectId) -> Option<wireframe::WireframeStyle> {
+        self.wireframe.style(id)
+    }
+
+    // Adds an instance of obj_model and returns its index. The instance
+    // buffer doubles in size when it's full, so only the spawn that outgrows
+    // it reallocates.
+    pub fn spawn_instance(&mut self, instance: instance::Instance) -> anyhow::Result<u32> {
+        self.reserve_instances(self.instances.len() as u32 + 1)?;
+        self.instances.push(instance);
+
+        Ok(self.instances.len() as u32 - 1)
+    }
+
+    // Removes the instance of obj_model at index and returns it. Instances
+    // after it move down an index, and so do their debug flags, visibility
+    // and selection. The instance buffer keeps its capacity.
+    pub fn despawn_instance(&mut self, index: u32) -> Option<instance::Instance> {
+        if index as usize >= self.instances.len() {
+            return None;
+        }
+        let instance = self.instances.remove(index as usize);
+        let shift = |i: u32| if i > index { i - 1 } else { i };
+        self.debug_instances = self
+            .debug_instances
+            .iter()
+            .filter(|&&i| i != index)
+            .map(|&i| shift(i))
+            .collect();
+        self.hidden_instances = self
+            .hidden_instances
+            .iter()
+            .filter(|&&i| i != index)
+            .map(|&i| shift(i))
+            .collect();
+        self.selected.retain(|&i| i != index);
+        for i in &mut self.selected {
+            *i = shift(*i);
+        }
+        // The slots drawn last still point at the old indices
+        self.refresh_instances();
+
+        Some(instance)
+    }
+
+    pub fn instance_count(&self) -> u32 {
+        self.instances.len() as u32
+    }
+
+    // Makes room for count instances in the instance buffer, at least
+    // doubling its capacity when it has to grow. A new buffer starts out
+    // empty, so it's filled again straight away.
+    fn reserve_instances(&mut self, count: u32) -> anyhow::Result<()> {
+        if count <= self.instance_capacity {
+            return Ok(());
+        }
+        let capacity = count.max(self.instance_capacity * 2);
+        self.instance_buffer = instance::InstanceBufferBuilder::new(Vec::new())
+            .max_instances(capacity)
+            .storage(self.instance_compute.is_some())
+            .build(&self.context.device)?;
+        self.instance_capacity = capacity;
+        if let Some(instance_compute) = &mut self.instance_compute {
+            instance_compute.set_instance_buffer(&self.context.device, &self.instance_buffer);
+        }
+        self.memory_growth.rebaseline(memory::estimated().total());
+        self.refresh_instances();
+
+        Ok(())
+    }
+
+    // Brings the instance buffer and the slots drawn in line with instances
+    // now, rather than at the next update, which doesn't come while paused
+    fn refresh_instances(&mut self) {
+        match &self.instance_compute {
+            Some(_) if self.gpu_driven => self.use_gpu_instances(),
+            _ => self.update_instances(),
+        }
+    }
+
+    // Replaces every instance of obj_model with one per transform, growing
+    // the instance buffer like spawn_instance if they don't fit. Debug flags
+    // and the selection are dropped for indices that no longer exist. GPU
+    // driven instances keep their grid, see gpu_driven.
+    pub fn set_instance_transforms(
+        &mut self,
+        transforms: &[instance::Transform],
+    ) -> anyhow::Result<()> {
+        self.instances = instance::Instance::from_transforms(transforms);
+        let count = self.instances.len() as u32;
+        self.debug_instances.retain(|&i| i < count);
+        self.hidden_instances.retain(|&i| i < count);
+        self.selected.retain(|&i| i < count);
+        if count > self.instance_capacity {
+            self.reserve_instances(count)
+        } else {
+            self.refresh_instances();
+            Ok(())
+        }
+    }
+
+    // Lets updater move obj_model's instances around every frame before they
+    // are culled, sorted and written to the instance buffer, which happens
+    // each frame regardless. The slice can't change length, use
+    // spawn_instance, despawn_instance or set_instance_transforms for that. Skipped while
+    // paused. While gpu_driven it still runs, but nothing it moves is drawn.
+    pub fn set_instance_updater(&mut self, updater: InstanceUpdater) {
+        self.instance_updater = Some(updater);
+    }
+
+    pub fn clear_instance_updater(&mut self) {
+        self.instance_updater = None;
+    }
+
+    // Instances that fit before the instance buffer has to grow
+    pub fn instance_capacity(&self) -> u32 {
+        self.instance_capacity
+    }
+
+    // Cuts away everything on the negative side of the plane
+    // dot(plane.xyz, position) + plane.w = 0, for looking inside models.
+    // None turns clipping off.
+    pub fn set_clip_plane(&mut self, plane: Option<[f32; 4]>) {
+        self.clip_plane = plane;
+    }
+
+    pub fn clip_plane(&self) -> Option<[f32; 4]> {
+        self.clip_plane
+    }
+
+    // Format of the target the scene is drawn into
+    pub fn scene_format(&self) -> wgpu::TextureFormat {
+        self.hdr.format
+    }
+
+    // The bind group layouts models are drawn with, indexed by group:
+    //
+    //     0: material, diffuse texture and sampler at bindings 0 and 1,
+    //        normal map and sampler at 2 and 3, model::MaterialUniform at 4
+    //     1: camera uniform at binding 0, settings::SettingsUniform at 1,
+    //        settings::GlobalsUniform at 2
+    //     2: light::LightArray at binding 0, point and directional lights.
+    //        A storage buffer, or a uniform where storage isn't supported,
+    //        see light::shader_source
+    //     3: per mesh transform, model::MeshUniform at binding 0
+    //
+    // A user pipeline doesn't have to use every group, but any group it does
+    // use has to match, since the draw code binds all four.
+    pub fn bind_group_layouts(&self) -> [&wgpu::BindGroupLayout; 4] {
+        [
+            &self.texture_bind_group_layout,
+            &self.camera_bind_group_layout,
+            &self.light_bind_group_layout,
+            &self.mesh_bind_group_layout,
+        ]
+    }
+
+    pub fn create_shader(&self, wgsl_source: &str) -> wgpu::ShaderModule {
+        self.context
+            .device
+            .create_shader_module(&wgpu::ShaderModuleDescriptor {
+                label: Some("User Shader"),
+                source: wgpu::ShaderSource::Wgsl(wgsl_source.into()),
+            })
+    }
+
+    // Builds an opaque pipeline around a user shader, rendering into the
+    // scene target with depth. The shader needs vs_main and fs_main entry
+    // points. Models are drawn with the vertex buffer at slot 0 and the
+    // instance buffer at slot 1, so vertex_layouts is usually
+    // [model::ModelVertex::desc(), instance::InstanceRaw::desc()].
+    pub fn create_material_pipeline(
+        &self,
+        shader: &wgpu::ShaderModule,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        vertex_layouts: &[wgpu::VertexBufferLayout],
+    ) -> wgpu::RenderPipeline {
+        let layout =
+            renderer::RenderPipeline::create_pipeline_layout(&self.context.device, bind_group_layouts);
+        renderer::RenderPipeline::from_module(
+            &self.context.device,
+            &layout,
+            self.scene_format(),
+            Some(texture::Texture::DEPTH_FORMAT),
+            vertex_layouts,
+            shader,
+            renderer::PipelineOptions {
+                sample_count: self.anti_aliasing.sample_count(),
+                ..Default::default()
+            },
+        )
+        .render_pipeline
+    }
+
+    // Draws obj_model with a pipeline from create_material_pipeline, or with
+    // the built in ones again when None
+    pub fn set_model_pipeline(&mut self, pipeline: Option<wgpu::RenderPipeline>) {
+        self.model_pipeline = pipeline;
+    }
+
+    // Draws the instance with the debug material while the others keep
+    // their own. Holding space still applies it to everything.
+    pub fn set_instance_debug(&mut self, index: u32, debug: bool) {
+        if debug {
+            self.debug_instances.insert(index);
+        } else {
+            self.debug_instances.remove(&index);
+        }
+    }
+
+    pub fn is_instance_debug(&self, index: u32) -> bool {
+        self.use_debug || self.debug_instances.contains(&index)
+    }
+
+    // Hidden instances are left out when the instance buffer is rebuilt
+    // each update, like culled ones, so they cost no vertex or fragment
+    // work and the instance keeps its index. GPU driven instances can't be
+    // hidden.
+    pub fn set_instance_visible(&mut self, index: u32, visible: bool) {
+        if visible {
+            self.hidden_instances.remove(&index);
+        } else {
+            self.hidden_instances.insert(index);
+        }
+    }
+
+    pub fn is_instance_visible(&self, index: u32) -> bool {
+        !self.hidden_instances.contains(&index)
+    }
+
+    // Flags every other column of the instance grid, or clears the flags if
+    // any are set
+    fn toggle_debug_columns(&mut self) {
+        if !self.debug_instances.is_empty() {
+            self.debug_instances.clear();
+            return;
+        }
+        for i in 0..self.instances.len() as u32 {
+            if i % NUM_INSTANCES_PER_ROW % 2 == 1 {
+                self.debug_instances.insert(i);
+            }
+        }
+    }
+
+    // Loads a material from a diffuse and normal map and assigns it to one
+    // of obj_model's meshes. The mesh's previous material is dropped if
+    // nothing else uses it.
+    pub async fn load_material(
+        &mut self,
+        mesh_index: usize,
+        diffuse_file_name: &str,
+        normal_file_name: &str,
+    ) -> anyhow::Result<()> {
+        if mesh_index >= self.obj_model.meshes.len() {
+            anyhow::bail!("Mesh index {} is out of range", mesh_index);
+        }
+
+        let material = resources::load_material(
+            diffuse_file_name,
+            diffuse_file_name,
+            normal_file_name,
+            texture::AddressModes::default(),
+            &self.context.device,
+            &self.context.queue,
+            &self.texture_bind_group_layout,
+        )
+        .await?;
+        self.obj_model.set_material(mesh_index, material);
+
+        Ok(())
+    }
+
+    // Instance culling results from the last update
+    pub fn cull_stats(&self) -> instance::CullStats {
+        self.cull_stats
+    }
+
+    // Instance buffer slots of the selected instances that weren't culled
+    pub fn selected_slots(&self) -> Vec<u32> {
+        let drawn = (self.num_opaque_instances + self.num_fading_instances) as usize;
+        self.instance_order[..drawn]
+            .iter()
+            .enumerate()
+            .filter(|(_, i)| self.selected.contains(i))
+            .map(|(slot, _)| slot as u32)
+            .collect()
+    }
+
+    pub fn selected(&self) -> &[u32] {
+        &self.selected
+    }
+
+    // Outlines these instances of obj_model, see selection::SelectionOutline.
+    // Indices past the last instance are left out, and an empty slice clears
+    // the selection.
+    pub fn set_selected(&mut self, indices: &[u32]) {
+        let count = self.instances.len() as u32;
+        self.selected = indices.iter().copied().filter(|&i| i < count).collect();
+        self.selected.sort_unstable();
+        self.selected.dedup();
+    }
+
+    // Returns the index of the instance drawn at pixel (x, y), or None if
+    // only the background is there
+    pub fn pick_id_at(&self, x: u32, y: u32) -> Option<u32> {
+        let id = picking::pick(self, x, y)?;
+        self.instance_order.get(id as usize - 1).copied()
+    }
+
+    fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        self.redraw_requested = false;
+        renderer::render(self)
+    }
+
+    // Asks for another frame when the App renders on demand, for anything
+    // changed from outside input, like a moved object or a new model
+    pub fn request_redraw(&mut self) {
+        self.redraw_requested = true;
+    }
+
+    // Whether an on demand App has to draw another frame. Animations like
+    // model_rotation_speed don't count, they only advance on frames that
+    // are drawn anyway.
+    pub fn needs_redraw(&self) -> bool {
+        self.redraw_requested
+            || self.camera_controller.moved()
+            || self.camera_controller.is_active()
+    }
+
+    // Uploads a generated shape with the default primitive material, ready
+    // for add_instanced_model
+    pub fn create_primitive(
+        &self,
+        data: primitives::MeshData,
+        name: &str,
+    ) -> anyhow::Result<model::Model> {
+        data.into_model(
+            &self.context.device,
+            &self.context.queue,
+            &self.texture_bind_group_layout,
+            &self.mesh_bind_group_layout,
+            name,
+        )
+    }
+
+    // Renders a frame offscreen and returns its pixels. Pair with update and
+    // a fixed dt to get the same image every run. Needs an 8 bit surface, an
+    // HDR one has no pixels an RgbaImage can take as is.
+    pub fn render_to_image(&mut self) -> anyhow::Result<image::RgbaImage> {
+        renderer::render_to_image(self)
+    }
+
+    // Renders a new frame and reads it back, like render_to_image, then
+    // writes it to path as a PNG. The frame on screen isn't copied, so
+    // anything changed since it was drawn shows up in the capture.
+    pub fn capture_frame(&mut self, path: &str) -> anyhow::Result<()> {
+        self.render_to_image()?
+            .save_with_format(path, image::ImageFormat::Png)
+            .with_context(|| format!("Failed to write the captured frame to {}", path))
+    }
+}
+
+// The surface configuration resized by a resolution scale, never smaller
+// than a pixel
+fn scaled_config(config: &wgpu::SurfaceConfiguration, scale: f32) -> wgpu::SurfaceConfiguration {
+    let scale = |size: u32| ((size as f32 * scale).round() as u32).max(1);
+    wgpu::SurfaceConfiguration {
+        width: scale(config.width),
+        height: scale(config.height),
+        ..config.clone()
+    }
+}
+
+// The demo as an App with nothing added
+pub async fn run() {
+    app::App::new().with_demo_scene(true).run().await
 }
@@ -1,9 +1,32 @@
-use wgpu::util::DeviceExt;
+use std::borrow::Cow;
+
+use cgmath::{InnerSpace, Vector3};
+
+use crate::{memory, renderer};
+
+// Lights past this many aren't drawn where LightArray has to fall back to a
+// uniform buffer, see LightArray::is_storage_supported
+pub const MAX_LIGHTS: usize = 32;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u32)]
+pub enum LightType {
+    // Shines out from position in every direction
+    Point = 0,
+    // Shines along direction everywhere, like the sun
+    Directional = 1,
+}
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct LightUniform {
+    // Unused by directional lights
     pub position: [f32; 3],
+    // LightType as a u32, which fills the rest of position's 16 bytes
+    light_type: u32,
+    // The way a directional light travels, normalized. Unused by point
+    // lights.
+    direction: [f32; 3],
     // Due to uniforms requiring 16 byte (4 float) spacing, we need to use a padding field here
     _padding: u32,
     pub color: [f32; 3],
@@ -13,35 +36,341 @@ pub struct LightUniform {
 
 impl LightUniform {
     pub fn new() -> Self {
+        Self::new_point([2.0, 2.0, 2.0], [1.0, 1.0, 1.0])
+    }
+
+    pub fn new_point(position: [f32; 3], color: [f32; 3]) -> Self {
+        LightUniform {
+            position,
+            light_type: LightType::Point as u32,
+            direction: [0.0, -1.0, 0.0],
+            _padding: 0,
+            color,
+            _padding2: 0,
+        }
+    }
+
+    // A light along direction, from the light towards the scene
+    pub fn new_directional(direction: [f32; 3], color: [f32; 3]) -> Self {
         LightUniform {
-            position: [2.0, 2.0, 2.0],
+            position: [0.0; 3],
+            light_type: LightType::Directional as u32,
+            direction: Vector3::from(direction).normalize().into(),
             _padding: 0,
-            color: [1.0, 1.0, 1.0],
+            color,
             _padding2: 0,
         }
     }
+
+    pub fn light_type(&self) -> LightType {
+        match self.light_type {
+            1 => LightType::Directional,
+            _ => LightType::Point,
+        }
+    }
+
+    pub fn direction(&self) -> [f32; 3] {
+        self.direction
+    }
 }
 
-pub fn create_buffer_init(device: &wgpu::Device, light_uniform: LightUniform) -> wgpu::Buffer {
-    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-        label: Some("Light VB"),
-        contents: bytemuck::cast_slice(&[light_uniform]),
-        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-    })
+impl Default for LightUniform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// A light infinitely far away, like the sun, lighting everything from the
+// same direction. Goes in the LightArray as a LightType::Directional
+// LightUniform, see State::set_directional_lights.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DirectionalLight {
+    // The way the light travels, from the light towards the scene
+    pub direction: Vector3<f32>,
+    pub color: [f32; 3],
+}
+
+impl From<DirectionalLight> for LightUniform {
+    fn from(light: DirectionalLight) -> Self {
+        LightUniform::new_directional(light.direction.into(), light.color)
+    }
+}
+
+// Sets of directional lights for State::apply_lighting_preset. They are added
+// on top of the point lights rather than replacing them.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LightingPreset {
+    // No directional lights, only the point lights
+    None,
+    // The classic photography setup, with the intensity of each light. The
+    // key light lights the subject from above and to one side of the camera,
+    // the fill softens its shadows from the other side, and the rim light
+    // from behind picks out the edges.
+    ThreePoint { key: f32, fill: f32, rim: f32 },
+}
+
+impl LightingPreset {
+    pub fn three_point() -> Self {
+        LightingPreset::ThreePoint {
+            key: 1.0,
+            fill: 0.4,
+            rim: 0.6,
+        }
+    }
+
+    // The lights for a camera looking along forward
+    pub fn lights(self, forward: Vector3<f32>) -> Vec<DirectionalLight> {
+        match self {
+            LightingPreset::None => Vec::new(),
+            LightingPreset::ThreePoint { key, fill, rim } => {
+                let up = Vector3::unit_y();
+                let right = forward.cross(up).normalize();
+                // Each light points from where it sits, relative to the
+                // subject, back at the subject
+                let light = |from: Vector3<f32>, intensity: f32| DirectionalLight {
+                    direction: -from.normalize(),
+                    color: [intensity; 3],
+                };
+
+                vec![
+                    light(-forward + right + up, key),
+                    light(-forward - right + up * 0.25, fill),
+                    light(forward + up * 0.5, rim),
+                ]
+            }
+        }
+    }
+}
+
+// The lights, in a buffer the shaders loop over. The buffer starts with the
+// count, padded to 16 bytes, followed by the lights. As a storage buffer it
+// grows as lights are pushed, so the bind group has to be recreated from
+// buffer() after push_light. Without storage buffers it's a uniform with room
+// for MAX_LIGHTS and the shaders have to be built from shader_source.
+pub struct LightArray {
+    lights: Vec<LightUniform>,
+    buffer: memory::Tracked<wgpu::Buffer>,
+    storage: bool,
+}
+
+impl LightArray {
+    const HEADER_SIZE: wgpu::BufferAddress = 16;
+    const LIGHT_SIZE: wgpu::BufferAddress = std::mem::size_of::<LightUniform>() as _;
+
+    // The light markers read the lights in the vertex stage. WebGL2 has no
+    // storage buffers at all, and some downlevel adapters none there.
+    pub fn is_storage_supported(adapter: &wgpu::Adapter, device: &wgpu::Device) -> bool {
+        adapter
+            .get_downlevel_properties()
+            .flags
+            .contains(wgpu::DownlevelFlags::VERTEX_STORAGE)
+            && device.limits().max_storage_buffers_per_shader_stage > 0
+    }
+
+    // storage has to match the layout from create_bind_group_layout
+    pub fn new(device: &wgpu::Device, lights: &[LightUniform], storage: bool) -> Self {
+        let capacity = if storage { lights.len() } else { MAX_LIGHTS };
+        Self::with_capacity(device, lights, capacity, storage)
+    }
+
+    // Whether the buffer is a storage buffer or the uniform fallback
+    pub fn is_storage(&self) -> bool {
+        self.storage
+    }
+
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    pub fn lights(&self) -> &[LightUniform] {
+        &self.lights
+    }
+
+    pub fn len(&self) -> usize {
+        self.lights.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+
+    // The lights the shaders see, which leaves out the ones past MAX_LIGHTS
+    // with the uniform fallback
+    pub fn drawn_len(&self) -> usize {
+        self.lights.len().min(self.capacity())
+    }
+
+    pub fn get(&self, index: usize) -> Option<LightUniform> {
+        self.lights.get(index).copied()
+    }
+
+    // Adds a light at the end and returns its index. The uniform fallback
+    // keeps lights past MAX_LIGHTS without drawing them.
+    pub fn push_light(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        light: LightUniform,
+    ) -> usize {
+        self.lights.push(light);
+        if self.lights.len() > self.capacity() {
+            if self.storage {
+                // Doubles, so pushing one by one doesn't reallocate every time
+                *self = Self::with_capacity(device, &self.lights, self.lights.len() * 2, true);
+            } else if self.lights.len() == MAX_LIGHTS + 1 {
+                log::warn!(
+                    "Only {} lights are drawn without storage buffers, the rest are left out",
+                    MAX_LIGHTS
+                );
+            }
+        } else {
+            self.write_light(queue, self.lights.len() - 1);
+            self.write_count(queue);
+        }
+        self.lights.len() - 1
+    }
+
+    // Removes the light at index, moving the ones after it down by one
+    pub fn remove_light(&mut self, queue: &wgpu::Queue, index: usize) -> Option<LightUniform> {
+        if index >= self.lights.len() {
+            return None;
+        }
+        let light = self.lights.remove(index);
+        let end = self.drawn_len();
+        if index < end {
+            queue.write_buffer(
+                &self.buffer,
+                Self::HEADER_SIZE + Self::LIGHT_SIZE * index as u64,
+                bytemuck::cast_slice(&self.lights[index..end]),
+            );
+        }
+        self.write_count(queue);
+        Some(light)
+    }
+
+    // Keeps only the lights keep returns true for, in order
+    pub fn retain(&mut self, queue: &wgpu::Queue, keep: impl FnMut(&LightUniform) -> bool) {
+        let len = self.lights.len();
+        self.lights.retain(keep);
+        if self.lights.len() != len {
+            let end = self.drawn_len();
+            queue.write_buffer(
+                &self.buffer,
+                Self::HEADER_SIZE,
+                bytemuck::cast_slice(&self.lights[..end]),
+            );
+            self.write_count(queue);
+        }
+    }
+
+    // Does nothing past the end
+    pub fn set_light(&mut self, queue: &wgpu::Queue, index: usize, light: LightUniform) {
+        if let Some(slot) = self.lights.get_mut(index) {
+            *slot = light;
+            if index < self.capacity() {
+                self.write_light(queue, index);
+            }
+        }
+    }
+
+    // Lights the buffer has room for
+    fn capacity(&self) -> usize {
+        ((self.buffer.size() - Self::HEADER_SIZE) / Self::LIGHT_SIZE) as usize
+    }
+
+    fn with_capacity(
+        device: &wgpu::Device,
+        lights: &[LightUniform],
+        capacity: usize,
+        storage: bool,
+    ) -> Self {
+        let written = &lights[..lights.len().min(capacity.max(1))];
+        let mut contents = bytemuck::cast_slice(&[written.len() as u32, 0, 0, 0]).to_vec();
+        contents.extend_from_slice(bytemuck::cast_slice(written));
+        // Bindings can't be empty, so there's always room for one light
+        contents.resize(
+            (Self::HEADER_SIZE + Self::LIGHT_SIZE * capacity.max(1) as u64) as usize,
+            0,
+        );
+        let buffer = memory::create_buffer_init(
+            device,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Light Buffer"),
+                contents: &contents,
+                usage: if storage {
+                    wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST
+                } else {
+                    wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST
+                },
+            },
+        );
+        Self {
+            lights: lights.to_vec(),
+            buffer,
+            storage,
+        }
+    }
+
+    fn write_count(&self, queue: &wgpu::Queue) {
+        let count = self.drawn_len() as u32;
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[count]));
+    }
+
+    fn write_light(&self, queue: &wgpu::Queue, index: usize) {
+        queue.write_buffer(
+            &self.buffer,
+            Self::HEADER_SIZE + Self::LIGHT_SIZE * index as u64,
+            bytemuck::cast_slice(&[self.lights[index]]),
+        );
+    }
 }
 
-pub fn create_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+// shader_a.wgsl and light.wgsl declare the lights as a storage buffer. For
+// the uniform fallback the declaration is swapped for a uniform array of
+// MAX_LIGHTS, the define the source has to be preprocessed with, see
+// renderer::preprocess.
+pub fn shader_source(source: &str, storage: bool) -> Cow<'_, str> {
+    if storage {
+        return Cow::Borrowed(source);
+    }
+    Cow::Owned(
+        source
+            .replace(
+                "var<storage, read> lights: Lights;",
+                "var<uniform> lights: Lights;",
+            )
+            .replace("lights: array<Light>;", "lights: array<Light, MAX_LIGHTS>;"),
+    )
+}
+
+// The renderer::ShaderDefines shader_source's output needs
+pub fn shader_defines() -> renderer::ShaderDefines {
+    [("MAX_LIGHTS".to_string(), format!("{}u", MAX_LIGHTS))]
+        .into_iter()
+        .collect()
+}
+
+// storage has to match the LightArray bound to it
+pub fn create_bind_group_layout(device: &wgpu::Device, storage: bool) -> wgpu::BindGroupLayout {
+    let ty = if storage {
+        wgpu::BufferBindingType::Storage { read_only: true }
+    } else {
+        wgpu::BufferBindingType::Uniform
+    };
     device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-        entries: &[wgpu::BindGroupLayoutEntry {
-            binding: 0,
-            visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-            ty: wgpu::BindingType::Buffer {
-                ty: wgpu::BufferBindingType::Uniform,
-                has_dynamic_offset: false,
-                min_binding_size: None,
+        entries: &[
+            // LightArray, read in the vertex stage for the light markers
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
             },
-            count: None,
-        }],
+        ],
         label: None,
     })
 }
@@ -0,0 +1,193 @@
+use std::{
+    ops::Deref,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use wgpu::util::DeviceExt;
+
+// Bytes held by every Tracked buffer and texture alive, across all devices
+static BUFFER_BYTES: AtomicU64 = AtomicU64::new(0);
+static TEXTURE_BYTES: AtomicU64 = AtomicU64::new(0);
+
+// A single frame growing by this much is reported straight away
+const GROWTH_WARNING: u64 = 64 * 1024 * 1024;
+// The engine doesn't allocate in steady state, so growing this many frames
+// in a row is reported too, which catches small per-frame leaks
+const GROWTH_WARNING_FRAMES: u32 = 120;
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct MemoryEstimate {
+    pub buffers: u64,
+    pub textures: u64,
+}
+
+impl MemoryEstimate {
+    pub fn total(&self) -> u64 {
+        self.buffers + self.textures
+    }
+}
+
+// What the Tracked buffers and textures alive right now asked for. Drivers
+// pad and align allocations and keep their own, so this is only a lower
+// bound, and anything made with the device directly isn't counted.
+pub fn estimated() -> MemoryEstimate {
+    MemoryEstimate {
+        buffers: BUFFER_BYTES.load(Ordering::Relaxed),
+        textures: TEXTURE_BYTES.load(Ordering::Relaxed),
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Kind {
+    Buffer,
+    Texture,
+}
+
+impl Kind {
+    fn counter(self) -> &'static AtomicU64 {
+        match self {
+            Kind::Buffer => &BUFFER_BYTES,
+            Kind::Texture => &TEXTURE_BYTES,
+        }
+    }
+}
+
+// A buffer or texture counted in estimated() until it's dropped. Derefs to
+// the wgpu resource, so it can be used in its place.
+#[derive(Debug)]
+pub struct Tracked<T> {
+    resource: T,
+    size: u64,
+    kind: Kind,
+}
+
+impl<T> Tracked<T> {
+    fn new(resource: T, size: u64, kind: Kind) -> Self {
+        kind.counter().fetch_add(size, Ordering::Relaxed);
+        Self {
+            resource,
+            size,
+            kind,
+        }
+    }
+
+    // Bytes counted for this resource
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+impl<T> Deref for Tracked<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.resource
+    }
+}
+
+impl<T> Drop for Tracked<T> {
+    fn drop(&mut self) {
+        self.kind.counter().fetch_sub(self.size, Ordering::Relaxed);
+    }
+}
+
+pub fn create_buffer(
+    device: &wgpu::Device,
+    desc: &wgpu::BufferDescriptor,
+) -> Tracked<wgpu::Buffer> {
+    Tracked::new(device.create_buffer(desc), desc.size, Kind::Buffer)
+}
+
+pub fn create_buffer_init(
+    device: &wgpu::Device,
+    desc: &wgpu::util::BufferInitDescriptor,
+) -> Tracked<wgpu::Buffer> {
+    // create_buffer_init pads the contents to the copy alignment
+    let align = wgpu::COPY_BUFFER_ALIGNMENT;
+    let size = (desc.contents.len() as u64).div_ceil(align) * align;
+    Tracked::new(device.create_buffer_init(desc), size, Kind::Buffer)
+}
+
+pub fn create_texture(
+    device: &wgpu::Device,
+    desc: &wgpu::TextureDescriptor,
+) -> Tracked<wgpu::Texture> {
+    Tracked::new(
+        device.create_texture(desc),
+        texture_size(desc),
+        Kind::Texture,
+    )
+}
+
+// Every mip level of every layer and sample
+fn texture_size(desc: &wgpu::TextureDescriptor) -> u64 {
+    let info = desc.format.describe();
+    let (block_width, block_height) = info.block_dimensions;
+    let (block_width, block_height) = (block_width as u32, block_height as u32);
+    let layers = match desc.dimension {
+        wgpu::TextureDimension::D3 => 1,
+        _ => desc.size.depth_or_array_layers,
+    };
+    (0..desc.mip_level_count)
+        .map(|level| {
+            let size = desc
+                .size
+                .mip_level_size(level, desc.dimension == wgpu::TextureDimension::D3);
+            let blocks_wide = size.width.div_ceil(block_width);
+            let blocks_high = size.height.div_ceil(block_height);
+            let depth = match desc.dimension {
+                wgpu::TextureDimension::D3 => size.depth_or_array_layers,
+                _ => 1,
+            };
+            blocks_wide as u64 * blocks_high as u64 * depth as u64 * info.block_size as u64
+        })
+        .sum::<u64>()
+        * layers as u64
+        * desc.sample_count as u64
+}
+
+// Warns about the estimate growing when nothing should be allocating, see
+// State::estimated_gpu_memory
+#[derive(Debug, Default)]
+pub struct GrowthMonitor {
+    // None until the first frame, which has nothing to compare against
+    last: Option<u64>,
+    growing_frames: u32,
+}
+
+impl GrowthMonitor {
+    // Takes the current total as expected, for after deliberate allocations
+    // like a resize
+    pub fn rebaseline(&mut self, total: u64) {
+        self.last = Some(total);
+        self.growing_frames = 0;
+    }
+
+    // Called once per frame
+    pub fn check(&mut self, total: u64) {
+        let last = match self.last.replace(total) {
+            Some(last) => last,
+            None => return,
+        };
+        let growth = total.saturating_sub(last);
+        if growth >= GROWTH_WARNING {
+            log::warn!(
+                "Estimated GPU memory grew by {} bytes in a frame, to {} bytes",
+                growth,
+                total
+            );
+        }
+        if growth > 0 {
+            self.growing_frames += 1;
+            if self.growing_frames == GROWTH_WARNING_FRAMES {
+                log::warn!(
+                    "Estimated GPU memory grew for {} frames in a row, to {} bytes",
+                    GROWTH_WARNING_FRAMES,
+                    total
+                );
+            }
+        } else {
+            self.growing_frames = 0;
+        }
+    }
+}
@@ -1,6 +1,8 @@
 use std::ops::Range;
 
-use crate::texture;
+use cgmath::{EuclideanSpace, InnerSpace, Matrix, SquareMatrix, Transform};
+
+use crate::{memory, texture};
 
 pub trait Vertex {
     fn desc<'a>() -> wgpu::VertexBufferLayout<'a>;
@@ -12,8 +14,9 @@ pub struct ModelVertex {
     pub position: [f32; 3],
     pub tex_coords: [f32; 2],
     pub normal: [f32; 3],
-    pub tangent: [f32; 3],
-    pub bitangent: [f32; 3],
+    // xyz is the tangent, w the handedness of the tangent space, 1 or -1.
+    // The shaders take the bitangent as cross(normal, tangent.xyz) * w.
+    pub tangent: [f32; 4],
 }
 
 impl Vertex for ModelVertex {
@@ -41,11 +44,52 @@ impl Vertex for ModelVertex {
                 wgpu::VertexAttribute {
                     offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
                     shader_location: 3,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+}
+
+// The w of ModelVertex::tangent for a tangent space with this bitangent, so
+// cross(normal, tangent) * w points the same way. Mirrored UVs give -1.
+pub fn tangent_handedness(
+    normal: cgmath::Vector3<f32>,
+    tangent: cgmath::Vector3<f32>,
+    bitangent: cgmath::Vector3<f32>,
+) -> f32 {
+    use cgmath::InnerSpace;
+    if normal.cross(tangent).dot(bitangent) < 0.0 {
+        -1.0
+    } else {
+        1.0
+    }
+}
+
+// Both ends of a triangle edge, in mesh space, for drawing thick wireframe
+// lines with one instance per edge, see wireframe::Wireframe
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct EdgeInstance {
+    pub start: [f32; 3],
+    pub end: [f32; 3],
+}
+
+impl Vertex for EdgeInstance {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<EdgeInstance>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
                     format: wgpu::VertexFormat::Float32x3,
                 },
                 wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 11]>() as wgpu::BufferAddress,
-                    shader_location: 4,
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
                     format: wgpu::VertexFormat::Float32x3,
                 },
             ],
@@ -53,11 +97,53 @@ impl Vertex for ModelVertex {
     }
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum BlendMode {
+    #[default]
+    Opaque,
+    // Blended with the texture's alpha
+    AlphaBlend,
+    // Adds to what is behind it, for glows
+    Additive,
+    // Darkens what is behind it, for stains and decals
+    Multiply,
+}
+
 pub struct Material {
     pub name: String,
     pub diffuse_texture: texture::Texture,
     pub normal_texture: texture::Texture,
     pub bind_group: wgpu::BindGroup,
+    // Anything other than Opaque is drawn after the opaque geometry without
+    // writing depth
+    pub blend_mode: BlendMode,
+    // Changed through the setters, which also update uniform_buffer
+    uniform: MaterialUniform,
+    uniform_buffer: memory::Tracked<wgpu::Buffer>,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct MaterialUniform {
+    two_sided: u32,
+    shininess: f32,
+    specular_intensity: f32,
+    flip_green: u32,
+    uv_scale: [f32; 2],
+    uv_offset: [f32; 2],
+}
+
+impl Default for MaterialUniform {
+    fn default() -> Self {
+        Self {
+            two_sided: 0,
+            shininess: 32.0,
+            specular_intensity: 1.0,
+            flip_green: 0,
+            uv_scale: [1.0, 1.0],
+            uv_offset: [0.0, 0.0],
+        }
+    }
 }
 
 impl Material {
@@ -68,6 +154,14 @@ impl Material {
         normal_texture: texture::Texture,
         layout: &wgpu::BindGroupLayout,
     ) -> Self {
+        let uniform_buffer = memory::create_buffer_init(
+            device,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("{:?} Material Buffer", name)),
+                contents: bytemuck::cast_slice(&[MaterialUniform::default()]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            },
+        );
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout,
             entries: &[
@@ -87,6 +181,10 @@ impl Material {
                     binding: 3,
                     resource: wgpu::BindingResource::Sampler(&normal_texture.sampler),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
             ],
             label: Some(name),
         });
@@ -96,21 +194,429 @@ impl Material {
             diffuse_texture,
             normal_texture,
             bind_group,
+            blend_mode: BlendMode::default(),
+            uniform: MaterialUniform::default(),
+            uniform_buffer,
+        }
+    }
+
+    pub fn two_sided(&self) -> bool {
+        self.uniform.two_sided != 0
+    }
+
+    // For thin surfaces seen from both sides, like leaves or paper. These
+    // are drawn without back face culling, and the back faces are lit with
+    // the normal flipped towards the camera.
+    pub fn set_two_sided(&mut self, queue: &wgpu::Queue, two_sided: bool) {
+        self.uniform.two_sided = two_sided as u32;
+        self.write_uniform(queue);
+    }
+
+    pub fn shininess(&self) -> f32 {
+        self.uniform.shininess
+    }
+
+    // The Blinn-Phong specular exponent, 32 by default. Low values spread
+    // the highlight out for a matte look, high ones make it small and
+    // glossy. Kept at 1 or above.
+    pub fn set_shininess(&mut self, queue: &wgpu::Queue, shininess: f32) {
+        self.uniform.shininess = shininess.max(1.0);
+        self.write_uniform(queue);
+    }
+
+    pub fn specular_intensity(&self) -> f32 {
+        self.uniform.specular_intensity
+    }
+
+    // Scales the highlight, 0 turns it off
+    pub fn set_specular_intensity(&mut self, queue: &wgpu::Queue, intensity: f32) {
+        self.uniform.specular_intensity = intensity.max(0.0);
+        self.write_uniform(queue);
+    }
+
+    pub fn flip_green(&self) -> bool {
+        self.uniform.flip_green != 0
+    }
+
+    // For DirectX style normal maps, where green points down the texture
+    // instead of up. The bumps of one read with the wrong convention look
+    // pushed in. Off by default, which suits the bundled models and glTF.
+    pub fn set_flip_green(&mut self, queue: &wgpu::Queue, flip_green: bool) {
+        self.uniform.flip_green = flip_green as u32;
+        self.write_uniform(queue);
+    }
+
+    pub fn uv_scale(&self) -> [f32; 2] {
+        self.uniform.uv_scale
+    }
+
+    // Multiplies the mesh's UVs before both textures are sampled, so (4, 4)
+    // tiles them four times across. Tiling needs textures with repeating
+    // address modes, see resources::load_material.
+    pub fn set_uv_scale(&mut self, queue: &wgpu::Queue, scale: [f32; 2]) {
+        self.uniform.uv_scale = scale;
+        self.write_uniform(queue);
+    }
+
+    pub fn uv_offset(&self) -> [f32; 2] {
+        self.uniform.uv_offset
+    }
+
+    // Added to the UVs after uv_scale, in texture widths and heights
+    pub fn set_uv_offset(&mut self, queue: &wgpu::Queue, offset: [f32; 2]) {
+        self.uniform.uv_offset = offset;
+        self.write_uniform(queue);
+    }
+
+    fn write_uniform(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[self.uniform]),
+        );
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MeshUniform {
+    model: [[f32; 4]; 4],
+    // mat3x3 columns are 16 byte aligned in uniforms, so each column is padded to a vec4
+    normal: [[f32; 4]; 3],
+}
+
+impl MeshUniform {
+    pub fn new(transform: cgmath::Matrix4<f32>) -> Self {
+        // Normals need the inverse transpose so non-uniform scale doesn't skew them
+        let linear = cgmath::Matrix3::from_cols(
+            transform.x.truncate(),
+            transform.y.truncate(),
+            transform.z.truncate(),
+        );
+        let normal = linear
+            .invert()
+            .map(|m| m.transpose())
+            .unwrap_or_else(cgmath::Matrix3::identity);
+
+        Self {
+            model: transform.into(),
+            normal: [
+                normal.x.extend(0.0).into(),
+                normal.y.extend(0.0).into(),
+                normal.z.extend(0.0).into(),
+            ],
         }
     }
 }
 
+// Axis aligned box around a mesh's vertices, in the mesh's own space before
+// its transform
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Bounds {
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+}
+
+impl Bounds {
+    // An empty slice gives a box around the origin
+    pub fn from_vertices(vertices: &[ModelVertex]) -> Self {
+        if vertices.is_empty() {
+            return Self {
+                min: [0.0; 3],
+                max: [0.0; 3],
+            };
+        }
+        let (min, max) = vertices
+            .iter()
+            .fold(([f32::MAX; 3], [f32::MIN; 3]), |(min, max), v| {
+                (
+                    [0, 1, 2].map(|i| min[i].min(v.position[i])),
+                    [0, 1, 2].map(|i| max[i].max(v.position[i])),
+                )
+            });
+        Self { min, max }
+    }
+
+    // Length of the longest side
+    pub fn extent(&self) -> f32 {
+        (0..3)
+            .map(|i| self.max[i] - self.min[i])
+            .fold(0.0, f32::max)
+    }
+
+    pub fn corners(&self) -> [cgmath::Point3<f32>; 8] {
+        let mut corners = [cgmath::Point3::origin(); 8];
+        for (i, corner) in corners.iter_mut().enumerate() {
+            let pick = |axis: usize| {
+                if i & (1 << axis) == 0 {
+                    self.min[axis]
+                } else {
+                    self.max[axis]
+                }
+            };
+            *corner = cgmath::Point3::new(pick(0), pick(1), pick(2));
+        }
+        corners
+    }
+}
+
+// A simplified index buffer over the same vertices as the full mesh
+pub struct MeshLod {
+    pub index_buffer: memory::Tracked<wgpu::Buffer>,
+    pub num_elements: u32,
+}
+
 pub struct Mesh {
     pub name: String,
-    pub vertex_buffer: wgpu::Buffer,
-    pub index_buffer: wgpu::Buffer,
+    pub vertex_buffer: memory::Tracked<wgpu::Buffer>,
+    pub num_vertices: u32,
+    // LOD 0, the full detail mesh
+    pub index_buffer: memory::Tracked<wgpu::Buffer>,
     pub num_elements: u32,
+    // LOD 1 and up, each coarser than the last
+    pub lods: Vec<MeshLod>,
+    pub bounds: Bounds,
     pub material: usize,
+    // Local transform applied before the instance transform
+    pub transform: cgmath::Matrix4<f32>,
+    pub transform_buffer: memory::Tracked<wgpu::Buffer>,
+    pub transform_bind_group: wgpu::BindGroup,
+    // Every edge of the full detail triangles once, as EdgeInstances
+    pub edge_buffer: memory::Tracked<wgpu::Buffer>,
+    pub num_edges: u32,
+}
+
+impl Mesh {
+    // Edges shared by two triangles only go in once
+    pub fn create_edge_buffer_init(
+        device: &wgpu::Device,
+        vertices: &[ModelVertex],
+        indices: &[u32],
+    ) -> (memory::Tracked<wgpu::Buffer>, u32) {
+        let mut seen = std::collections::HashSet::new();
+        let mut edges = Vec::new();
+        for triangle in indices.chunks_exact(3) {
+            for (a, b) in [
+                (triangle[0], triangle[1]),
+                (triangle[1], triangle[2]),
+                (triangle[2], triangle[0]),
+            ] {
+                if seen.insert((a.min(b), a.max(b))) {
+                    edges.push(EdgeInstance {
+                        start: vertices[a as usize].position,
+                        end: vertices[b as usize].position,
+                    });
+                }
+            }
+        }
+
+        let buffer = memory::create_buffer_init(
+            device,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Mesh Edge Buffer"),
+                contents: bytemuck::cast_slice(&edges),
+                usage: wgpu::BufferUsages::VERTEX,
+            },
+        );
+        (buffer, edges.len() as u32)
+    }
+
+    pub fn create_transform_buffer_init(
+        device: &wgpu::Device,
+        transform: cgmath::Matrix4<f32>,
+    ) -> memory::Tracked<wgpu::Buffer> {
+        memory::create_buffer_init(
+            device,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Mesh Transform Buffer"),
+                contents: bytemuck::cast_slice(&[MeshUniform::new(transform)]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            },
+        )
+    }
+
+    pub fn create_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("mesh_bind_group_layout"),
+        })
+    }
+
+    pub fn create_bind_group(
+        device: &wgpu::Device,
+        mesh_bind_group_layout: &wgpu::BindGroupLayout,
+        transform_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: mesh_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: transform_buffer.as_entire_binding(),
+            }],
+            label: Some("mesh_bind_group"),
+        })
+    }
+
+    pub fn lod_count(&self) -> usize {
+        1 + self.lods.len()
+    }
+
+    pub fn vertex_count(&self) -> u32 {
+        self.num_vertices
+    }
+
+    // Of the full detail mesh, LOD 0
+    pub fn index_count(&self) -> u32 {
+        self.num_elements
+    }
+
+    // Index buffer and element count for a LOD level, clamped to the
+    // coarsest level available
+    pub fn lod(&self, level: usize) -> (&wgpu::Buffer, u32) {
+        match level.min(self.lods.len()) {
+            0 => (&self.index_buffer, self.num_elements),
+            n => {
+                let lod = &self.lods[n - 1];
+                (&lod.index_buffer, lod.num_elements)
+            }
+        }
+    }
+
+    pub fn set_transform(&mut self, queue: &wgpu::Queue, transform: cgmath::Matrix4<f32>) {
+        self.transform = transform;
+        queue.write_buffer(
+            &self.transform_buffer,
+            0,
+            bytemuck::cast_slice(&[MeshUniform::new(transform)]),
+        );
+    }
 }
 
 pub struct Model {
     pub meshes: Vec<Mesh>,
     pub materials: Vec<Material>,
+    // Camera distance at which each LOD level after 0 starts, ascending
+    pub lod_distances: Vec<f32>,
+}
+
+impl Model {
+    pub fn lod_count(&self) -> usize {
+        self.meshes.iter().map(Mesh::lod_count).max().unwrap_or(1)
+    }
+
+    pub fn mesh_count(&self) -> usize {
+        self.meshes.len()
+    }
+
+    // Summed over the meshes from the counts stored when they were built, so
+    // nothing is read back from the GPU
+    pub fn vertex_count(&self) -> u32 {
+        self.meshes.iter().map(Mesh::vertex_count).sum()
+    }
+
+    pub fn index_count(&self) -> u32 {
+        self.meshes.iter().map(Mesh::index_count).sum()
+    }
+
+    // Radius of a sphere about the model's origin that encloses every mesh
+    // with its transform applied, for culling the model's instances. The
+    // meshes' bounds are stored when they're built, so nothing is read back.
+    pub fn bounding_radius(&self) -> f32 {
+        self.meshes
+            .iter()
+            .flat_map(|mesh| {
+                mesh.bounds
+                    .corners()
+                    .map(|corner| mesh.transform.transform_point(corner))
+            })
+            .map(|corner| corner.to_vec().magnitude())
+            .fold(0.0, f32::max)
+    }
+
+    // Picks the LOD level for something at the given distance from the camera
+    pub fn lod_for_distance(&self, distance: f32) -> usize {
+        let level = self
+            .lod_distances
+            .iter()
+            .take_while(|&&d| distance >= d)
+            .count();
+        level.min(self.lod_count() - 1)
+    }
+
+    // Makes the mesh draw with the given material. If no other mesh shares
+    // the old material it is replaced in place, which drops its textures and
+    // bind group. Returns the material's new index in materials.
+    pub fn set_material(&mut self, mesh_index: usize, material: Material) -> Option<usize> {
+        let old = self.meshes.get(mesh_index)?.material;
+        let shared = self
+            .meshes
+            .iter()
+            .enumerate()
+            .any(|(i, m)| i != mesh_index && m.material == old);
+
+        let index = if shared || old >= self.materials.len() {
+            self.materials.push(material);
+            self.materials.len() - 1
+        } else {
+            self.materials[old] = material;
+            old
+        };
+        self.meshes[mesh_index].material = index;
+
+        Some(index)
+    }
+}
+
+// A model drawn with an instance buffer of its own, next to obj_model. These
+// skip the distance fade and LODs and are always drawn opaque.
+pub struct InstancedModel {
+    // What State::add_model was given, empty for add_instanced_model
+    pub name: String,
+    pub model: Model,
+    pub instance_buffer: memory::Tracked<wgpu::Buffer>,
+    pub instance_count: u32,
+}
+
+// A model drawn once, without instances, from the transform in its meshes'
+// uniforms. See State::add_static_model.
+pub struct StaticModel {
+    pub model: Model,
+    transform: cgmath::Matrix4<f32>,
+    // The meshes' own transforms, which transform goes on top of
+    local_transforms: Vec<cgmath::Matrix4<f32>>,
+}
+
+impl StaticModel {
+    pub fn new(queue: &wgpu::Queue, model: Model, transform: cgmath::Matrix4<f32>) -> Self {
+        let local_transforms = model.meshes.iter().map(|mesh| mesh.transform).collect();
+        let mut static_model = Self {
+            model,
+            transform,
+            local_transforms,
+        };
+        static_model.set_transform(queue, transform);
+        static_model
+    }
+
+    pub fn transform(&self) -> cgmath::Matrix4<f32> {
+        self.transform
+    }
+
+    pub fn set_transform(&mut self, queue: &wgpu::Queue, transform: cgmath::Matrix4<f32>) {
+        self.transform = transform;
+        for (mesh, local) in self.model.meshes.iter_mut().zip(&self.local_transforms) {
+            mesh.set_transform(queue, transform * local);
+        }
+    }
 }
 
 pub trait DrawModel<'a> {
@@ -129,6 +635,15 @@ pub trait DrawModel<'a> {
         camera_bind_group: &'a wgpu::BindGroup,
         light_bind_group: &'a wgpu::BindGroup,
     );
+    fn draw_mesh_instanced_lod(
+        &mut self,
+        mesh: &'a Mesh,
+        material: &'a Material,
+        lod: usize,
+        instances: Range<u32>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
 
     fn draw_model(
         &mut self,
@@ -176,12 +691,33 @@ where
         camera_bind_group: &'b wgpu::BindGroup,
         light_bind_group: &'b wgpu::BindGroup,
     ) {
+        self.draw_mesh_instanced_lod(
+            mesh,
+            material,
+            0,
+            instances,
+            camera_bind_group,
+            light_bind_group,
+        );
+    }
+
+    fn draw_mesh_instanced_lod(
+        &mut self,
+        mesh: &'b Mesh,
+        material: &'b Material,
+        lod: usize,
+        instances: Range<u32>,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+    ) {
+        let (index_buffer, num_elements) = mesh.lod(lod);
         self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
-        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        self.set_index_buffer(index_buffer.slice(..), wgpu::IndexFormat::Uint32);
         self.set_bind_group(0, &material.bind_group, &[]);
         self.set_bind_group(1, camera_bind_group, &[]);
         self.set_bind_group(2, light_bind_group, &[]);
-        self.draw_indexed(0..mesh.num_elements, 0, instances);
+        self.set_bind_group(3, &mesh.transform_bind_group, &[]);
+        self.draw_indexed(0..num_elements, 0, instances);
     }
 
     fn draw_model(
@@ -314,3 +850,104 @@ where
         }
     }
 }
+
+// The shapes from crate::primitives as Models drawn with a supplied
+// material, for when the MeshData in between isn't needed. mesh_layout and
+// material are as for primitives::MeshData::into_model_with_material.
+pub mod primitives {
+    use super::{Material, Model};
+    use crate::primitives as shapes;
+
+    // See shapes::plane
+    pub fn plane(
+        device: &wgpu::Device,
+        mesh_layout: &wgpu::BindGroupLayout,
+        size: f32,
+        subdivisions: u32,
+        material: Material,
+    ) -> Model {
+        shapes::plane(size, subdivisions).into_model_with_material(
+            device,
+            mesh_layout,
+            "Plane",
+            material,
+        )
+    }
+
+    // See shapes::uv_sphere
+    pub fn uv_sphere(
+        device: &wgpu::Device,
+        mesh_layout: &wgpu::BindGroupLayout,
+        radius: f32,
+        sectors: u32,
+        stacks: u32,
+        material: Material,
+    ) -> Model {
+        shapes::uv_sphere(radius, sectors, stacks).into_model_with_material(
+            device,
+            mesh_layout,
+            "UV Sphere",
+            material,
+        )
+    }
+
+    // See shapes::cube
+    pub fn cube(
+        device: &wgpu::Device,
+        mesh_layout: &wgpu::BindGroupLayout,
+        size: f32,
+        material: Material,
+    ) -> Model {
+        shapes::cube(size).into_model_with_material(device, mesh_layout, "Cube", material)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Matrix3, Matrix4, Vector3, Vector4};
+
+    use super::*;
+
+    // Where shader_a.wgsl puts a vertex of a mesh drawn with instance
+    fn world_position(
+        instance: Matrix4<f32>,
+        mesh: &MeshUniform,
+        position: Vector3<f32>,
+    ) -> Vector4<f32> {
+        instance * Matrix4::from(mesh.model) * position.extend(1.0)
+    }
+
+    #[test]
+    fn meshes_keep_their_own_transforms() {
+        // A gun whose slide is pulled back along -Z while the frame stays put
+        let frame = MeshUniform::new(Matrix4::identity());
+        let slide = MeshUniform::new(Matrix4::from_translation(Vector3::new(0.0, 0.0, -0.5)));
+        let instance = Matrix4::from_translation(Vector3::new(10.0, 0.0, 0.0));
+
+        let position = Vector3::new(0.0, 1.0, 0.0);
+        assert_eq!(
+            world_position(instance, &frame, position),
+            Vector4::new(10.0, 1.0, 0.0, 1.0)
+        );
+        assert_eq!(
+            world_position(instance, &slide, position),
+            Vector4::new(10.0, 1.0, -0.5, 1.0)
+        );
+    }
+
+    #[test]
+    fn mesh_normals_stay_perpendicular_under_non_uniform_scale() {
+        let transform = Matrix4::from_nonuniform_scale(4.0, 1.0, 1.0);
+        let mesh = MeshUniform::new(transform);
+        let normal_matrix = Matrix3::from_cols(
+            Vector4::from(mesh.normal[0]).truncate(),
+            Vector4::from(mesh.normal[1]).truncate(),
+            Vector4::from(mesh.normal[2]).truncate(),
+        );
+
+        // A face along the diagonal of the XY plane
+        let tangent = (transform * Vector4::new(1.0, 1.0, 0.0, 0.0)).truncate();
+        let normal = normal_matrix * Vector3::new(1.0, -1.0, 0.0);
+        assert!(tangent.dot(normal).abs() < 1e-6);
+    }
+}
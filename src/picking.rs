@@ -0,0 +1,205 @@
+use std::num::NonZeroU32;
+
+use crate::{
+    instance, memory,
+    model::{self, DrawModel, Vertex},
+    renderer, settings, texture, State,
+};
+
+pub const ID_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Uint;
+
+// Renders instance ids into an integer target so a single pixel can be
+// read back to find what is under the cursor
+pub struct Picker {
+    pub render_pipeline: wgpu::RenderPipeline,
+    pub texture: memory::Tracked<wgpu::Texture>,
+    pub view: wgpu::TextureView,
+    pub depth_texture: texture::Texture,
+    pub readback_buffer: memory::Tracked<wgpu::Buffer>,
+}
+
+impl Picker {
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        layout: &wgpu::PipelineLayout,
+    ) -> Self {
+        let shader = wgpu::ShaderModuleDescriptor {
+            label: Some("Picking Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                settings::shader_source(include_str!("shaders/picking.wgsl")).into(),
+            ),
+        };
+
+        let render_pipeline = renderer::RenderPipeline::new(
+            device,
+            layout,
+            ID_FORMAT,
+            Some(texture::Texture::DEPTH_FORMAT),
+            &[model::ModelVertex::desc(), instance::InstanceRaw::desc()],
+            shader,
+            renderer::PipelineOptions {
+                // Integer targets can't be blended
+                blend: None,
+                // The depth test already hides back faces, and this way
+                // mirrored instances don't need a pipeline of their own
+                cull_mode: None,
+                ..Default::default()
+            },
+        )
+        .render_pipeline;
+
+        let (texture, view) = Self::create_id_texture(device, config);
+        let depth_texture =
+            texture::Texture::create_depth_texture(device, config, "picking_depth_texture");
+
+        // Texture to buffer copies need rows padded to COPY_BYTES_PER_ROW_ALIGNMENT,
+        // even though we only ever read a single pixel
+        let readback_buffer = memory::create_buffer(
+            device,
+            &wgpu::BufferDescriptor {
+                label: Some("Picking Readback Buffer"),
+                size: wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            },
+        );
+
+        Self {
+            render_pipeline,
+            texture,
+            view,
+            depth_texture,
+            readback_buffer,
+        }
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        let (texture, view) = Self::create_id_texture(device, config);
+        self.texture = texture;
+        self.view = view;
+        self.depth_texture =
+            texture::Texture::create_depth_texture(device, config, "picking_depth_texture");
+    }
+
+    fn create_id_texture(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> (memory::Tracked<wgpu::Texture>, wgpu::TextureView) {
+        let texture = memory::create_texture(
+            device,
+            &wgpu::TextureDescriptor {
+                label: Some("picking_texture"),
+                size: wgpu::Extent3d {
+                    width: config.width,
+                    height: config.height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: ID_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            },
+        );
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        (texture, view)
+    }
+}
+
+// Renders the id pass and returns the id of the pixel at (x, y), where ids
+// are the instance's slot in the instance buffer plus one. Returns None for
+// the background or when (x, y) is outside the surface.
+pub fn pick(state: &State, x: u32, y: u32) -> Option<u32> {
+    let config = &state.context.config;
+    if x >= config.width || y >= config.height {
+        return None;
+    }
+
+    let picker = &state.picker;
+    let mut encoder =
+        state
+            .context
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Picking Encoder"),
+            });
+
+    {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Picking Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: &picker.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &picker.depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+
+        // The id texture is window sized, so (x, y) only lines up with the
+        // scene when it's letterboxed the same way
+        state.viewport().apply(&mut render_pass);
+        render_pass.set_vertex_buffer(1, state.instance_buffer.slice(..));
+        render_pass.set_pipeline(&picker.render_pipeline);
+        render_pass.draw_model_instanced(
+            &state.obj_model,
+            0..state.num_opaque_instances + state.num_fading_instances,
+            &state.camera_bind_group,
+            &state.light_bind_group,
+        );
+    }
+
+    encoder.copy_texture_to_buffer(
+        wgpu::ImageCopyTexture {
+            texture: &picker.texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d { x, y, z: 0 },
+            aspect: wgpu::TextureAspect::All,
+        },
+        wgpu::ImageCopyBuffer {
+            buffer: &picker.readback_buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT),
+                rows_per_image: None,
+            },
+        },
+        wgpu::Extent3d {
+            width: 1,
+            height: 1,
+            depth_or_array_layers: 1,
+        },
+    );
+
+    state
+        .context
+        .queue
+        .submit(std::iter::once(encoder.finish()));
+
+    let buffer_slice = picker.readback_buffer.slice(..);
+    let mapping = buffer_slice.map_async(wgpu::MapMode::Read);
+    state.context.device.poll(wgpu::Maintain::Wait);
+    if let Err(e) = pollster::block_on(mapping) {
+        log::warn!("Failed to read back picking buffer: {:?}", e);
+        return None;
+    }
+
+    let id = {
+        let data = buffer_slice.get_mapped_range();
+        u32::from_ne_bytes([data[0], data[1], data[2], data[3]])
+    };
+    picker.readback_buffer.unmap();
+
+    (id != 0).then_some(id)
+}
@@ -0,0 +1,502 @@
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+use cgmath::{InnerSpace, SquareMatrix, Vector3};
+
+use crate::model::{self, ModelVertex};
+use crate::{memory, texture};
+
+// Vertices and indices of a generated shape, ready to be uploaded with
+// into_model. Texture coordinates follow wgpu, with v pointing down, and the
+// tangent handedness matches what resources::load_model computes.
+pub struct MeshData {
+    pub vertices: Vec<ModelVertex>,
+    pub indices: Vec<u32>,
+}
+
+impl MeshData {
+    fn new() -> Self {
+        Self {
+            vertices: Vec::new(),
+            indices: Vec::new(),
+        }
+    }
+
+    fn push_vertex(
+        &mut self,
+        position: Vector3<f32>,
+        tex_coords: [f32; 2],
+        normal: Vector3<f32>,
+        tangent: Vector3<f32>,
+        bitangent: Vector3<f32>,
+    ) -> u32 {
+        let handedness = model::tangent_handedness(normal, tangent, bitangent);
+        self.vertices.push(ModelVertex {
+            position: position.into(),
+            tex_coords,
+            normal: normal.into(),
+            tangent: tangent.extend(handedness).into(),
+        });
+        self.vertices.len() as u32 - 1
+    }
+
+    // Adds a triangle wound counter clockwise when seen from the side its
+    // normals point to, so back face culling keeps the outside
+    fn push_triangle(&mut self, a: u32, b: u32, c: u32) {
+        let [pa, pb, pc] = [a, b, c].map(|i| Vector3::from(self.vertices[i as usize].position));
+        let face = (pb - pa).cross(pc - pa);
+        if face.magnitude2() == 0.0 {
+            return;
+        }
+        let normal = [a, b, c]
+            .iter()
+            .map(|&i| Vector3::from(self.vertices[i as usize].normal))
+            .fold(Vector3::new(0.0, 0.0, 0.0), |sum, n| sum + n);
+        if face.dot(normal) >= 0.0 {
+            self.indices.extend([a, b, c]);
+        } else {
+            self.indices.extend([a, c, b]);
+        }
+    }
+
+    // A flat square grid centred on center, spanning half_size along the
+    // tangent and bitangent axes
+    fn push_face(
+        &mut self,
+        center: Vector3<f32>,
+        normal: Vector3<f32>,
+        tangent: Vector3<f32>,
+        bitangent: Vector3<f32>,
+        half_size: f32,
+        subdivisions: u32,
+    ) {
+        let cells = subdivisions + 1;
+        let first = self.vertices.len() as u32;
+        for row in 0..=cells {
+            for column in 0..=cells {
+                let u = column as f32 / cells as f32;
+                let v = row as f32 / cells as f32;
+                let position = center
+                    + tangent * (u * 2.0 - 1.0) * half_size
+                    + bitangent * (1.0 - v * 2.0) * half_size;
+                self.push_vertex(position, [u, v], normal, tangent, bitangent);
+            }
+        }
+        for row in 0..cells {
+            for column in 0..cells {
+                let top_left = first + row * (cells + 1) + column;
+                let bottom_left = top_left + cells + 1;
+                self.push_triangle(bottom_left, bottom_left + 1, top_left + 1);
+                self.push_triangle(bottom_left, top_left + 1, top_left);
+            }
+        }
+    }
+
+    // Uploads the data as a single mesh model with a plain white material
+    pub fn into_model(
+        self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+        mesh_layout: &wgpu::BindGroupLayout,
+        name: &str,
+    ) -> anyhow::Result<model::Model> {
+        let material = default_material(device, queue, layout)?;
+        Ok(self.into_model_with_material(device, mesh_layout, name, material))
+    }
+
+    // Like into_model, drawn with material instead. The material has to be
+    // made with the texture bind group layout the model is drawn with, see
+    // State::bind_group_layouts.
+    pub fn into_model_with_material(
+        self,
+        device: &wgpu::Device,
+        mesh_layout: &wgpu::BindGroupLayout,
+        name: &str,
+        material: model::Material,
+    ) -> model::Model {
+        let vertex_buffer = memory::create_buffer_init(
+            device,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("{:?} Vertex Buffer", name)),
+                contents: bytemuck::cast_slice(&self.vertices),
+                usage: wgpu::BufferUsages::VERTEX,
+            },
+        );
+        let index_buffer = memory::create_buffer_init(
+            device,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("{:?} Index Buffer", name)),
+                contents: bytemuck::cast_slice(&self.indices),
+                usage: wgpu::BufferUsages::INDEX,
+            },
+        );
+
+        let transform = cgmath::Matrix4::identity();
+        let transform_buffer = model::Mesh::create_transform_buffer_init(device, transform);
+        let transform_bind_group =
+            model::Mesh::create_bind_group(device, mesh_layout, &transform_buffer);
+        let (edge_buffer, num_edges) =
+            model::Mesh::create_edge_buffer_init(device, &self.vertices, &self.indices);
+
+        let mesh = model::Mesh {
+            name: name.to_string(),
+            vertex_buffer,
+            num_vertices: self.vertices.len() as u32,
+            index_buffer,
+            num_elements: self.indices.len() as u32,
+            lods: Vec::new(),
+            bounds: model::Bounds::from_vertices(&self.vertices),
+            material: 0,
+            transform,
+            transform_buffer,
+            transform_bind_group,
+            edge_buffer,
+            num_edges,
+        };
+
+        model::Model {
+            meshes: vec![mesh],
+            materials: vec![material],
+            lod_distances: Vec::new(),
+        }
+    }
+}
+
+// White diffuse and a flat normal map, so the shape is only lit
+pub fn default_material(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+) -> anyhow::Result<model::Material> {
+    let pixel = |rgba: [u8; 4]| {
+        image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba(rgba)))
+    };
+    let diffuse_texture = texture::Texture::from_image(
+        device,
+        queue,
+        &pixel([255, 255, 255, 255]),
+        Some("primitive_diffuse"),
+        false,
+        texture::AlphaMode::Straight,
+        texture::AddressModes::default(),
+    )?;
+    let normal_texture = texture::Texture::from_image(
+        device,
+        queue,
+        &pixel([128, 128, 255, 255]),
+        Some("primitive_normal"),
+        true,
+        texture::AlphaMode::Straight,
+        texture::AddressModes::default(),
+    )?;
+
+    Ok(model::Material::new(
+        device,
+        "primitive",
+        diffuse_texture,
+        normal_texture,
+        layout,
+    ))
+}
+
+// An axis aligned cube centred on the origin, each face mapped to the whole
+// texture
+pub fn cube(size: f32) -> MeshData {
+    let half = size * 0.5;
+    let x = Vector3::unit_x();
+    let y = Vector3::unit_y();
+    let z = Vector3::unit_z();
+    // Normal, tangent and bitangent of each face
+    let faces = [
+        (x, -z, y),
+        (-x, z, y),
+        (y, x, -z),
+        (-y, x, z),
+        (z, x, y),
+        (-z, -x, y),
+    ];
+
+    let mut data = MeshData::new();
+    for (normal, tangent, bitangent) in faces {
+        data.push_face(normal * half, normal, tangent, bitangent, half, 0);
+    }
+    data
+}
+
+// A square in the XZ plane facing +Y, split into (subdivisions + 1)^2 cells
+pub fn plane(size: f32, subdivisions: u32) -> MeshData {
+    let mut data = MeshData::new();
+    data.push_face(
+        Vector3::new(0.0, 0.0, 0.0),
+        Vector3::unit_y(),
+        Vector3::unit_x(),
+        -Vector3::unit_z(),
+        size * 0.5,
+        subdivisions,
+    );
+    data
+}
+
+// Point on the unit sphere for a polar angle from +Y and an azimuth about Y
+fn sphere_direction(polar: f32, azimuth: f32) -> Vector3<f32> {
+    Vector3::new(
+        polar.sin() * azimuth.cos(),
+        polar.cos(),
+        -polar.sin() * azimuth.sin(),
+    )
+}
+
+// Direction u grows in on the sphere, where u follows the azimuth
+fn sphere_tangent(azimuth: f32) -> Vector3<f32> {
+    Vector3::new(-azimuth.sin(), 0.0, -azimuth.cos())
+}
+
+// A UV sphere with segments slices around Y and half as many stacks
+pub fn sphere(radius: f32, segments: u32) -> MeshData {
+    uv_sphere(radius, segments, segments / 2)
+}
+
+pub fn uv_sphere(radius: f32, sectors: u32, stacks: u32) -> MeshData {
+    let sectors = sectors.max(3);
+    let stacks = stacks.max(2);
+
+    let mut data = MeshData::new();
+    for stack in 0..=stacks {
+        let v = stack as f32 / stacks as f32;
+        let polar = v * PI;
+        for sector in 0..=sectors {
+            let u = sector as f32 / sectors as f32;
+            let azimuth = u * 2.0 * PI;
+            let normal = sphere_direction(polar, azimuth);
+            let tangent = sphere_tangent(azimuth);
+            data.push_vertex(
+                normal * radius,
+                [u, v],
+                normal,
+                tangent,
+                normal.cross(tangent),
+            );
+        }
+    }
+    for stack in 0..stacks {
+        for sector in 0..sectors {
+            let top_left = stack * (sectors + 1) + sector;
+            let bottom_left = top_left + sectors + 1;
+            // The triangles that collapse at the poles are dropped. Their
+            // corners only nearly meet at the south pole, since sin(PI) isn't
+            // quite 0, so push_triangle can't be left to catch them.
+            if stack + 1 < stacks {
+                data.push_triangle(bottom_left, bottom_left + 1, top_left + 1);
+            }
+            if stack > 0 {
+                data.push_triangle(bottom_left, top_left + 1, top_left);
+            }
+        }
+    }
+    data
+}
+
+// A subdivided icosahedron, which spreads its triangles more evenly than a
+// UV sphere. Each subdivision splits every triangle in four.
+pub fn icosphere(radius: f32, subdivisions: u32) -> MeshData {
+    let t = (1.0 + 5.0_f32.sqrt()) * 0.5;
+    let mut positions = [
+        [-1.0, t, 0.0],
+        [1.0, t, 0.0],
+        [-1.0, -t, 0.0],
+        [1.0, -t, 0.0],
+        [0.0, -1.0, t],
+        [0.0, 1.0, t],
+        [0.0, -1.0, -t],
+        [0.0, 1.0, -t],
+        [t, 0.0, -1.0],
+        [t, 0.0, 1.0],
+        [-t, 0.0, -1.0],
+        [-t, 0.0, 1.0],
+    ]
+    .map(|p| Vector3::from(p).normalize())
+    .to_vec();
+    let mut triangles: Vec<[usize; 3]> = vec![
+        [0, 11, 5],
+        [0, 5, 1],
+        [0, 1, 7],
+        [0, 7, 10],
+        [0, 10, 11],
+        [1, 5, 9],
+        [5, 11, 4],
+        [11, 10, 2],
+        [10, 7, 6],
+        [7, 1, 8],
+        [3, 9, 4],
+        [3, 4, 2],
+        [3, 2, 6],
+        [3, 6, 8],
+        [3, 8, 9],
+        [4, 9, 5],
+        [2, 4, 11],
+        [6, 2, 10],
+        [8, 6, 7],
+        [9, 8, 1],
+    ];
+
+    for _ in 0..subdivisions {
+        let mut midpoints = HashMap::new();
+        let mut midpoint = |a: usize, b: usize| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                positions.push((positions[a] + positions[b]).normalize());
+                positions.len() - 1
+            })
+        };
+        triangles = triangles
+            .into_iter()
+            .flat_map(|[a, b, c]| {
+                let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+                [[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+            })
+            .collect();
+    }
+
+    // Vertices aren't shared between triangles, so the ones crossing the
+    // texture seam can wrap u instead of smearing the whole texture
+    let mut data = MeshData::new();
+    for triangle in triangles {
+        let corners = triangle.map(|i| {
+            let normal = positions[i];
+            let azimuth = (-normal.z).atan2(normal.x);
+            let u = (azimuth / (2.0 * PI)).rem_euclid(1.0);
+            let v = normal.y.clamp(-1.0, 1.0).acos() / PI;
+            (normal, azimuth, u, v)
+        });
+        let mut us = corners.map(|(_, _, u, _)| u);
+        let max_u = us.iter().cloned().fold(f32::MIN, f32::max);
+        for u in &mut us {
+            if max_u - *u > 0.5 {
+                *u += 1.0;
+            }
+        }
+        // The azimuth is undefined at the poles, so they take the u of the
+        // rest of the triangle
+        let poles = corners.map(|(n, ..)| n.x.abs() < 1e-6 && n.z.abs() < 1e-6);
+        let others = (0..3).filter(|&i| !poles[i]).collect::<Vec<_>>();
+        if others.len() < 3 && !others.is_empty() {
+            let average = others.iter().map(|&i| us[i]).sum::<f32>() / others.len() as f32;
+            for i in 0..3 {
+                if poles[i] {
+                    us[i] = average;
+                }
+            }
+        }
+
+        let indices = [0, 1, 2].map(|i| {
+            let (normal, mut azimuth, _, v) = corners[i];
+            if poles[i] {
+                azimuth = us[i] * 2.0 * PI;
+            }
+            let tangent = sphere_tangent(azimuth);
+            data.push_vertex(
+                normal * radius,
+                [us[i], v],
+                normal,
+                tangent,
+                normal.cross(tangent),
+            )
+        });
+        data.push_triangle(indices[0], indices[1], indices[2]);
+    }
+    data
+}
+
+// A capped cylinder along Y centred on the origin
+pub fn cylinder(radius: f32, height: f32, segments: u32) -> MeshData {
+    let segments = segments.max(3);
+    let half_height = height * 0.5;
+    let up = Vector3::unit_y();
+
+    let mut data = MeshData::new();
+    let side = data.vertices.len() as u32;
+    for segment in 0..=segments {
+        let u = segment as f32 / segments as f32;
+        let azimuth = u * 2.0 * PI;
+        let normal = sphere_direction(PI * 0.5, azimuth);
+        let tangent = sphere_tangent(azimuth);
+        for (v, y) in [(0.0, half_height), (1.0, -half_height)] {
+            data.push_vertex(normal * radius + up * y, [u, v], normal, tangent, up);
+        }
+    }
+    for segment in 0..segments {
+        let top = side + segment * 2;
+        let bottom = top + 1;
+        data.push_triangle(bottom, bottom + 2, top + 2);
+        data.push_triangle(bottom, top + 2, top);
+    }
+
+    // The caps use a top down projection of the texture
+    for (normal, bitangent) in [(up, -Vector3::unit_z()), (-up, Vector3::unit_z())] {
+        let tangent = Vector3::unit_x();
+        let center = data.push_vertex(normal * half_height, [0.5, 0.5], normal, tangent, bitangent);
+        for segment in 0..=segments {
+            let azimuth = segment as f32 / segments as f32 * 2.0 * PI;
+            let offset = sphere_direction(PI * 0.5, azimuth);
+            let tex_coords = [
+                0.5 + offset.dot(tangent) * 0.5,
+                0.5 - offset.dot(bitangent) * 0.5,
+            ];
+            data.push_vertex(
+                normal * half_height + offset * radius,
+                tex_coords,
+                normal,
+                tangent,
+                bitangent,
+            );
+        }
+        for segment in 0..segments {
+            data.push_triangle(center, center + 1 + segment, center + 2 + segment);
+        }
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_counts(data: &MeshData, vertices: usize, triangles: usize) {
+        assert_eq!(data.vertices.len(), vertices);
+        assert_eq!(data.indices.len(), triangles * 3);
+        assert!(data.indices.iter().all(|&i| (i as usize) < vertices));
+    }
+
+    #[test]
+    fn plane_counts() {
+        assert_counts(&plane(1.0, 0), 4, 2);
+        assert_counts(&plane(1.0, 2), 16, 18);
+    }
+
+    #[test]
+    fn cube_counts() {
+        assert_counts(&cube(1.0), 24, 12);
+    }
+
+    #[test]
+    fn uv_sphere_counts() {
+        // Each stack has a ring of sectors + 1 vertices, and the quads
+        // touching the poles lose the triangle that collapses there
+        assert_counts(&uv_sphere(1.0, 3, 2), 12, 6);
+        assert_counts(&uv_sphere(1.0, 8, 4), 45, 48);
+        // Too few sectors and stacks are raised to the minimum
+        assert_counts(&uv_sphere(1.0, 0, 0), 12, 6);
+    }
+
+    #[test]
+    fn icosphere_counts() {
+        assert_counts(&icosphere(1.0, 0), 60, 20);
+        assert_counts(&icosphere(1.0, 2), 960, 320);
+    }
+
+    #[test]
+    fn cylinder_counts() {
+        // Two side vertices per segment edge, and a centre and rim per cap
+        assert_counts(&cylinder(1.0, 2.0, 8), 18 + 2 * 10, 32);
+    }
+}
@@ -0,0 +1,158 @@
+use std::time::Duration;
+
+use crate::memory;
+
+// Timestamps written each frame, see renderer::render
+pub const LIGHT_PASS_START: u32 = 0;
+pub const MODEL_PASS_START: u32 = 1;
+pub const MODEL_PASS_END: u32 = 2;
+const QUERY_COUNT: u32 = 3;
+const QUERY_SIZE: wgpu::BufferAddress = std::mem::size_of::<u64>() as wgpu::BufferAddress;
+// Query resolves have to land on a 256 byte boundary, so the pipeline
+// statistics go after the timestamps at the next one
+const STATISTICS_OFFSET: wgpu::BufferAddress = wgpu::QUERY_RESOLVE_BUFFER_ALIGNMENT;
+const READBACK_SIZE: wgpu::BufferAddress = STATISTICS_OFFSET + QUERY_SIZE;
+
+// GPU time spent in each pass of the last frame. Everything is zero when
+// the adapter doesn't support timestamp queries.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct FrameStats {
+    pub light_pass: Duration,
+    pub model_pass: Duration,
+    // Fragment shader invocations in the main scene pass, which is the
+    // overdraw the opaque sort order is there to cut down. Zero when the
+    // adapter doesn't support pipeline statistics queries.
+    pub model_pass_fragments: u64,
+}
+
+pub struct GpuTimer {
+    query_set: wgpu::QuerySet,
+    // None without PIPELINE_STATISTICS_QUERY
+    statistics_query_set: Option<wgpu::QuerySet>,
+    resolve_buffer: memory::Tracked<wgpu::Buffer>,
+    readback_buffer: memory::Tracked<wgpu::Buffer>,
+    // Nanoseconds per timestamp tick
+    period: f32,
+}
+
+impl GpuTimer {
+    // Returns None unless the device was created with TIMESTAMP_QUERY
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Option<Self> {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            log::info!("Timestamp queries aren't supported, GPU timings will read as zero");
+            return None;
+        }
+
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("Timestamp Query Set"),
+            ty: wgpu::QueryType::Timestamp,
+            count: QUERY_COUNT,
+        });
+        let statistics_query_set = device
+            .features()
+            .contains(wgpu::Features::PIPELINE_STATISTICS_QUERY)
+            .then(|| {
+                device.create_query_set(&wgpu::QuerySetDescriptor {
+                    label: Some("Pipeline Statistics Query Set"),
+                    ty: wgpu::QueryType::PipelineStatistics(
+                        wgpu::PipelineStatisticsTypes::FRAGMENT_SHADER_INVOCATIONS,
+                    ),
+                    count: 1,
+                })
+            });
+        let resolve_buffer = memory::create_buffer(
+            device,
+            &wgpu::BufferDescriptor {
+                label: Some("Timestamp Resolve Buffer"),
+                size: READBACK_SIZE,
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            },
+        );
+        let readback_buffer = memory::create_buffer(
+            device,
+            &wgpu::BufferDescriptor {
+                label: Some("Timestamp Readback Buffer"),
+                size: READBACK_SIZE,
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            },
+        );
+
+        Some(Self {
+            query_set,
+            statistics_query_set,
+            resolve_buffer,
+            readback_buffer,
+            period: queue.get_timestamp_period(),
+        })
+    }
+
+    pub fn write_timestamp(&self, encoder: &mut wgpu::CommandEncoder, index: u32) {
+        encoder.write_timestamp(&self.query_set, index);
+    }
+
+    // Starts counting fragment shader invocations, see
+    // FrameStats::model_pass_fragments. Only one pass per frame can be
+    // counted.
+    pub fn begin_statistics<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if let Some(query_set) = &self.statistics_query_set {
+            render_pass.begin_pipeline_statistics_query(query_set, 0);
+        }
+    }
+
+    pub fn end_statistics(&self, render_pass: &mut wgpu::RenderPass) {
+        if self.statistics_query_set.is_some() {
+            render_pass.end_pipeline_statistics_query();
+        }
+    }
+
+    // Records copying the frame's timestamps and statistics somewhere they
+    // can be read. Has to come after the last write_timestamp.
+    pub fn resolve(&self, encoder: &mut wgpu::CommandEncoder) {
+        encoder.resolve_query_set(&self.query_set, 0..QUERY_COUNT, &self.resolve_buffer, 0);
+        if let Some(query_set) = &self.statistics_query_set {
+            encoder.resolve_query_set(query_set, 0..1, &self.resolve_buffer, STATISTICS_OFFSET);
+        }
+        encoder.copy_buffer_to_buffer(
+            &self.resolve_buffer,
+            0,
+            &self.readback_buffer,
+            0,
+            READBACK_SIZE,
+        );
+    }
+
+    // Waits for the submitted frame to finish and reads its timings. This
+    // stalls the CPU until the GPU catches up, so it is only worth having on
+    // while profiling.
+    pub fn read(&self, device: &wgpu::Device) -> FrameStats {
+        let buffer_slice = self.readback_buffer.slice(..);
+        let mapping = buffer_slice.map_async(wgpu::MapMode::Read);
+        device.poll(wgpu::Maintain::Wait);
+        if let Err(e) = pollster::block_on(mapping) {
+            log::warn!("Failed to read back timestamps: {:?}", e);
+            return FrameStats::default();
+        }
+
+        let values: Vec<u64> = {
+            let data = buffer_slice.get_mapped_range();
+            bytemuck::cast_slice(&data).to_vec()
+        };
+        self.readback_buffer.unmap();
+
+        let elapsed = |start: u32, end: u32| {
+            let ticks = values[end as usize].saturating_sub(values[start as usize]);
+            Duration::from_nanos((ticks as f64 * self.period as f64) as u64)
+        };
+        FrameStats {
+            light_pass: elapsed(LIGHT_PASS_START, MODEL_PASS_START),
+            model_pass: elapsed(MODEL_PASS_START, MODEL_PASS_END),
+            model_pass_fragments: if self.statistics_query_set.is_some() {
+                values[(STATISTICS_OFFSET / QUERY_SIZE) as usize]
+            } else {
+                0
+            },
+        }
+    }
+}
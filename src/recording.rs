@@ -0,0 +1,161 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use winit::event::{MouseScrollDelta, VirtualKeyCode};
+
+use crate::camera;
+
+// The keys camera::CameraController listens to
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CameraKey {
+    Forward,
+    Backward,
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+impl CameraKey {
+    pub fn from_key(key: VirtualKeyCode) -> Option<Self> {
+        match key {
+            VirtualKeyCode::W | VirtualKeyCode::Up => Some(Self::Forward),
+            VirtualKeyCode::S | VirtualKeyCode::Down => Some(Self::Backward),
+            VirtualKeyCode::A | VirtualKeyCode::Left => Some(Self::Left),
+            VirtualKeyCode::D | VirtualKeyCode::Right => Some(Self::Right),
+            VirtualKeyCode::Space => Some(Self::Up),
+            VirtualKeyCode::LShift => Some(Self::Down),
+            _ => None,
+        }
+    }
+
+    pub fn key(self) -> VirtualKeyCode {
+        match self {
+            Self::Forward => VirtualKeyCode::W,
+            Self::Backward => VirtualKeyCode::S,
+            Self::Left => VirtualKeyCode::A,
+            Self::Right => VirtualKeyCode::D,
+            Self::Up => VirtualKeyCode::Space,
+            Self::Down => VirtualKeyCode::LShift,
+        }
+    }
+}
+
+// Input as the camera controller received it
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum InputEvent {
+    Key { key: CameraKey, pressed: bool },
+    MouseMotion { dx: f64, dy: f64 },
+    ScrollLines(f32),
+    ScrollPixels(f64),
+}
+
+impl InputEvent {
+    pub fn apply(self, controller: &mut camera::CameraController) {
+        match self {
+            InputEvent::Key { key, pressed } => {
+                let state = if pressed {
+                    winit::event::ElementState::Pressed
+                } else {
+                    winit::event::ElementState::Released
+                };
+                controller.process_keyboard(key.key(), state);
+            }
+            InputEvent::MouseMotion { dx, dy } => controller.process_mouse(dx, dy),
+            InputEvent::ScrollLines(lines) => {
+                controller.process_scroll(&MouseScrollDelta::LineDelta(0.0, lines))
+            }
+            InputEvent::ScrollPixels(y) => controller.process_scroll(
+                &MouseScrollDelta::PixelDelta(winit::dpi::PhysicalPosition::new(0.0, y)),
+            ),
+        }
+    }
+}
+
+// The events that arrived before an update_camera call, and that call's dt
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RecordedFrame {
+    pub dt: Duration,
+    pub events: Vec<InputEvent>,
+}
+
+// A fly-through saved as RON. Frames keep their dt, so replaying one moves
+// the camera the same way whatever the frame rate is during the replay.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct InputRecording {
+    pub frames: Vec<RecordedFrame>,
+}
+
+impl InputRecording {
+    pub fn save(&self, path: &str) -> anyhow::Result<()> {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        std::fs::write(path, text)
+            .with_context(|| format!("Failed to write input recording {}", path))
+    }
+
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read input recording {}", path))?;
+        ron::from_str(&text).with_context(|| format!("Failed to parse input recording {}", path))
+    }
+
+    // Time from the start of the recording to the end of its last frame
+    pub fn duration(&self) -> Duration {
+        self.frames.iter().map(|frame| frame.dt).sum()
+    }
+}
+
+// Collects what a CameraController receives, see
+// CameraController::start_recording
+#[derive(Debug, Default)]
+pub struct InputRecorder {
+    recording: InputRecording,
+    pending: Vec<InputEvent>,
+}
+
+impl InputRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, event: InputEvent) {
+        self.pending.push(event);
+    }
+
+    pub fn end_frame(&mut self, dt: Duration) {
+        self.recording.frames.push(RecordedFrame {
+            dt,
+            events: std::mem::take(&mut self.pending),
+        });
+    }
+
+    // Events after the last frame never reached the camera, so they're
+    // dropped
+    pub fn finish(self) -> InputRecording {
+        self.recording
+    }
+}
+
+// Plays a recording back one frame at a time
+pub struct InputReplay {
+    frames: std::vec::IntoIter<RecordedFrame>,
+}
+
+impl InputReplay {
+    pub fn new(recording: InputRecording) -> Self {
+        Self {
+            frames: recording.frames.into_iter(),
+        }
+    }
+
+    // Feeds the next frame's events to the controller and returns the dt to
+    // update with, or None once the recording is over
+    pub fn next_frame(&mut self, controller: &mut camera::CameraController) -> Option<Duration> {
+        let frame = self.frames.next()?;
+        for event in frame.events {
+            event.apply(controller);
+        }
+        Some(frame.dt)
+    }
+}
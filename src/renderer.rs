@@ -1,4 +1,292 @@
-use crate::{model::DrawModel, State};
+use std::{borrow::Cow, collections::BTreeMap, ops::Range};
+
+use anyhow::Context as _;
+
+use crate::{
+    batch, instance, light, memory,
+    model::{self, DrawModel, Vertex},
+    profiler, selection, settings, texture, State,
+};
+
+// A rectangle of the surface in physical pixels
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Viewport {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Viewport {
+    // The largest whole pixel rectangle with target_aspect (width / height)
+    // centered in a width x height surface, or all of it for None
+    pub fn letterbox(width: u32, height: u32, target_aspect: Option<f32>) -> Self {
+        let (surface_width, surface_height) = (width as f32, height as f32);
+        let (width, height) = match target_aspect.filter(|aspect| *aspect > 0.0) {
+            Some(aspect) if surface_width / surface_height > aspect => {
+                (surface_height * aspect, surface_height)
+            }
+            Some(aspect) => (surface_width, surface_width / aspect),
+            None => (surface_width, surface_height),
+        };
+        let (width, height) = (width.round().max(1.0), height.round().max(1.0));
+        Self {
+            x: ((surface_width - width) / 2.0).floor(),
+            y: ((surface_height - height) / 2.0).floor(),
+            width,
+            height,
+        }
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.width as u32, self.height as u32)
+    }
+
+    pub fn apply(&self, render_pass: &mut wgpu::RenderPass) {
+        render_pass.set_viewport(self.x, self.y, self.width, self.height, 0.0, 1.0);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PipelineOptions {
+    // Must be None for integer color targets
+    pub blend: Option<wgpu::BlendState>,
+    pub depth_write_enabled: bool,
+    pub depth_compare: wgpu::CompareFunction,
+    // Only has an effect with a depth format that has a stencil aspect
+    pub stencil: wgpu::StencilState,
+    pub write_mask: wgpu::ColorWrites,
+    pub topology: wgpu::PrimitiveTopology,
+    pub front_face: wgpu::FrontFace,
+    pub cull_mode: Option<wgpu::Face>,
+    // Anything but Fill needs the matching wgpu::Features
+    pub polygon_mode: wgpu::PolygonMode,
+    pub vertex_entry_point: &'static str,
+    pub fragment_entry_point: &'static str,
+    // Has to match the color and depth targets, see antialiasing::AntiAliasing
+    pub sample_count: u32,
+    // Constants the WGSL source is built with, see preprocess
+    pub defines: ShaderDefines,
+}
+
+impl PipelineOptions {
+    // Alpha blended geometry is sorted back to front on the CPU, so it
+    // tests against depth but doesn't write it
+    pub fn transparent() -> Self {
+        Self {
+            blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+            depth_write_enabled: false,
+            ..Default::default()
+        }
+    }
+
+    // For textures uploaded with texture::AlphaMode::Premultiplied. The
+    // shader has to output premultiplied color through fs_premultiplied.
+    pub fn transparent_premultiplied() -> Self {
+        Self {
+            blend: Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+            fragment_entry_point: "fs_premultiplied",
+            ..Self::transparent()
+        }
+    }
+
+    // Adds to the target, scaled by alpha. The fade needs the color
+    // premultiplied, so this uses fs_premultiplied.
+    pub fn additive() -> Self {
+        Self {
+            blend: Some(wgpu::BlendState {
+                color: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::One,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::Zero,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+            }),
+            fragment_entry_point: "fs_premultiplied",
+            ..Self::transparent()
+        }
+    }
+
+    // Multiplies the target by the color, which fades towards white as
+    // alpha drops: dst * (src * a + 1 - a) with premultiplied src
+    pub fn multiply() -> Self {
+        Self {
+            blend: Some(wgpu::BlendState {
+                color: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::Dst,
+                    dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::Zero,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+            }),
+            fragment_entry_point: "fs_premultiplied",
+            ..Self::transparent()
+        }
+    }
+
+    // Writes the depth of opaque geometry and nothing else, see
+    // State::set_depth_prepass
+    pub fn depth_prepass() -> Self {
+        Self {
+            blend: None,
+            write_mask: wgpu::ColorWrites::empty(),
+            fragment_entry_point: "fs_depth",
+            ..Default::default()
+        }
+    }
+
+    // Only shades the fragments the depth prepass left visible, without
+    // writing depth again
+    pub fn depth_equal(self) -> Self {
+        Self {
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::Equal,
+            ..self
+        }
+    }
+
+    // Draws back faces too, see model::Material::set_two_sided
+    pub fn two_sided(self) -> Self {
+        Self {
+            cull_mode: None,
+            ..self
+        }
+    }
+
+    // For instances with a negative scale, whose triangles wind the other way
+    pub fn mirrored(self) -> Self {
+        Self {
+            front_face: match self.front_face {
+                wgpu::FrontFace::Ccw => wgpu::FrontFace::Cw,
+                wgpu::FrontFace::Cw => wgpu::FrontFace::Ccw,
+            },
+            ..self
+        }
+    }
+
+    // Marks the stencil buffer with the reference value wherever the
+    // geometry is visible, without touching color or depth
+    pub fn stencil_mask() -> Self {
+        let face = wgpu::StencilFaceState {
+            compare: wgpu::CompareFunction::Always,
+            fail_op: wgpu::StencilOperation::Keep,
+            depth_fail_op: wgpu::StencilOperation::Keep,
+            pass_op: wgpu::StencilOperation::Replace,
+        };
+        Self {
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::LessEqual,
+            stencil: wgpu::StencilState {
+                front: face,
+                back: face,
+                read_mask: 0xff,
+                write_mask: 0xff,
+            },
+            write_mask: wgpu::ColorWrites::empty(),
+            // Works for mirrored instances too
+            cull_mode: None,
+            ..Default::default()
+        }
+    }
+
+    // Draws on top of everything, except where the stencil buffer holds the
+    // reference value
+    pub fn outline() -> Self {
+        let face = wgpu::StencilFaceState {
+            compare: wgpu::CompareFunction::NotEqual,
+            fail_op: wgpu::StencilOperation::Keep,
+            depth_fail_op: wgpu::StencilOperation::Keep,
+            pass_op: wgpu::StencilOperation::Keep,
+        };
+        Self {
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::Always,
+            stencil: wgpu::StencilState {
+                front: face,
+                back: face,
+                read_mask: 0xff,
+                write_mask: 0,
+            },
+            cull_mode: None,
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for PipelineOptions {
+    fn default() -> Self {
+        Self {
+            blend: Some(wgpu::BlendState {
+                alpha: wgpu::BlendComponent::REPLACE,
+                color: wgpu::BlendComponent::REPLACE,
+            }),
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            write_mask: wgpu::ColorWrites::ALL,
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            polygon_mode: wgpu::PolygonMode::Fill,
+            vertex_entry_point: "vs_main",
+            fragment_entry_point: "fs_main",
+            sample_count: 1,
+            defines: ShaderDefines::new(),
+        }
+    }
+}
+
+// Names and WGSL expressions for preprocess, like ("MAX_LIGHTS", "8u") or
+// ("USE_FOG", "true"). Sorted, so the same defines always build the same
+// source.
+pub type ShaderDefines = BTreeMap<String, String>;
+
+// Turns every `//#define NAME value` line in source into a module constant,
+// `let NAME = value;`, with the value from defines where it has one. The
+// line's own value is the default, so the file still works on its own.
+// Defines the source doesn't declare are added as constants at the top.
+// Everything else, comments included, is left alone.
+pub fn preprocess<'a>(source: &'a str, defines: &ShaderDefines) -> Cow<'a, str> {
+    if defines.is_empty() && !source.contains("//#define") {
+        return Cow::Borrowed(source);
+    }
+
+    let mut declared = Vec::new();
+    let mut body = String::with_capacity(source.len());
+    for line in source.lines() {
+        let define = line
+            .trim_start()
+            .strip_prefix("//#define")
+            .filter(|rest| rest.starts_with(char::is_whitespace))
+            .and_then(|rest| rest.trim().split_once(char::is_whitespace));
+        match define {
+            Some((name, default)) => {
+                let value = defines.get(name).map_or(default.trim(), String::as_str);
+                body.push_str(&format!("let {} = {};", name, value));
+                declared.push(name);
+            }
+            None => body.push_str(line),
+        }
+        body.push('\n');
+    }
+
+    let mut output = String::with_capacity(body.len());
+    for (name, value) in defines {
+        if !declared.contains(&name.as_str()) {
+            output.push_str(&format!("let {} = {};\n", name, value));
+        }
+    }
+    output.push_str(&body);
+    Cow::Owned(output)
+}
 
 pub struct RenderPipeline {
     pub render_pipeline: wgpu::RenderPipeline,
@@ -12,36 +300,62 @@ impl RenderPipeline {
         depth_format: Option<wgpu::TextureFormat>,
         vertex_layouts: &[wgpu::VertexBufferLayout],
         shader: wgpu::ShaderModuleDescriptor,
+        options: PipelineOptions,
     ) -> RenderPipeline {
-        let shader = device.create_shader_module(&shader);
+        let wgpu::ShaderSource::Wgsl(source) = shader.source;
+        let source = match preprocess(&source, &options.defines) {
+            Cow::Owned(processed) => Cow::Owned(processed),
+            Cow::Borrowed(_) => source,
+        };
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: shader.label,
+            source: wgpu::ShaderSource::Wgsl(source),
+        });
+        Self::from_module(
+            device,
+            layout,
+            color_format,
+            depth_format,
+            vertex_layouts,
+            &shader,
+            options,
+        )
+    }
 
+    // Like new, for a shader module that has already been created. The
+    // module needs the vertex and fragment entry points named in options.
+    pub fn from_module(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+        depth_format: Option<wgpu::TextureFormat>,
+        vertex_layouts: &[wgpu::VertexBufferLayout],
+        shader: &wgpu::ShaderModule,
+        options: PipelineOptions,
+    ) -> RenderPipeline {
         let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
             label: Some("Render Pipeline"),
             layout: Some(layout),
             vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main",
+                module: shader,
+                entry_point: options.vertex_entry_point,
                 buffers: vertex_layouts,
             },
             fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
+                module: shader,
+                entry_point: options.fragment_entry_point,
                 targets: &[wgpu::ColorTargetState {
                     format: color_format,
-                    blend: Some(wgpu::BlendState {
-                        alpha: wgpu::BlendComponent::REPLACE,
-                        color: wgpu::BlendComponent::REPLACE,
-                    }),
-                    write_mask: wgpu::ColorWrites::ALL,
+                    blend: options.blend,
+                    write_mask: options.write_mask,
                 }],
             }),
             primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
+                topology: options.topology,
                 strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
-                polygon_mode: wgpu::PolygonMode::Fill,
+                front_face: options.front_face,
+                cull_mode: options.cull_mode,
+                polygon_mode: options.polygon_mode,
                 // Requires Features::DEPTH_CLIP_CONTROL
                 unclipped_depth: false,
                 // Requires Features::CONSERVATIVE_RASTERIZATION
@@ -49,13 +363,13 @@ impl RenderPipeline {
             },
             depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
                 format,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: wgpu::StencilState::default(),
+                depth_write_enabled: options.depth_write_enabled,
+                depth_compare: options.depth_compare,
+                stencil: options.stencil,
                 bias: wgpu::DepthBiasState::default(),
             }),
             multisample: wgpu::MultisampleState {
-                count: 1,
+                count: options.sample_count,
                 mask: !0,
                 alpha_to_coverage_enabled: false,
             },
@@ -79,75 +393,1030 @@ impl RenderPipeline {
     }
 }
 
+// Fragment entry points in shader_a.wgsl that opaque draws can be switched
+// to for debugging, see State::shader_variant. The first is the normal lit
+// shading.
+pub const SHADER_VARIANTS: [&str; 4] = ["fs_main", "fs_unlit", "fs_normals", "fs_uvs"];
+
+// The built in pipelines that draw into the scene target. They have to
+// match its sample count, so they are rebuilt together whenever that
+// changes.
+pub struct ScenePipelines {
+    pub opaque: wgpu::RenderPipeline,
+    pub transparent: wgpu::RenderPipeline,
+    pub transparent_premultiplied: wgpu::RenderPipeline,
+    pub additive: wgpu::RenderPipeline,
+    pub multiply: wgpu::RenderPipeline,
+    // Instances with a negative scale wind their triangles the other way
+    pub mirrored: wgpu::RenderPipeline,
+    pub mirrored_transparent: wgpu::RenderPipeline,
+    pub mirrored_transparent_premultiplied: wgpu::RenderPipeline,
+    pub mirrored_additive: wgpu::RenderPipeline,
+    pub mirrored_multiply: wgpu::RenderPipeline,
+    // Normal and mirrored pipelines for each of SHADER_VARIANTS after the
+    // first
+    pub shader_variants: Vec<(wgpu::RenderPipeline, wgpu::RenderPipeline)>,
+    // Normal and mirrored pipelines without culling for two sided materials:
+    // opaque, transparent, premultiplied, additive and multiply
+    pub two_sided: Vec<(wgpu::RenderPipeline, wgpu::RenderPipeline)>,
+    // Only built while the depth prepass is on
+    pub prepass: Option<PrepassPipelines>,
+    // For selection::OutlineMode::Stencil
+    pub stencil_mask: wgpu::RenderPipeline,
+    pub outline: wgpu::RenderPipeline,
+    // Triangle edges only, for every material, see State::set_use_wireframe.
+    // None without Features::POLYGON_MODE_LINE.
+    pub wireframe: Option<wgpu::RenderPipeline>,
+    pub light: wgpu::RenderPipeline,
+}
+
+impl ScenePipelines {
+    // layout is the one from State::bind_group_layouts, light_layout has the
+    // camera and light groups only. light_storage is light::LightArray's
+    // is_storage.
+    pub fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        light_layout: &wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        depth_prepass: bool,
+        light_storage: bool,
+    ) -> Self {
+        // The opaque and transparent pipelines are all built from the same source
+        let source = settings::shader_source(include_str!("shaders/shader_a.wgsl"));
+        let source = light::shader_source(&source, light_storage);
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Normal Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                preprocess(&source, &light::shader_defines()).into_owned().into(),
+            ),
+        });
+        let vertex_layouts = [model::ModelVertex::desc(), instance::InstanceRaw::desc()];
+        let pipeline = |options: PipelineOptions| {
+            RenderPipeline::from_module(
+                device,
+                layout,
+                color_format,
+                Some(texture::Texture::DEPTH_FORMAT),
+                &vertex_layouts,
+                &shader,
+                PipelineOptions {
+                    sample_count,
+                    ..options
+                },
+            )
+            .render_pipeline
+        };
+
+        let shader_variants = SHADER_VARIANTS[1..]
+            .iter()
+            .map(|&entry_point| {
+                let options = PipelineOptions {
+                    fragment_entry_point: entry_point,
+                    ..Default::default()
+                };
+                (pipeline(options.clone()), pipeline(options.mirrored()))
+            })
+            .collect();
+
+        let two_sided = [
+            PipelineOptions::default(),
+            PipelineOptions::transparent(),
+            PipelineOptions::transparent_premultiplied(),
+            PipelineOptions::additive(),
+            PipelineOptions::multiply(),
+        ]
+        .into_iter()
+        .map(|options| {
+            let options = options.two_sided();
+            (pipeline(options.clone()), pipeline(options.mirrored()))
+        })
+        .collect();
+
+        // Normal and mirrored pipelines for the options
+        let pair =
+            |options: PipelineOptions| (pipeline(options.clone()), pipeline(options.mirrored()));
+        let prepass = depth_prepass.then(|| PrepassPipelines {
+            depth: pair(PipelineOptions::depth_prepass()),
+            depth_two_sided: pair(PipelineOptions::depth_prepass().two_sided()),
+            opaque: pair(PipelineOptions::default().depth_equal()),
+            two_sided: pair(PipelineOptions::default().two_sided().depth_equal()),
+            shader_variants: SHADER_VARIANTS[1..]
+                .iter()
+                .map(|&entry_point| {
+                    pair(
+                        PipelineOptions {
+                            fragment_entry_point: entry_point,
+                            ..Default::default()
+                        }
+                        .depth_equal(),
+                    )
+                })
+                .collect(),
+        });
+
+        // Without culling, so the back edges show through too and mirroring
+        // doesn't matter
+        let wireframe = device
+            .features()
+            .contains(wgpu::Features::POLYGON_MODE_LINE)
+            .then(|| {
+                pipeline(PipelineOptions {
+                    polygon_mode: wgpu::PolygonMode::Line,
+                    cull_mode: None,
+                    ..Default::default()
+                })
+            });
+
+        let outline = RenderPipeline::new(
+            device,
+            layout,
+            color_format,
+            Some(texture::Texture::DEPTH_FORMAT),
+            &vertex_layouts,
+            wgpu::ShaderModuleDescriptor {
+                label: Some("Outline Shader"),
+                source: wgpu::ShaderSource::Wgsl(
+                    settings::shader_source(include_str!("shaders/outline.wgsl")).into(),
+                ),
+            },
+            PipelineOptions {
+                sample_count,
+                ..PipelineOptions::outline()
+            },
+        )
+        .render_pipeline;
+
+        let light = RenderPipeline::new(
+            device,
+            light_layout,
+            color_format,
+            Some(texture::Texture::DEPTH_FORMAT),
+            &[model::ModelVertex::desc()],
+            wgpu::ShaderModuleDescriptor {
+                label: Some("Light Shader"),
+                source: wgpu::ShaderSource::Wgsl(
+                    light::shader_source(
+                        &settings::shader_source(include_str!("shaders/light.wgsl")),
+                        light_storage,
+                    )
+                    .into_owned()
+                    .into(),
+                ),
+            },
+            PipelineOptions {
+                sample_count,
+                defines: light::shader_defines(),
+                ..Default::default()
+            },
+        )
+        .render_pipeline;
+
+        Self {
+            opaque: pipeline(PipelineOptions::default()),
+            transparent: pipeline(PipelineOptions::transparent()),
+            transparent_premultiplied: pipeline(PipelineOptions::transparent_premultiplied()),
+            additive: pipeline(PipelineOptions::additive()),
+            multiply: pipeline(PipelineOptions::multiply()),
+            mirrored: pipeline(PipelineOptions::default().mirrored()),
+            mirrored_transparent: pipeline(PipelineOptions::transparent().mirrored()),
+            mirrored_transparent_premultiplied: pipeline(
+                PipelineOptions::transparent_premultiplied().mirrored(),
+            ),
+            mirrored_additive: pipeline(PipelineOptions::additive().mirrored()),
+            mirrored_multiply: pipeline(PipelineOptions::multiply().mirrored()),
+            shader_variants,
+            two_sided,
+            prepass,
+            stencil_mask: pipeline(PipelineOptions::stencil_mask()),
+            outline,
+            wireframe,
+            light,
+        }
+    }
+}
+
+// Normal and mirrored pipelines for the depth prepass, and the opaque
+// pipelines that draw after it testing for equal depth
+pub struct PrepassPipelines {
+    pub depth: (wgpu::RenderPipeline, wgpu::RenderPipeline),
+    pub depth_two_sided: (wgpu::RenderPipeline, wgpu::RenderPipeline),
+    pub opaque: (wgpu::RenderPipeline, wgpu::RenderPipeline),
+    pub two_sided: (wgpu::RenderPipeline, wgpu::RenderPipeline),
+    pub shader_variants: Vec<(wgpu::RenderPipeline, wgpu::RenderPipeline)>,
+}
+
+struct DrawRun {
+    lod: usize,
+    debug: bool,
+    mirrored: bool,
+    instances: Range<u32>,
+}
+
+// Splits a range of instance buffer slots into runs that share a LOD level,
+// material and winding. Slots are sorted by distance, so each level ends up
+// as a single run unless debug flagged or mirrored instances are mixed in.
+fn draw_runs(state: &State, instances: Range<u32>) -> Vec<DrawRun> {
+    // GPU driven instances don't use the CPU side transforms
+    let cpu_instances = state.gpu_instance_compute().is_none();
+    let mut runs: Vec<DrawRun> = Vec::new();
+    for slot in instances {
+        let lod = state
+            .obj_model
+            .lod_for_distance(state.instance_distances[slot as usize]);
+        let index = state.instance_order[slot as usize];
+        let debug = state.is_instance_debug(index);
+        let mirrored = cpu_instances && state.instances[index as usize].is_mirrored();
+        match runs.last_mut() {
+            Some(run) if run.lod == lod && run.debug == debug && run.mirrored == mirrored => {
+                run.instances.end = slot + 1
+            }
+            _ => runs.push(DrawRun {
+                lod,
+                debug,
+                mirrored,
+                instances: slot..slot + 1,
+            }),
+        }
+    }
+    runs
+}
+
+// Picks between the built in pipelines for a draw. Fading instances of an
+// opaque material are alpha blended.
+fn scene_pipeline<'a>(
+    state: &'a State,
+    material: &model::Material,
+    fading: bool,
+    mirrored: bool,
+) -> &'a wgpu::RenderPipeline {
+    if let Some(wireframe) = state.wireframe_pipeline() {
+        return wireframe;
+    }
+    let pipelines = &state.pipelines;
+    if let Some(prepass) = state.depth_prepass_pipelines() {
+        if material.blend_mode == model::BlendMode::Opaque && !fading {
+            let variant = prepass
+                .shader_variants
+                .get(state.shader_variant.wrapping_sub(1));
+            let (pipeline, mirrored_pipeline) = match variant {
+                Some(variant) => variant,
+                None if material.two_sided() => &prepass.two_sided,
+                None => &prepass.opaque,
+            };
+            return if mirrored {
+                mirrored_pipeline
+            } else {
+                pipeline
+            };
+        }
+    }
+    let premultiplied = material.diffuse_texture.alpha_mode == texture::AlphaMode::Premultiplied;
+    // Index into ScenePipelines::two_sided, which follows the same order
+    let (pipeline, mirrored_pipeline, two_sided) = match material.blend_mode {
+        model::BlendMode::Opaque if !fading => {
+            match pipelines.shader_variants.get(state.shader_variant.wrapping_sub(1)) {
+                Some((pipeline, mirrored_pipeline)) => (pipeline, mirrored_pipeline, None),
+                None => (&pipelines.opaque, &pipelines.mirrored, Some(0)),
+            }
+        }
+        // Premultiplied textures need a different blend state
+        model::BlendMode::Opaque | model::BlendMode::AlphaBlend if premultiplied => (
+            &pipelines.transparent_premultiplied,
+            &pipelines.mirrored_transparent_premultiplied,
+            Some(2),
+        ),
+        model::BlendMode::Opaque | model::BlendMode::AlphaBlend => (
+            &pipelines.transparent,
+            &pipelines.mirrored_transparent,
+            Some(1),
+        ),
+        model::BlendMode::Additive => (&pipelines.additive, &pipelines.mirrored_additive, Some(3)),
+        model::BlendMode::Multiply => (&pipelines.multiply, &pipelines.mirrored_multiply, Some(4)),
+    };
+    // The debug shader variants keep culling
+    let (pipeline, mirrored_pipeline) = match two_sided {
+        Some(i) if material.two_sided() => (&pipelines.two_sided[i].0, &pipelines.two_sided[i].1),
+        _ => (pipeline, mirrored_pipeline),
+    };
+    if mirrored {
+        mirrored_pipeline
+    } else {
+        pipeline
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Phase {
+    // Opaque materials on the opaque instances, front to back
+    Opaque,
+    // Every material on the fading instances, back to front
+    Fading,
+    // Blended materials on the opaque instances. These are closer than
+    // anything fading, so they go last.
+    Blended,
+}
+
+// State::clear_color's default
+pub const CLEAR_COLOR: wgpu::Color = wgpu::Color {
+    r: 0.1,
+    g: 0.2,
+    b: 0.3,
+    a: 1.0,
+};
+
+// What a pass does with the depth and stencil buffer it starts with. Only
+// the first pass of the frame clears both, every later pass loads them, or
+// whatever was drawn before it stops occluding and ends up behind what comes
+// after. The depth attachment is always stored, DoF samples it after the
+// scene passes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum DepthLoad {
+    Clear,
+    // For the foreground layers with State::clear_depth_between_layers. The
+    // stencil is kept since nothing after the main pass reads it.
+    ClearDepth,
+    Load,
+}
+
+fn depth_stencil_attachment(
+    state: &State,
+    load: DepthLoad,
+) -> wgpu::RenderPassDepthStencilAttachment<'_> {
+    let (depth_load, stencil_load) = match load {
+        DepthLoad::Clear => (wgpu::LoadOp::Clear(1.0), wgpu::LoadOp::Clear(0)),
+        DepthLoad::ClearDepth => (wgpu::LoadOp::Clear(1.0), wgpu::LoadOp::Load),
+        DepthLoad::Load => (wgpu::LoadOp::Load, wgpu::LoadOp::Load),
+    };
+    wgpu::RenderPassDepthStencilAttachment {
+        view: &state.depth_texture.view,
+        depth_ops: Some(wgpu::Operations {
+            load: depth_load,
+            store: true,
+        }),
+        stencil_ops: Some(wgpu::Operations {
+            load: stencil_load,
+            store: true,
+        }),
+    }
+}
+
+// A scene pass drawing into the color target and depth buffer. Only the
+// first pass of the frame clears the color target.
+fn begin_scene_pass<'a>(
+    encoder: &'a mut wgpu::CommandEncoder,
+    state: &'a State,
+    scene_view: &'a wgpu::TextureView,
+    resolve_target: Option<&'a wgpu::TextureView>,
+    label: &'a str,
+    depth_load: DepthLoad,
+) -> wgpu::RenderPass<'a> {
+    encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some(label),
+        color_attachments: &[wgpu::RenderPassColorAttachment {
+            view: scene_view,
+            resolve_target,
+            ops: wgpu::Operations {
+                load: if depth_load == DepthLoad::Clear {
+                    wgpu::LoadOp::Clear(state.clear_color)
+                } else {
+                    wgpu::LoadOp::Load
+                },
+                store: true,
+            },
+        }],
+        depth_stencil_attachment: Some(depth_stencil_attachment(state, depth_load)),
+    })
+}
+
+// A pass starting a draw layer. The first pass of the frame clears the
+// targets, the others keep them and only clear depth when layers are
+// to be drawn over each other.
+fn begin_layer_pass<'a>(
+    encoder: &'a mut wgpu::CommandEncoder,
+    state: &'a State,
+    scene_view: &'a wgpu::TextureView,
+    resolve_target: Option<&'a wgpu::TextureView>,
+    label: &'a str,
+    first: bool,
+) -> wgpu::RenderPass<'a> {
+    let depth_load = if first {
+        DepthLoad::Clear
+    } else if state.clear_depth_between_layers {
+        DepthLoad::ClearDepth
+    } else {
+        DepthLoad::Load
+    };
+    begin_scene_pass(
+        encoder,
+        state,
+        scene_view,
+        resolve_target,
+        label,
+        depth_load,
+    )
+}
+
+// Models other than obj_model are drawn with the opaque pipeline, or the
+// cullless one for two sided materials, unless a model pipeline is set.
+// prepassed is for geometry the depth prepass drew.
+fn draw_opaque_model<'a>(
+    render_pass: &mut wgpu::RenderPass<'a>,
+    state: &'a State,
+    model: &'a model::Model,
+    instances: Range<u32>,
+    prepassed: bool,
+) {
+    let prepass = state.depth_prepass_pipelines().filter(|_| prepassed);
+    for mesh in &model.meshes {
+        let material = &model.materials[mesh.material];
+        let pipeline = match (&state.model_pipeline, state.wireframe_pipeline(), prepass) {
+            (Some(pipeline), _, _) => pipeline,
+            (None, Some(wireframe), _) => wireframe,
+            (None, None, Some(prepass)) if material.two_sided() => &prepass.two_sided.0,
+            (None, None, Some(prepass)) => &prepass.opaque.0,
+            (None, None, None) if material.two_sided() => &state.pipelines.two_sided[0].0,
+            (None, None, None) => &state.pipelines.opaque,
+        };
+        render_pass.set_pipeline(pipeline);
+        render_pass.draw_mesh_instanced(
+            mesh,
+            material,
+            instances.clone(),
+            &state.camera_bind_group,
+            &state.light_bind_group,
+        );
+    }
+}
+
+// Writes the depth of the opaque geometry in the world layer, everything
+// the main pass draws with an opaque pipeline, so that pass only shades the
+// nearest fragments. Has to match the main pass draw for draw, down to the
+// LOD levels, or the equal depth test fails.
+fn draw_depth_prepass<'a>(
+    render_pass: &mut wgpu::RenderPass<'a>,
+    state: &'a State,
+    prepass: &'a PrepassPipelines,
+) {
+    let depth_pipeline = |material: &model::Material, mirrored: bool| {
+        let (pipeline, mirrored_pipeline) = if material.two_sided() {
+            &prepass.depth_two_sided
+        } else {
+            &prepass.depth
+        };
+        if mirrored {
+            mirrored_pipeline
+        } else {
+            pipeline
+        }
+    };
+    let draw_model =
+        |render_pass: &mut wgpu::RenderPass<'a>, model: &'a model::Model, instances: Range<u32>| {
+            for mesh in &model.meshes {
+                let material = &model.materials[mesh.material];
+                render_pass.set_pipeline(depth_pipeline(material, false));
+                render_pass.draw_mesh_instanced(
+                    mesh,
+                    material,
+                    instances.clone(),
+                    &state.camera_bind_group,
+                    &state.light_bind_group,
+                );
+            }
+        };
+
+    render_pass.set_vertex_buffer(1, state.static_instance_buffer.slice(..));
+    for static_model in state.static_models() {
+        draw_model(render_pass, &static_model.model, 0..1);
+    }
+
+    render_pass.set_vertex_buffer(1, state.instance_buffer.slice(..));
+    for run in draw_runs(state, 0..state.num_opaque_instances) {
+        for mesh in &state.obj_model.meshes {
+            let material = if run.debug {
+                &state.debug_material
+            } else {
+                &state.obj_model.materials[mesh.material]
+            };
+            if material.blend_mode != model::BlendMode::Opaque {
+                continue;
+            }
+            render_pass.set_pipeline(depth_pipeline(material, run.mirrored));
+            render_pass.draw_mesh_instanced_lod(
+                mesh,
+                material,
+                run.lod,
+                run.instances.clone(),
+                &state.camera_bind_group,
+                &state.light_bind_group,
+            );
+        }
+    }
+
+    for entry in &state.models {
+        render_pass.set_vertex_buffer(1, entry.instance_buffer.slice(..));
+        draw_model(render_pass, &entry.model, 0..entry.instance_count);
+    }
+    for (model, instance_buffer, instances) in state.batcher.draws(batch::WORLD_LAYER) {
+        render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+        draw_model(render_pass, model, instances);
+    }
+}
+
+// The batched objects in the layer, with their wireframes
+fn draw_batched_layer<'a>(render_pass: &mut wgpu::RenderPass<'a>, state: &'a State, layer: i32) {
+    for (model, instance_buffer, instances) in state.batcher.draws(layer) {
+        render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+        draw_opaque_model(
+            render_pass,
+            state,
+            model,
+            instances,
+            layer == batch::WORLD_LAYER,
+        );
+    }
+    if state.features.wireframe {
+        state
+            .wireframe
+            .render(render_pass, &state.batcher, &state.camera_bind_group, layer);
+    }
+}
+
+// Headless there's no surface to present to, so the frame is drawn into a
+// texture that's dropped straight after, see render_to_image to keep it
 pub fn render(state: &mut State) -> Result<(), wgpu::SurfaceError> {
-    let output = state.context.surface.get_current_texture()?;
+    state.context.wait_for_frame_latency();
+    let surface = match &state.context.surface {
+        Some(surface) => surface,
+        None => {
+            let texture = frame_texture(state, wgpu::TextureUsages::RENDER_ATTACHMENT);
+            let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+            render_to_view(state, &view);
+            present_windows(state);
+            return Ok(());
+        }
+    };
+    let output = surface.get_current_texture()?;
     let view = output
         .texture
         .create_view(&wgpu::TextureViewDescriptor::default());
 
+    render_to_view(state, &view);
+    output.present();
+    present_windows(state);
+
+    Ok(())
+}
+
+// Tone maps the scene target, which still holds the frame render_to_view
+// drew, into each of State::add_window's windows. A window that can't be
+// presented to this frame is skipped rather than failing the main one.
+fn present_windows(state: &mut State) {
+    let (width, height) = state.viewport().size();
+    let aspect = width as f32 / height as f32;
+    for window in &state.windows {
+        let output = match window.surface.get_current_texture() {
+            Ok(output) => output,
+            Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                window.configure_surface(&state.context.device);
+                continue;
+            }
+            Err(e) => {
+                log::warn!("Skipping window {:?}: {:?}", window.window_id, e);
+                continue;
+            }
+        };
+        let view = output
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        let (width, height) = (window.config.width, window.config.height);
+        let viewport = Viewport::letterbox(width, height, Some(aspect));
+
+        let device = &state.context.device;
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Window Encoder"),
+        });
+        state.hdr().process(&mut encoder, &view, Some(&viewport));
+        state.context.queue.submit(std::iter::once(encoder.finish()));
+        output.present();
+    }
+}
+
+// Draws and submits a frame into view, which has to match the surface's size
+// and format.
+//
+// The scene passes share one depth and stencil buffer, see DepthLoad:
+// - The background gradient pass clears it, without writing depth
+// - Background layer passes write depth, the first of them clears it
+//   without a gradient
+// - The light pass writes depth, and clears it without either
+// - The depth prepass writes the opaque world geometry's depth
+// - The main pass writes depth for opaque draws, or only tests it for
+//   equality after the prepass. Fading and blended draws and the grid only
+//   test it. The stencil selection outline writes and tests the stencil.
+// - Foreground layer passes write depth, and clear it first with
+//   State::clear_depth_between_layers
+// DoF then samples the depth, and the passes after it have no depth buffer.
+pub fn render_to_view(state: &mut State, view: &wgpu::TextureView) {
     let mut encoder = state
         .context.device
         .create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("Render Encoder"),
         });
 
+    // The scene goes into the offscreen target, and is tone mapped and
+    // upscaled into the swapchain afterwards. With MSAA it is drawn into the
+    // multisampled target and resolved into the offscreen one.
+    let (scene_view, resolve_target) = match state.msaa_target() {
+        Some(msaa) => (&msaa.view, Some(&state.hdr().view)),
+        None => (&state.hdr().view, None),
+    };
+
+    if let Some(instance_compute) = state.gpu_instance_compute() {
+        instance_compute.dispatch(&mut encoder);
+    }
+
+    let gpu_timer = state.gpu_timer();
+    if let Some(timer) = gpu_timer {
+        timer.write_timestamp(&mut encoder, profiler::LIGHT_PASS_START);
+    }
+
+    // A background gradient goes first, in a pass that clears the targets
+    let has_gradient = state.background.gradient().is_some();
+    if has_gradient {
+        let mut render_pass = begin_scene_pass(
+            &mut encoder,
+            state,
+            scene_view,
+            resolve_target,
+            "Background Pass",
+            DepthLoad::Clear,
+        );
+        state.background.render(&mut render_pass);
+    }
+
+    // Layers below the world are drawn before anything else, so the light
+    // pass only clears the targets when there are none
+    let layers = state.batcher.layers();
+    let background = layers
+        .iter()
+        .copied()
+        .filter(|&layer| layer < batch::WORLD_LAYER)
+        .collect::<Vec<_>>();
+    for (i, &layer) in background.iter().enumerate() {
+        let mut render_pass = begin_layer_pass(
+            &mut encoder,
+            state,
+            scene_view,
+            resolve_target,
+            "Background Layer Pass",
+            i == 0 && !has_gradient,
+        );
+        draw_batched_layer(&mut render_pass, state, layer);
+    }
+
+    // The light gets a pass of its own so the GPU timer can measure it
+    // separately from the models
     {
+        let mut render_pass = begin_layer_pass(
+            &mut encoder,
+            state,
+            scene_view,
+            resolve_target,
+            "Light Pass",
+            background.is_empty() && !has_gradient,
+        );
+
+        // The pass still runs without the marker, it clears the targets
+        if state.features.light_debug {
+            use crate::model::DrawLight;
+            render_pass.set_pipeline(&state.pipelines.light);
+            render_pass.draw_light_model_instanced(
+                &state.obj_model,
+                0..state.lights.drawn_len() as u32,
+                &state.camera_bind_group,
+                &state.light_bind_group,
+            );
+        }
+    }
+
+    if let Some(timer) = gpu_timer {
+        timer.write_timestamp(&mut encoder, profiler::MODEL_PASS_START);
+    }
+
+    if let Some(prepass) = state.depth_prepass_pipelines() {
+        // The color target is attached because the pipelines have it, but
+        // their write mask leaves it alone
         let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-            label: Some("Render Pass"),
+            label: Some("Depth Prepass"),
             color_attachments: &[wgpu::RenderPassColorAttachment {
-                view: &view,
+                view: scene_view,
                 resolve_target: None,
                 ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(wgpu::Color {
-                        r: 0.1,
-                        g: 0.2,
-                        b: 0.3,
-                        a: 1.0,
-                    }),
+                    load: wgpu::LoadOp::Load,
                     store: true,
                 },
             }],
-            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                view: &state.depth_texture.view,
-                depth_ops: Some(wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(1.0),
-                    store: true,
-                }),
-                stencil_ops: None,
-            }),
+            depth_stencil_attachment: Some(depth_stencil_attachment(state, DepthLoad::Load)),
         });
+        draw_depth_prepass(&mut render_pass, state, prepass);
+    }
+
+    {
+        let mut render_pass = begin_scene_pass(
+            &mut encoder,
+            state,
+            scene_view,
+            resolve_target,
+            "Render Pass",
+            DepthLoad::Load,
+        );
+        if let Some(timer) = gpu_timer {
+            timer.begin_statistics(&mut render_pass);
+        }
+
+        // Static models go first, each a single draw with one identity
+        // instance
+        render_pass.set_vertex_buffer(1, state.static_instance_buffer.slice(..));
+        for static_model in state.static_models() {
+            draw_opaque_model(&mut render_pass, state, &static_model.model, 0..1, true);
+        }
 
         render_pass.set_vertex_buffer(1, state.instance_buffer.slice(..));
 
-        use crate::model::DrawLight;
-        render_pass.set_pipeline(&state.light_render_pipeline);
-        render_pass.draw_light_model(
-            &state.obj_model,
+        // Opaque instances are written first, followed by the fading
+        // instances sorted back to front
+        let opaque = 0..state.num_opaque_instances;
+        let fading = opaque.end..opaque.end + state.num_fading_instances;
+        let instance_debug = state.instance_draw_mode != settings::InstanceDrawMode::Meshes;
+        for (instances, phase) in [
+            (opaque.clone(), Phase::Opaque),
+            (fading, Phase::Fading),
+            (opaque, Phase::Blended),
+        ] {
+            // The grid is blended, so it goes between the opaque and
+            // fading instances
+            if phase == Phase::Fading && state.features.grid {
+                state.grid.render(&mut render_pass, &state.camera_bind_group);
+            }
+            if instances.is_empty() || instance_debug {
+                continue;
+            }
+            for run in draw_runs(state, instances) {
+                for mesh in &state.obj_model.meshes {
+                    let material = if run.debug {
+                        &state.debug_material
+                    } else {
+                        &state.obj_model.materials[mesh.material]
+                    };
+                    let opaque_material = material.blend_mode == model::BlendMode::Opaque;
+                    match phase {
+                        Phase::Opaque if !opaque_material => continue,
+                        Phase::Blended if opaque_material => continue,
+                        _ => {}
+                    }
+                    let pipeline = state.model_pipeline.as_ref().unwrap_or_else(|| {
+                        scene_pipeline(state, material, phase == Phase::Fading, run.mirrored)
+                    });
+                    render_pass.set_pipeline(pipeline);
+                    render_pass.draw_mesh_instanced_lod(
+                        mesh,
+                        material,
+                        run.lod,
+                        run.instances.clone(),
+                        &state.camera_bind_group,
+                        &state.light_bind_group,
+                    );
+                }
+            }
+        }
+
+        // The selected instances are drawn again to mark their silhouettes
+        // in the stencil buffer, then scaled up copies are drawn everywhere
+        // outside the mark, which leaves just the outlines
+        if state.outline_mode == selection::OutlineMode::Stencil {
+            let slots = state.selected_slots();
+            if !slots.is_empty() {
+                render_pass.set_stencil_reference(1);
+            }
+            for pipeline in [&state.pipelines.stencil_mask, &state.pipelines.outline] {
+                render_pass.set_pipeline(pipeline);
+                for &slot in &slots {
+                    for mesh in &state.obj_model.meshes {
+                        render_pass.draw_mesh_instanced(
+                            mesh,
+                            &state.obj_model.materials[mesh.material],
+                            slot..slot + 1,
+                            &state.camera_bind_group,
+                            &state.light_bind_group,
+                        );
+                    }
+                }
+            }
+        }
+
+        // Binds the instance buffer to slot 0, which every draw after this
+        // sets again for its vertices
+        state.instance_debug.render(
+            &mut render_pass,
+            state.instance_draw_mode,
+            &state.instance_buffer,
+            0..state.num_opaque_instances + state.num_fading_instances,
             &state.camera_bind_group,
-            &state.light_bind_group,
         );
 
-        render_pass.set_pipeline(&state.render_pipeline);
-        if state.use_debug {
-            render_pass.draw_model_instanced_with_material(
-                &state.obj_model,
-                &state.debug_material,
-                0..state.instances.len() as u32,
-                &state.camera_bind_group,
-                &state.light_bind_group,
+        // Every other model shares the opaque pipeline and only swaps the
+        // instance buffer
+        for entry in &state.models {
+            render_pass.set_vertex_buffer(1, entry.instance_buffer.slice(..));
+            draw_opaque_model(
+                &mut render_pass,
+                state,
+                &entry.model,
+                0..entry.instance_count,
+                true,
             );
-        } else {
-            render_pass.draw_model_instanced(
-                &state.obj_model,
-                0..state.instances.len() as u32,
+        }
+        if let Some(skinning) = &state.skinning {
+            skinning.render(
+                &mut render_pass,
+                &state.skinned_models,
+                &state.static_instance_buffer,
                 &state.camera_bind_group,
                 &state.light_bind_group,
             );
         }
+        draw_batched_layer(&mut render_pass, state, batch::WORLD_LAYER);
+        if let Some(timer) = gpu_timer {
+            timer.end_statistics(&mut render_pass);
+        }
+    }
+
+    for layer in layers
+        .into_iter()
+        .filter(|&layer| layer > batch::WORLD_LAYER)
+    {
+        let mut render_pass = begin_layer_pass(
+            &mut encoder,
+            state,
+            scene_view,
+            resolve_target,
+            "Foreground Layer Pass",
+            false,
+        );
+        draw_batched_layer(&mut render_pass, state, layer);
+    }
+
+    // After the layers, since each MSAA resolve overwrites the scene texture
+    if state.outline_mode == selection::OutlineMode::ScreenSpace {
+        state.selection_outline.render(&mut encoder, state, &state.hdr().view);
     }
 
+    if let Some(timer) = gpu_timer {
+        timer.write_timestamp(&mut encoder, profiler::MODEL_PASS_END);
+        timer.resolve(&mut encoder);
+    }
+
+    if state.features.depth_of_field {
+        state.dof.process(&mut encoder, &state.hdr().view);
+    }
+
+    // Only the pass writing to the surface is letterboxed, everything before
+    // it is sized to the viewport already. Its clear leaves the bars black.
+    let viewport = state.viewport();
+    match state.fxaa() {
+        Some(fxaa) => {
+            state.hdr().process(&mut encoder, &fxaa.view, None);
+            fxaa.process(&mut encoder, view, Some(&viewport));
+        }
+        None => state.hdr().process(&mut encoder, view, Some(&viewport)),
+    }
+
+    state.gizmo.render(
+        &mut encoder,
+        view,
+        state.context.config.width,
+        state.context.config.height,
+    );
+
     state.context.queue.submit(std::iter::once(encoder.finish()));
-    output.present();
+    if let Some(timer) = gpu_timer {
+        state.frame_stats = timer.read(&state.context.device);
+    }
+    state.context.frame_submitted();
+}
 
-    Ok(())
+// A texture shaped like the surface's frames
+fn frame_texture(state: &State, usage: wgpu::TextureUsages) -> memory::Tracked<wgpu::Texture> {
+    let config = &state.context.config;
+    memory::create_texture(&state.context.device, &wgpu::TextureDescriptor {
+        label: Some("Frame Texture"),
+        size: wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: config.format,
+        usage,
+    })
+}
+
+// Renders a frame into an offscreen texture instead of the surface and
+// reads it back, for screenshots and image comparisons. Fails for anything
+// but an 8 bit RGBA or BGRA surface, since the pixels are copied as is.
+pub fn render_to_image(state: &mut State) -> anyhow::Result<image::RgbaImage> {
+    let device = &state.context.device;
+    let config = &state.context.config;
+    let (width, height, format) = (config.width, config.height, config.format);
+    if !matches!(
+        format,
+        wgpu::TextureFormat::Rgba8Unorm
+            | wgpu::TextureFormat::Rgba8UnormSrgb
+            | wgpu::TextureFormat::Bgra8Unorm
+            | wgpu::TextureFormat::Bgra8UnormSrgb
+    ) {
+        anyhow::bail!("Frames can't be read back from a {:?} surface", format);
+    }
+
+    let texture = frame_texture(
+        state,
+        wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+    );
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+    // Texture to buffer copies need rows padded to COPY_BYTES_PER_ROW_ALIGNMENT
+    let unpadded_bytes_per_row = width * 4;
+    let padded_bytes_per_row =
+        unpadded_bytes_per_row.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+    let readback_buffer = memory::create_buffer(device, &wgpu::BufferDescriptor {
+        label: Some("Capture Readback Buffer"),
+        size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    render_to_view(state, &view);
+
+    let mut encoder = state
+        .context
+        .device
+        .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Capture Encoder"),
+        });
+    encoder.copy_texture_to_buffer(
+        wgpu::ImageCopyTexture {
+            texture: &texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+            aspect: wgpu::TextureAspect::All,
+        },
+        wgpu::ImageCopyBuffer {
+            buffer: &readback_buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(padded_bytes_per_row),
+                rows_per_image: None,
+            },
+        },
+        wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+    );
+    state
+        .context
+        .queue
+        .submit(std::iter::once(encoder.finish()));
+
+    let buffer_slice = readback_buffer.slice(..);
+    let mapping = buffer_slice.map_async(wgpu::MapMode::Read);
+    state.context.device.poll(wgpu::Maintain::Wait);
+    pollster::block_on(mapping).context("Failed to read back the captured frame")?;
+
+    let mut pixels = Vec::with_capacity((unpadded_bytes_per_row * height) as usize);
+    {
+        let data = buffer_slice.get_mapped_range();
+        for row in data.chunks(padded_bytes_per_row as usize) {
+            pixels.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+        }
+    }
+    readback_buffer.unmap();
+
+    // The surface is usually BGRA
+    if matches!(
+        format,
+        wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+    ) {
+        for pixel in pixels.chunks_mut(4) {
+            pixel.swap(0, 2);
+        }
+    }
+
+    image::RgbaImage::from_raw(width, height, pixels)
+        .context("The captured frame doesn't match the surface size")
 }
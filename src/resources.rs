@@ -1,9 +1,11 @@
+use std::collections::HashMap;
 use std::io::{BufReader, Cursor};
 
 use cfg_if::cfg_if;
-use wgpu::util::DeviceExt;
+use cgmath::SquareMatrix;
 
 use crate::{
+    memory,
     model::{self, Material},
     texture,
 };
@@ -60,14 +62,231 @@ pub async fn load_binary(file_name: &str) -> anyhow::Result<Vec<u8>> {
     Ok(data)
 }
 
+// Resolves a path referenced from inside another asset (an .mtl from an
+// .obj, a texture from an .mtl) relative to the referencing file
+pub fn resolve_relative(file_name: &str, relative: &str) -> String {
+    let base = std::path::Path::new(file_name)
+        .parent()
+        .unwrap_or_else(|| std::path::Path::new(""));
+    base.join(relative).to_string_lossy().into_owned()
+}
+
 pub async fn load_texture(
     file_name: &str,
     is_normal_map: bool,
+    address_modes: texture::AddressModes,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
 ) -> anyhow::Result<texture::Texture> {
     let data = load_binary(file_name).await?;
-    texture::Texture::from_bytes(device, queue, &data, file_name, is_normal_map)
+    texture::Texture::from_bytes(
+        device,
+        queue,
+        &data,
+        file_name,
+        is_normal_map,
+        texture::AlphaMode::Straight,
+        address_modes,
+    )
+}
+
+// Loads a diffuse and normal map pair into a material that can be handed to
+// model::Model::set_material. address_modes applies to both textures.
+pub async fn load_material(
+    name: &str,
+    diffuse_file_name: &str,
+    normal_file_name: &str,
+    address_modes: texture::AddressModes,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+) -> anyhow::Result<Material> {
+    let diffuse_texture = load_texture(
+        diffuse_file_name,
+        false,
+        address_modes,
+        device,
+        queue,
+    )
+    .await?;
+    let normal_texture = load_texture(
+        normal_file_name,
+        true,
+        address_modes,
+        device,
+        queue,
+    )
+    .await?;
+
+    Ok(Material::new(
+        device,
+        name,
+        diffuse_texture,
+        normal_texture,
+        layout,
+    ))
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, serde::Deserialize)]
+pub enum UpAxis {
+    // The engine's convention, no conversion is applied
+    #[default]
+    Y,
+    // Blender and most CAD tools. Converted by rotating -90° about X:
+    //     | 1  0  0 |
+    //     | 0  0  1 |
+    //     | 0 -1  0 |
+    // so (x, y, z) becomes (x, z, -y)
+    Z,
+}
+
+impl UpAxis {
+    fn to_y_up(self, v: [f32; 3]) -> [f32; 3] {
+        match self {
+            UpAxis::Y => v,
+            UpAxis::Z => [v[0], v[2], -v[1]],
+        }
+    }
+}
+
+// Distance between LOD switches unless the model's lod_distances are changed
+const DEFAULT_LOD_DISTANCE: f32 = 15.0;
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct ImportOptions {
+    pub up_axis: UpAxis,
+    // Number of simplified LOD levels to generate on top of the full mesh
+    pub lod_levels: usize,
+    // For DirectX style normal maps, see model::Material::set_flip_green
+    pub flip_green: bool,
+}
+
+// Fills in each vertex's tangent from the triangles around it, with the
+// handedness set by the bitangent the UVs give. Triangles with degenerate
+// UVs are skipped, and vertices left without a usable tangent get one
+// perpendicular to their normal, so no NaNs reach the shaders.
+pub fn compute_tangents(vertices: &mut [model::ModelVertex], indices: &[u32]) {
+    let mut tangents = vec![cgmath::Vector3::new(0.0, 0.0, 0.0); vertices.len()];
+    let mut bitangents = tangents.clone();
+
+    // Calculate tangents and bitangets. We're going to
+    // use the triangles, so we need to loop through the
+    // indices in chunks of 3
+    for c in indices.chunks(3) {
+        let v0 = vertices[c[0] as usize];
+        let v1 = vertices[c[1] as usize];
+        let v2 = vertices[c[2] as usize];
+
+        let pos0: cgmath::Vector3<_> = v0.position.into();
+        let pos1: cgmath::Vector3<_> = v1.position.into();
+        let pos2: cgmath::Vector3<_> = v2.position.into();
+
+        let uv0: cgmath::Vector2<_> = v0.tex_coords.into();
+        let uv1: cgmath::Vector2<_> = v1.tex_coords.into();
+        let uv2: cgmath::Vector2<_> = v2.tex_coords.into();
+
+        // Calculate the edges of the triangle
+        let delta_pos1 = pos1 - pos0;
+        let delta_pos2 = pos2 - pos0;
+
+        // This will give us a direction to calculate the
+        // tangent and bitangent
+        let delta_uv1 = uv1 - uv0;
+        let delta_uv2 = uv2 - uv0;
+
+        // Solving the following system of equations will
+        // give us the tangent and bitangent.
+        //     delta_pos1 = delta_uv1.x * T + delta_u.y * B
+        //     delta_pos2 = delta_uv2.x * T + delta_uv2.y * B
+        // Luckily, the place I found this equation provided
+        // the solution!
+        let det = delta_uv1.x * delta_uv2.y - delta_uv1.y * delta_uv2.x;
+        // Zero area in UV space, the system has no solution
+        if det.abs() < DEGENERATE_UV_AREA || !det.is_finite() {
+            continue;
+        }
+        let r = 1.0 / det;
+        let tangent = (delta_pos1 * delta_uv2.y - delta_pos2 * delta_uv1.y) * r;
+        // We flip the bitangent to enable right-handed normal
+        // maps with wgpu texture coordinate system
+        let bitangent = (delta_pos2 * delta_uv1.x - delta_pos1 * delta_uv2.x) * -r;
+
+        // We'll use the same tangent/bitangent for each vertex in the triangle
+        for &i in c {
+            tangents[i as usize] += tangent;
+            bitangents[i as usize] += bitangent;
+        }
+    }
+
+    // The vertices only keep the tangent, the bitangent just decides
+    // its handedness. The summed tangent is made perpendicular to the
+    // normal and normalized, which averages the triangles sharing it.
+    for (v, (tangent, bitangent)) in vertices
+        .iter_mut()
+        .zip(tangents.into_iter().zip(bitangents))
+    {
+        let normal = cgmath::Vector3::from(v.normal);
+        let tangent = orthonormal_tangent(normal, tangent);
+        let handedness = model::tangent_handedness(normal, tangent, bitangent);
+        v.tangent = tangent.extend(handedness).into();
+    }
+}
+
+// Smallest UV space triangle area, doubled, that tangents are solved for
+const DEGENERATE_UV_AREA: f32 = 1e-12;
+
+// tangent with the part along normal removed, or some direction
+// perpendicular to normal when nothing is left of it
+fn orthonormal_tangent(
+    normal: cgmath::Vector3<f32>,
+    tangent: cgmath::Vector3<f32>,
+) -> cgmath::Vector3<f32> {
+    use cgmath::InnerSpace;
+    let normal = if normal.magnitude2() > 0.0 {
+        normal.normalize()
+    } else {
+        cgmath::Vector3::unit_y()
+    };
+    let tangent = tangent - normal * normal.dot(tangent);
+    if tangent.magnitude2() > f32::EPSILON && tangent.magnitude2().is_finite() {
+        return tangent.normalize();
+    }
+    // Any axis that isn't close to the normal will do
+    let axis = if normal.x.abs() < 0.9 {
+        cgmath::Vector3::unit_x()
+    } else {
+        cgmath::Vector3::unit_z()
+    };
+    normal.cross(axis).normalize()
+}
+
+// Simplifies a mesh by snapping its vertices to a grid and merging every
+// vertex in a cell into the first one found there, dropping any triangles
+// that collapse. The result indexes into the same vertices as the full mesh
+// so LOD levels only need their own index buffer.
+fn decimate(vertices: &[model::ModelVertex], indices: &[u32], cell_size: f32) -> Vec<u32> {
+    let mut representatives = HashMap::new();
+    let remap = vertices
+        .iter()
+        .enumerate()
+        .map(|(i, v)| {
+            let cell = v.position.map(|p| (p / cell_size).floor() as i32);
+            *representatives.entry(cell).or_insert(i as u32)
+        })
+        .collect::<Vec<_>>();
+
+    indices
+        .chunks(3)
+        .filter_map(|c| {
+            let (a, b, c) = (
+                remap[c[0] as usize],
+                remap[c[1] as usize],
+                remap[c[2] as usize],
+            );
+            (a != b && b != c && a != c).then_some([a, b, c])
+        })
+        .flatten()
+        .collect()
 }
 
 pub async fn load_model(
@@ -75,6 +294,8 @@ pub async fn load_model(
     device: &wgpu::Device,
     queue: &wgpu::Queue,
     layout: &wgpu::BindGroupLayout,
+    mesh_layout: &wgpu::BindGroupLayout,
+    options: ImportOptions,
 ) -> anyhow::Result<model::Model> {
     let obj_text = load_string(file_name).await?;
     let obj_cursor = Cursor::new(obj_text);
@@ -88,7 +309,7 @@ pub async fn load_model(
             ..Default::default()
         },
         |p| async move {
-            let mat_text = load_string(&p).await.unwrap();
+            let mat_text = load_string(&resolve_relative(file_name, &p)).await.unwrap();
             tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text)))
         },
     )
@@ -96,16 +317,30 @@ pub async fn load_model(
 
     let mut materials = Vec::new();
     for m in obj_materials? {
-        let diffuse_texture = load_texture(&m.diffuse_texture, true, device, queue).await?;
-        let normal_texture = load_texture(&m.normal_texture, true, device, queue).await?;
-
-        materials.push(Material::new(
+        let diffuse_path = resolve_relative(file_name, &m.diffuse_texture);
+        let normal_path = resolve_relative(file_name, &m.normal_texture);
+        let diffuse_texture = load_texture(
+            &diffuse_path,
+            true,
+            texture::AddressModes::default(),
             device,
-            &m.name,
-            diffuse_texture,
-            normal_texture,
-            layout,
-        ));
+            queue,
+        )
+        .await?;
+        let normal_texture = load_texture(
+            &normal_path,
+            true,
+            texture::AddressModes::default(),
+            device,
+            queue,
+        )
+        .await?;
+
+        let mut material = Material::new(device, &m.name, diffuse_texture, normal_texture, layout);
+        if options.flip_green {
+            material.set_flip_green(queue, true);
+        }
+        materials.push(material);
     }
 
     let meshes = models
@@ -113,111 +348,99 @@ pub async fn load_model(
         .map(|m| {
             let mut vertices = (0..m.mesh.positions.len() / 3)
                 .map(|i| model::ModelVertex {
-                    position: [
+                    position: options.up_axis.to_y_up([
                         m.mesh.positions[i * 3],
                         m.mesh.positions[i * 3 + 1],
                         m.mesh.positions[i * 3 + 2],
-                    ],
+                    ]),
                     tex_coords: [m.mesh.texcoords[i * 2], m.mesh.texcoords[i * 2 + 1]],
-                    normal: [
+                    normal: options.up_axis.to_y_up([
                         m.mesh.normals[i * 3],
                         m.mesh.normals[i * 3 + 1],
                         m.mesh.normals[i * 3 + 2],
-                    ],
-                    // We'll calculate these later
-                    tangent: [0.0; 3],
-                    bitangent: [0.0; 3],
+                    ]),
+                    // We'll calculate these later. Since they're derived from
+                    // the converted positions they end up Y-up as well
+                    tangent: [0.0; 4],
                 })
                 .collect::<Vec<_>>();
 
-            let indices = &m.mesh.indices;
-            let mut triangles_included = vec![0; vertices.len()];
-
-            // Calculate tangents and bitangets. We're going to
-            // use the triangles, so we need to loop through the
-            // indices in chunks of 3
-            for c in indices.chunks(3) {
-                let v0 = vertices[c[0] as usize];
-                let v1 = vertices[c[1] as usize];
-                let v2 = vertices[c[2] as usize];
-
-                let pos0: cgmath::Vector3<_> = v0.position.into();
-                let pos1: cgmath::Vector3<_> = v1.position.into();
-                let pos2: cgmath::Vector3<_> = v2.position.into();
-
-                let uv0: cgmath::Vector2<_> = v0.tex_coords.into();
-                let uv1: cgmath::Vector2<_> = v1.tex_coords.into();
-                let uv2: cgmath::Vector2<_> = v2.tex_coords.into();
-
-                // Calculate the edges of the triangle
-                let delta_pos1 = pos1 - pos0;
-                let delta_pos2 = pos2 - pos0;
-
-                // This will give us a direction to calculate the
-                // tangent and bitangent
-                let delta_uv1 = uv1 - uv0;
-                let delta_uv2 = uv2 - uv0;
-
-                // Solving the following system of equations will
-                // give us the tangent and bitangent.
-                //     delta_pos1 = delta_uv1.x * T + delta_u.y * B
-                //     delta_pos2 = delta_uv2.x * T + delta_uv2.y * B
-                // Luckily, the place I found this equation provided
-                // the solution!
-                let r = 1.0 / (delta_uv1.x * delta_uv2.y - delta_uv1.y * delta_uv2.x);
-                let tangent = (delta_pos1 * delta_uv2.y - delta_pos2 * delta_uv1.y) * r;
-                // We flip the bitangent to enable right-handed normal
-                // maps with wgpu texture coordinate system
-                let bitangent = (delta_pos2 * delta_uv1.x - delta_pos1 * delta_uv2.x) * -r;
-
-                // We'll use the same tangent/bitangent for each vertex in the triangle
-                vertices[c[0] as usize].tangent =
-                    (tangent + cgmath::Vector3::from(vertices[c[0] as usize].tangent)).into();
-                vertices[c[1] as usize].tangent =
-                    (tangent + cgmath::Vector3::from(vertices[c[1] as usize].tangent)).into();
-                vertices[c[2] as usize].tangent =
-                    (tangent + cgmath::Vector3::from(vertices[c[2] as usize].tangent)).into();
-                vertices[c[0] as usize].bitangent =
-                    (bitangent + cgmath::Vector3::from(vertices[c[0] as usize].bitangent)).into();
-                vertices[c[1] as usize].bitangent =
-                    (bitangent + cgmath::Vector3::from(vertices[c[1] as usize].bitangent)).into();
-                vertices[c[2] as usize].bitangent =
-                    (bitangent + cgmath::Vector3::from(vertices[c[2] as usize].bitangent)).into();
-
-                // Used to average the tangents/bitangents
-                triangles_included[c[0] as usize] += 1;
-                triangles_included[c[1] as usize] += 1;
-                triangles_included[c[2] as usize] += 1;
-            }
+            compute_tangents(&mut vertices, &m.mesh.indices);
+
+            let vertex_buffer = memory::create_buffer_init(
+                device,
+                &wgpu::util::BufferInitDescriptor {
+                    label: Some(&format!("{:?} Vertex Buffer", file_name)),
+                    contents: bytemuck::cast_slice(&vertices),
+                    usage: wgpu::BufferUsages::VERTEX,
+                },
+            );
+            let index_buffer = memory::create_buffer_init(
+                device,
+                &wgpu::util::BufferInitDescriptor {
+                    label: Some(&format!("{:?} Index Buffer", file_name)),
+                    contents: bytemuck::cast_slice(&m.mesh.indices),
+                    usage: wgpu::BufferUsages::INDEX,
+                },
+            );
 
-            // Average the tangents/bitangents
-            for (i, n) in triangles_included.into_iter().enumerate() {
-                let denom = 1.0 / n as f32;
-                let mut v = &mut vertices[i];
-                v.tangent = (cgmath::Vector3::from(v.tangent) * denom).into();
-                v.bitangent = (cgmath::Vector3::from(v.bitangent) * denom).into();
+            // Each LOD level doubles the grid size used to merge vertices,
+            // starting at 1/16th of the mesh's largest dimension
+            let bounds = model::Bounds::from_vertices(&vertices);
+            let extent = bounds.extent();
+            let mut lods = Vec::new();
+            for level in 1..=options.lod_levels {
+                let cell_size = extent * 2.0_f32.powi(level as i32) / 32.0;
+                let lod_indices = decimate(&vertices, &m.mesh.indices, cell_size);
+                if lod_indices.is_empty() {
+                    break;
+                }
+                let index_buffer = memory::create_buffer_init(
+                    device,
+                    &wgpu::util::BufferInitDescriptor {
+                        label: Some(&format!("{:?} LOD {} Index Buffer", file_name, level)),
+                        contents: bytemuck::cast_slice(&lod_indices),
+                        usage: wgpu::BufferUsages::INDEX,
+                    },
+                );
+                lods.push(model::MeshLod {
+                    index_buffer,
+                    num_elements: lod_indices.len() as u32,
+                });
             }
 
-            let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Vertex Buffer", file_name)),
-                contents: bytemuck::cast_slice(&vertices),
-                usage: wgpu::BufferUsages::VERTEX,
-            });
-            let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Index Buffer", file_name)),
-                contents: bytemuck::cast_slice(&m.mesh.indices),
-                usage: wgpu::BufferUsages::INDEX,
-            });
+            let transform = cgmath::Matrix4::identity();
+            let transform_buffer = model::Mesh::create_transform_buffer_init(device, transform);
+            let transform_bind_group =
+                model::Mesh::create_bind_group(device, mesh_layout, &transform_buffer);
+            let (edge_buffer, num_edges) =
+                model::Mesh::create_edge_buffer_init(device, &vertices, &m.mesh.indices);
 
             model::Mesh {
                 name: file_name.to_string(),
                 vertex_buffer,
+                num_vertices: vertices.len() as u32,
                 index_buffer,
                 num_elements: m.mesh.indices.len() as u32,
+                lods,
+                bounds,
                 material: m.mesh.material_id.unwrap_or(0),
+                transform,
+                transform_buffer,
+                transform_bind_group,
+                edge_buffer,
+                num_edges,
             }
         })
         .collect::<Vec<_>>();
 
-    Ok(model::Model { meshes, materials })
+    let lod_distances = (1..=options.lod_levels)
+        .map(|level| DEFAULT_LOD_DISTANCE * level as f32)
+        .collect();
+
+    Ok(model::Model {
+        meshes,
+        materials,
+        lod_distances,
+    })
 }
@@ -0,0 +1,165 @@
+use anyhow::{bail, Context};
+use serde::Deserialize;
+
+use crate::{camera, instance, light, model, resources};
+
+// A scene file lists the models to load, where to place their instances,
+// the lights, and where the camera starts. In RON it looks like:
+//
+//     (
+//         models: [
+//             (
+//                 path: "cube.obj",
+//                 instances: [
+//                     (position: (0.0, 0.0, 0.0)),
+//                     (position: (3.0, 0.0, 0.0), rotation: (0.0, 45.0, 0.0)),
+//                 ],
+//             ),
+//         ],
+//         lights: [(position: (2.0, 2.0, 2.0), color: (1.0, 1.0, 1.0))],
+//         camera: Some((position: (0.0, 5.0, 10.0), yaw: -90.0, pitch: -20.0)),
+//     )
+//
+// JSON uses the same field names with arrays in place of tuples. Model
+// paths are relative to the scene file, and angles are in degrees.
+#[derive(Debug, Deserialize)]
+pub struct SceneDescription {
+    #[serde(default)]
+    pub models: Vec<ModelDescription>,
+    #[serde(default)]
+    pub lights: Vec<LightDescription>,
+    #[serde(default)]
+    pub camera: Option<CameraDescription>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ModelDescription {
+    pub path: String,
+    #[serde(default)]
+    pub up_axis: resources::UpAxis,
+    #[serde(default)]
+    pub instances: Vec<InstanceDescription>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct InstanceDescription {
+    pub position: [f32; 3],
+    // Euler angles about X, Y and Z
+    #[serde(default)]
+    pub rotation: [f32; 3],
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LightDescription {
+    pub position: [f32; 3],
+    pub color: [f32; 3],
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CameraDescription {
+    pub position: [f32; 3],
+    pub yaw: f32,
+    pub pitch: f32,
+}
+
+pub struct SceneModel {
+    // Resolved relative to the scene file
+    pub path: String,
+    pub up_axis: resources::UpAxis,
+    pub instances: Vec<instance::Instance>,
+}
+
+// CPU side of a scene. Nothing here touches the GPU, so a scene can be
+// loaded anywhere and handed to the thread that owns the device, which then
+// calls create_models.
+pub struct Scene {
+    pub models: Vec<SceneModel>,
+    pub lights: Vec<light::LightUniform>,
+    pub camera: Option<camera::Camera>,
+}
+
+impl Scene {
+    pub fn from_description(path: &str, description: SceneDescription) -> Self {
+        let models = description
+            .models
+            .into_iter()
+            .map(|m| SceneModel {
+                path: resources::resolve_relative(path, &m.path),
+                up_axis: m.up_axis,
+                instances: m
+                    .instances
+                    .iter()
+                    .map(|i| {
+                        let rotation = cgmath::Euler::new(
+                            cgmath::Deg(i.rotation[0]),
+                            cgmath::Deg(i.rotation[1]),
+                            cgmath::Deg(i.rotation[2]),
+                        );
+                        instance::Instance::with_rotation(i.position.into(), rotation.into())
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        let lights = description
+            .lights
+            .iter()
+            .map(|l| light::LightUniform::new_point(l.position, l.color))
+            .collect();
+
+        let camera = description
+            .camera
+            .map(|c| camera::Camera::new(c.position, cgmath::Deg(c.yaw), cgmath::Deg(c.pitch)));
+
+        Self {
+            models,
+            lights,
+            camera,
+        }
+    }
+
+    // Creates the GPU resources for every model in the scene, in the same
+    // order as Scene::models
+    pub async fn create_models(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+        mesh_layout: &wgpu::BindGroupLayout,
+    ) -> anyhow::Result<Vec<model::Model>> {
+        let mut models = Vec::with_capacity(self.models.len());
+        for (i, m) in self.models.iter().enumerate() {
+            let options = resources::ImportOptions {
+                up_axis: m.up_axis,
+                ..Default::default()
+            };
+            let model = resources::load_model(&m.path, device, queue, layout, mesh_layout, options)
+                .await
+                .with_context(|| format!("Failed to load scene model {} ({})", i, m.path))?;
+            models.push(model);
+        }
+
+        Ok(models)
+    }
+}
+
+// Reads a .ron or .json scene description
+pub async fn load_from_file(path: &str) -> anyhow::Result<Scene> {
+    let text = resources::load_string(path)
+        .await
+        .with_context(|| format!("Failed to read scene {}", path))?;
+
+    let extension = std::path::Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str());
+    let description: SceneDescription = match extension {
+        Some("ron") => {
+            ron::from_str(&text).with_context(|| format!("Failed to parse scene {}", path))?
+        }
+        Some("json") => serde_json::from_str(&text)
+            .with_context(|| format!("Failed to parse scene {}", path))?,
+        _ => bail!("Unsupported scene format {}, expected .ron or .json", path),
+    };
+
+    Ok(Scene::from_description(path, description))
+}
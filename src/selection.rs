@@ -0,0 +1,331 @@
+use crate::{
+    instance, memory,
+    model::{self, DrawModel, Vertex},
+    picking, renderer, settings, texture, State,
+};
+
+// Widest outline the edge pass looks for, in pixels
+pub const MAX_WIDTH: u32 = 8;
+
+// How State draws the outlines around the selection
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum OutlineMode {
+    // SelectionOutline's edges around the instance ids
+    #[default]
+    ScreenSpace,
+    // The selected instances are marked in the stencil buffer, then drawn
+    // again scaled up, in a fixed color, everywhere outside the mark. Shows
+    // through geometry in front and only suits shapes centered on their
+    // origin.
+    Stencil,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct OutlineUniform {
+    color: [f32; 4],
+    width: i32,
+    _padding: [u32; 3],
+}
+
+// Screen space outlines around the selected instances, see
+// State::set_selected. The selected instances are drawn with their ids into
+// an integer target and everything else around them with id 0, so whatever
+// is in front hides them, then a fullscreen pass colors the pixels next to
+// an id they don't share. Batched objects and skinned models don't hide the
+// selection.
+pub struct SelectionOutline {
+    // Linear, alpha is blended over the scene
+    pub color: [f32; 4],
+    // In pixels of the scene target, up to MAX_WIDTH
+    pub width: u32,
+    id_pipeline: wgpu::RenderPipeline,
+    occluder_pipeline: wgpu::RenderPipeline,
+    edge_pipeline: wgpu::RenderPipeline,
+    edge_bind_group_layout: wgpu::BindGroupLayout,
+    edge_bind_group: wgpu::BindGroup,
+    uniform_buffer: memory::Tracked<wgpu::Buffer>,
+    view: wgpu::TextureView,
+    depth_texture: texture::Texture,
+}
+
+impl SelectionOutline {
+    pub fn new(
+        device: &wgpu::Device,
+        scene_config: &wgpu::SurfaceConfiguration,
+        scene_format: wgpu::TextureFormat,
+        layout: &wgpu::PipelineLayout,
+    ) -> Self {
+        let id_shader = || wgpu::ShaderModuleDescriptor {
+            label: Some("Selection Id Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                settings::shader_source(include_str!("shaders/picking.wgsl")).into(),
+            ),
+        };
+        let create_pipeline = |fragment_entry_point, depth_compare| {
+            renderer::RenderPipeline::new(
+                device,
+                layout,
+                picking::ID_FORMAT,
+                Some(texture::Texture::DEPTH_FORMAT),
+                &[model::ModelVertex::desc(), instance::InstanceRaw::desc()],
+                id_shader(),
+                renderer::PipelineOptions {
+                    // Integer targets can't be blended
+                    blend: None,
+                    depth_compare,
+                    cull_mode: None,
+                    fragment_entry_point,
+                    ..Default::default()
+                },
+            )
+            .render_pipeline
+        };
+        // The selected instances are drawn again after the occluders, so
+        // they have to win at equal depth
+        let id_pipeline = create_pipeline("fs_main", wgpu::CompareFunction::LessEqual);
+        let occluder_pipeline = create_pipeline("fs_occluder", wgpu::CompareFunction::Less);
+
+        let edge_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Uint,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+                label: Some("selection_bind_group_layout"),
+            });
+        let edge_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Selection Outline Pipeline Layout"),
+            bind_group_layouts: &[&edge_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let edge_pipeline = renderer::RenderPipeline::new(
+            device,
+            &edge_layout,
+            scene_format,
+            None,
+            &[],
+            wgpu::ShaderModuleDescriptor {
+                label: Some("Selection Outline Shader"),
+                source: wgpu::ShaderSource::Wgsl(include_str!("shaders/selection.wgsl").into()),
+            },
+            renderer::PipelineOptions {
+                cull_mode: None,
+                ..renderer::PipelineOptions::transparent()
+            },
+        )
+        .render_pipeline;
+
+        let color = [1.0, 0.6, 0.1, 1.0];
+        let width = 2;
+        let uniform_buffer = memory::create_buffer_init(
+            device,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Selection Outline Buffer"),
+                contents: bytemuck::cast_slice(&[OutlineUniform {
+                    color,
+                    width: width as i32,
+                    _padding: [0; 3],
+                }]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            },
+        );
+
+        let (view, depth_texture) = Self::create_targets(device, scene_config);
+        let edge_bind_group =
+            Self::create_bind_group(device, &edge_bind_group_layout, &view, &uniform_buffer);
+
+        Self {
+            color,
+            width,
+            id_pipeline,
+            occluder_pipeline,
+            edge_pipeline,
+            edge_bind_group_layout,
+            edge_bind_group,
+            uniform_buffer,
+            view,
+            depth_texture,
+        }
+    }
+
+    // Has to follow the scene target's size
+    pub fn resize(&mut self, device: &wgpu::Device, scene_config: &wgpu::SurfaceConfiguration) {
+        let (view, depth_texture) = Self::create_targets(device, scene_config);
+        self.edge_bind_group = Self::create_bind_group(
+            device,
+            &self.edge_bind_group_layout,
+            &view,
+            &self.uniform_buffer,
+        );
+        self.view = view;
+        self.depth_texture = depth_texture;
+    }
+
+    // Draws the outlines over scene_view, which has to be the resolved
+    // scene target. Does nothing when none of the selection was drawn.
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        state: &State,
+        scene_view: &wgpu::TextureView,
+    ) {
+        let slots = state.selected_slots();
+        if slots.is_empty() {
+            return;
+        }
+        state.context.queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[OutlineUniform {
+                color: self.color,
+                width: self.width.clamp(1, MAX_WIDTH) as i32,
+                _padding: [0; 3],
+            }]),
+        );
+
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Selection Id Pass"),
+                color_attachments: &[wgpu::RenderPassColorAttachment {
+                    view: &self.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.depth_texture.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: false,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+
+            render_pass.set_pipeline(&self.occluder_pipeline);
+            render_pass.set_vertex_buffer(1, state.static_instance_buffer.slice(..));
+            for static_model in state.static_models() {
+                render_pass.draw_model_instanced(
+                    &static_model.model,
+                    0..1,
+                    &state.camera_bind_group,
+                    &state.light_bind_group,
+                );
+            }
+            for entry in &state.models {
+                render_pass.set_vertex_buffer(1, entry.instance_buffer.slice(..));
+                render_pass.draw_model_instanced(
+                    &entry.model,
+                    0..entry.instance_count,
+                    &state.camera_bind_group,
+                    &state.light_bind_group,
+                );
+            }
+            render_pass.set_vertex_buffer(1, state.instance_buffer.slice(..));
+            render_pass.draw_model_instanced(
+                &state.obj_model,
+                0..state.num_opaque_instances + state.num_fading_instances,
+                &state.camera_bind_group,
+                &state.light_bind_group,
+            );
+
+            // Ids are instance buffer slots plus one, like picking's
+            render_pass.set_pipeline(&self.id_pipeline);
+            for slot in slots {
+                render_pass.draw_model_instanced(
+                    &state.obj_model,
+                    slot..slot + 1,
+                    &state.camera_bind_group,
+                    &state.light_bind_group,
+                );
+            }
+        }
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Selection Outline Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: scene_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.edge_pipeline);
+        render_pass.set_bind_group(0, &self.edge_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    fn create_targets(
+        device: &wgpu::Device,
+        scene_config: &wgpu::SurfaceConfiguration,
+    ) -> (wgpu::TextureView, texture::Texture) {
+        let texture = memory::create_texture(
+            device,
+            &wgpu::TextureDescriptor {
+                label: Some("selection_id_texture"),
+                size: wgpu::Extent3d {
+                    width: scene_config.width,
+                    height: scene_config.height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: picking::ID_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+            },
+        );
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let depth_texture =
+            texture::Texture::create_depth_texture(device, scene_config, "selection_depth_texture");
+
+        (view, depth_texture)
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        view: &wgpu::TextureView,
+        uniform_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("selection_bind_group"),
+        })
+    }
+}
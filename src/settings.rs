@@ -0,0 +1,269 @@
+use crate::{camera, memory};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum DepthMode {
+    // The projection's depth as is. Most of the precision ends up close to
+    // the near plane.
+    #[default]
+    Linear,
+    // Depth is remapped to log2(1 + w) / log2(1 + zfar) in the vertex shader,
+    // which spreads precision evenly enough for planet sized scenes. Only
+    // perspective projections are remapped, orthographic ones have w = 1
+    // and linear depth to begin with.
+    Logarithmic,
+}
+
+impl DepthMode {
+    // The value the shaders expect in SettingsUniform::log_depth_coef, where
+    // 0 means linear depth
+    pub fn log_depth_coef(self, projection: &camera::Projection) -> f32 {
+        match self {
+            DepthMode::Logarithmic if !projection.is_orthographic() => {
+                1.0 / (projection.zfar() + 1.0).log2()
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+// Prepends settings.wgsl to source, for the shaders that bind
+// SettingsUniform or remap depth with apply_depth_mode. WGSL has no
+// includes.
+pub fn shader_source(source: &str) -> String {
+    format!("{}\n{}", include_str!("shaders/settings.wgsl"), source)
+}
+
+// The optional parts of the frame, switched together with
+// State::set_render_features so a settings UI or config file has one place
+// to drive. Anything turned off has its passes skipped.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct RenderFeatures {
+    // The scene target is multisampled, see antialiasing::AntiAliasing::Msaa.
+    // Turning it off while FXAA is on leaves FXAA alone.
+    pub msaa: bool,
+    // Outlines of the batched objects given a style with
+    // State::set_object_wireframe
+    pub wireframe: bool,
+    // See State::grid
+    pub grid: bool,
+    // The cube marking the point light, toggled with K. Only hides the
+    // marker, the light itself stays.
+    pub light_debug: bool,
+    // State::ground
+    pub ground: bool,
+    // Blurs whatever is away from State::focus_distance, more so with a
+    // larger aperture. An aperture of 0 keeps everything sharp.
+    pub depth_of_field: bool,
+    // Dissolves geometry within State::near_fade_distance of the near plane
+    // instead of letting the plane cut through it when the camera gets close
+    pub near_fade: bool,
+}
+
+impl Default for RenderFeatures {
+    fn default() -> Self {
+        Self {
+            msaa: false,
+            wireframe: true,
+            grid: false,
+            light_debug: true,
+            ground: true,
+            depth_of_field: false,
+            near_fade: false,
+        }
+    }
+}
+
+// The order opaque objects are drawn in. Drawing the closest ones first
+// lets the depth test throw away most of what's behind them before it gets
+// shaded, which FrameStats::model_pass_fragments shows in dense scenes.
+// Fading and blended objects are always drawn back to front.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum OpaqueSortMode {
+    // The order things were added in
+    None,
+    #[default]
+    FrontToBack,
+}
+
+// What the main shader writes out instead of the lit color, for tracking
+// down broken normal maps and UVs
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum DebugView {
+    #[default]
+    Shaded,
+    // The normal lighting uses, after the normal map
+    WorldNormals,
+    Uvs,
+    Tangents,
+    // Distance from the camera, spread out logarithmically up to the far
+    // plane
+    Depth,
+}
+
+impl DebugView {
+    pub fn next(self) -> Self {
+        match self {
+            DebugView::Shaded => DebugView::WorldNormals,
+            DebugView::WorldNormals => DebugView::Uvs,
+            DebugView::Uvs => DebugView::Tangents,
+            DebugView::Tangents => DebugView::Depth,
+            DebugView::Depth => DebugView::Shaded,
+        }
+    }
+}
+
+// How the instances of obj_model are drawn. The debug modes stand in for
+// the meshes, to check instancing and culling at a glance, and are colored
+// with each instance's color.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum InstanceDrawMode {
+    #[default]
+    Meshes,
+    // A pixel at each instance's origin
+    Points,
+    // The world space bounding box of each instance's mesh, as lines
+    Bounds,
+}
+
+impl InstanceDrawMode {
+    pub fn next(self) -> Self {
+        match self {
+            InstanceDrawMode::Meshes => InstanceDrawMode::Points,
+            InstanceDrawMode::Points => InstanceDrawMode::Bounds,
+            InstanceDrawMode::Bounds => InstanceDrawMode::Meshes,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SettingsUniform {
+    // Distance from the camera where instances start fading out
+    pub fade_start: f32,
+    // Distance from the camera where instances are fully transparent
+    pub fade_end: f32,
+    // See DepthMode::log_depth_coef
+    pub log_depth_coef: f32,
+    // Non zero when clip_plane is in use
+    clip_enabled: u32,
+    // Plane equation in world space, the side where
+    // dot(xyz, position) + w < 0 is cut away
+    clip_plane: [f32; 4],
+    // DebugView as 0 to 4, in declaration order
+    debug_view: u32,
+    // Scales log2(1 + distance) into [0, 1] for DebugView::Depth
+    debug_depth_coef: f32,
+    // Geometry closer to the camera than near_fade_end dissolves, fully
+    // gone at near_fade_start. Off when both are 0.
+    near_fade_start: f32,
+    near_fade_end: f32,
+}
+
+impl SettingsUniform {
+    pub fn new(fade_start: f32, fade_end: f32, log_depth_coef: f32) -> Self {
+        Self {
+            fade_start,
+            fade_end,
+            log_depth_coef,
+            clip_enabled: 0,
+            clip_plane: [0.0; 4],
+            debug_view: 0,
+            debug_depth_coef: 0.0,
+            near_fade_start: 0.0,
+            near_fade_end: 0.0,
+        }
+    }
+
+    pub fn with_clip_plane(self, clip_plane: Option<[f32; 4]>) -> Self {
+        Self {
+            clip_enabled: clip_plane.is_some() as u32,
+            clip_plane: clip_plane.unwrap_or([0.0; 4]),
+            ..self
+        }
+    }
+
+    pub fn with_debug_view(self, debug_view: DebugView, zfar: f32) -> Self {
+        Self {
+            debug_view: debug_view as u32,
+            debug_depth_coef: 1.0 / (zfar + 1.0).log2(),
+            ..self
+        }
+    }
+
+    // Fades out what's within distance of the near plane at znear, None
+    // leaves it to clip
+    pub fn with_near_fade(self, znear: f32, distance: Option<f32>) -> Self {
+        let (near_fade_start, near_fade_end) = match distance {
+            Some(distance) if distance > 0.0 => (znear, znear + distance),
+            _ => (0.0, 0.0),
+        };
+        Self {
+            near_fade_start,
+            near_fade_end,
+            ..self
+        }
+    }
+}
+
+pub fn create_buffer_init(
+    device: &wgpu::Device,
+    settings_uniform: SettingsUniform,
+) -> memory::Tracked<wgpu::Buffer> {
+    memory::create_buffer_init(
+        device,
+        &wgpu::util::BufferInitDescriptor {
+            label: Some("Settings Buffer"),
+            contents: bytemuck::cast_slice(&[settings_uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        },
+    )
+}
+
+// Per frame values any shader built with the camera bind group layout can
+// read at its binding 2, see camera::Camera::camera_bind_group_layout. That
+// is group 1 in the scene's pipeline layouts, which have no room for a
+// group of their own under the default limit of 4. Custom materials can
+// animate from it, like scrolling UVs by time.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct GlobalsUniform {
+    // Seconds the State has run for, not counting pauses
+    pub time: f32,
+    // Seconds since the last frame, 0 while paused
+    pub delta_time: f32,
+    // The scene target's size in pixels, which fragment positions are in
+    pub resolution: [f32; 2],
+}
+
+pub fn create_globals_buffer_init(
+    device: &wgpu::Device,
+    globals_uniform: GlobalsUniform,
+) -> memory::Tracked<wgpu::Buffer> {
+    memory::create_buffer_init(
+        device,
+        &wgpu::util::BufferInitDescriptor {
+            label: Some("Globals Buffer"),
+            contents: bytemuck::cast_slice(&[globals_uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_depth_is_off_for_orthographic_projections() {
+        let perspective = camera::Projection::new(800, 600, cgmath::Deg(45.0), 0.1, 100.0);
+        let orthographic =
+            camera::Projection::new_orthographic(800, 600, -1.0, 1.0, -1.0, 1.0, 0.1, 100.0);
+
+        let coef = DepthMode::Logarithmic.log_depth_coef(&perspective);
+        // The far plane lands on a depth of 1
+        assert!(((100.0f32 + 1.0).log2() * coef - 1.0).abs() < 1e-6);
+        assert_eq!(DepthMode::Logarithmic.log_depth_coef(&orthographic), 0.0);
+        assert_eq!(DepthMode::Linear.log_depth_coef(&perspective), 0.0);
+    }
+}
@@ -0,0 +1,34 @@
+// Vertex shader
+
+struct Background {
+    top: vec4<f32>;
+    bottom: vec4<f32>;
+};
+
+[[group(0), binding(0)]]
+var<uniform> background: Background;
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    // 0 at the bottom of the target, 1 at the top
+    [[location(0)]] height: f32;
+};
+
+// A single triangle that covers the whole target, at the far plane
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] vertex_index: u32) -> VertexOutput {
+    let x = f32(i32(vertex_index & 1u) * 4 - 1);
+    let y = f32(i32(vertex_index >> 1u) * 4 - 1);
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(x, y, 1.0, 1.0);
+    out.height = (y + 1.0) * 0.5;
+    return out;
+}
+
+// Fragment shader
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return mix(background.bottom, background.top, clamp(in.height, 0.0, 1.0));
+}
@@ -0,0 +1,95 @@
+// Vertex shader
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] tex_coords: vec2<f32>;
+};
+
+// A single triangle that covers the whole screen
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] vertex_index: u32) -> VertexOutput {
+    let x = f32(i32(vertex_index & 1u) * 4 - 1);
+    let y = f32(i32(vertex_index >> 1u) * 4 - 1);
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(x, y, 0.0, 1.0);
+    out.tex_coords = vec2<f32>((x + 1.0) * 0.5, (1.0 - y) * 0.5);
+    return out;
+}
+
+// Fragment shader
+
+struct DepthOfField {
+    // (1, 0) for the horizontal pass, (0, 1) for the vertical one
+    direction: vec2<f32>;
+    focus_distance: f32;
+    aperture: f32;
+    znear: f32;
+    zfar: f32;
+    // See settings::DepthMode::log_depth_coef
+    log_depth_coef: f32;
+    // Blur radius in pixels of anything fully out of focus
+    max_radius: f32;
+};
+
+[[group(0), binding(0)]]
+var t_color: texture_2d<f32>;
+[[group(0), binding(1)]]
+var s_color: sampler;
+// The depth buffer, bound as a float texture since naga can't write GLSL
+// for loads from depth textures. Swapped for texture_multisampled_2d with
+// MSAA, see dof.rs.
+[[group(0), binding(2)]]
+var t_depth: texture_2d<f32>;
+[[group(0), binding(3)]]
+var<uniform> dof: DepthOfField;
+
+// Taps on each side of the pixel
+let TAPS: i32 = 8;
+
+// Distance from the camera stored in the depth buffer at a pixel, undoing
+// the projection or the logarithmic depth
+fn view_distance(pixel: vec2<i32>) -> f32 {
+    let size = textureDimensions(t_depth);
+    let depth = textureLoad(t_depth, clamp(pixel, vec2<i32>(0), size - 1), 0).r;
+    if (dof.log_depth_coef > 0.0) {
+        return exp2(depth / dof.log_depth_coef) - 1.0;
+    }
+    return dof.znear * dof.zfar / (dof.zfar - depth * (dof.zfar - dof.znear));
+}
+
+// Circle of confusion radius in pixels
+fn coc(pixel: vec2<i32>) -> f32 {
+    let distance = view_distance(pixel);
+    let blur = dof.aperture * abs(distance - dof.focus_distance) / max(distance, 1e-4);
+    return min(blur, 1.0) * dof.max_radius;
+}
+
+// A gather blur along direction, run once per axis. Each tap only counts if
+// its own circle of confusion reaches the pixel, which keeps sharp things
+// from smearing over a blurred background.
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let size = vec2<f32>(textureDimensions(t_color));
+    let pixel = vec2<i32>(in.tex_coords * size);
+    let radius = coc(pixel);
+    let center = textureSampleLevel(t_color, s_color, in.tex_coords, 0.0);
+    if (radius < 0.5) {
+        return center;
+    }
+
+    var sum = center.rgb;
+    var weight = 1.0;
+    for (var i: i32 = -TAPS; i <= TAPS; i = i + 1) {
+        if (i == 0) {
+            continue;
+        }
+        let offset = f32(i) / f32(TAPS) * radius;
+        let tap_pixel = pixel + vec2<i32>(dof.direction * offset);
+        let tap_weight = clamp(coc(tap_pixel) - abs(offset) + 1.0, 0.0, 1.0);
+        let uv = in.tex_coords + dof.direction * offset / size;
+        sum = sum + textureSampleLevel(t_color, s_color, uv, 0.0).rgb * tap_weight;
+        weight = weight + tap_weight;
+    }
+    return vec4<f32>(sum / weight, center.a);
+}
@@ -0,0 +1,55 @@
+[[group(0), binding(0)]]
+var t_equirect: texture_2d<f32>;
+[[group(0), binding(1)]]
+var s_equirect: sampler;
+// One layer per cube face, in the +X, -X, +Y, -Y, +Z, -Z order wgpu expects
+[[group(0), binding(2)]]
+var t_cube: texture_storage_2d_array<rgba16float, write>;
+
+let PI: f32 = 3.14159265359;
+
+// World space direction through uv on a face, with uv from -1 to 1 and v
+// pointing down the face
+fn face_direction(face: u32, uv: vec2<f32>) -> vec3<f32> {
+    var direction: vec3<f32>;
+    switch (face) {
+        case 0u: {
+            direction = vec3<f32>(1.0, -uv.y, -uv.x);
+        }
+        case 1u: {
+            direction = vec3<f32>(-1.0, -uv.y, uv.x);
+        }
+        case 2u: {
+            direction = vec3<f32>(uv.x, 1.0, uv.y);
+        }
+        case 3u: {
+            direction = vec3<f32>(uv.x, -1.0, -uv.y);
+        }
+        case 4u: {
+            direction = vec3<f32>(uv.x, -uv.y, 1.0);
+        }
+        default: {
+            direction = vec3<f32>(-uv.x, -uv.y, -1.0);
+        }
+    }
+    return normalize(direction);
+}
+
+// Matches WORKGROUP_SIZE in cubemap.rs
+[[stage(compute), workgroup_size(8, 8, 1)]]
+fn main([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let size = textureDimensions(t_cube);
+    if (i32(id.x) >= size.x || i32(id.y) >= size.y) {
+        return;
+    }
+
+    let uv = (vec2<f32>(id.xy) + 0.5) / vec2<f32>(size) * 2.0 - 1.0;
+    let direction = face_direction(id.z, uv);
+    // Longitude across the image, latitude down it
+    let equirect_uv = vec2<f32>(
+        atan2(direction.z, direction.x) / (2.0 * PI) + 0.5,
+        acos(clamp(direction.y, -1.0, 1.0)) / PI,
+    );
+    let color = textureSampleLevel(t_equirect, s_equirect, equirect_uv, 0.0);
+    textureStore(t_cube, vec2<i32>(id.xy), i32(id.z), color);
+}
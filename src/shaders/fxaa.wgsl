@@ -0,0 +1,93 @@
+// Vertex shader
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] tex_coords: vec2<f32>;
+};
+
+// A single triangle that covers the whole screen
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] vertex_index: u32) -> VertexOutput {
+    let x = f32(i32(vertex_index & 1u) * 4 - 1);
+    let y = f32(i32(vertex_index >> 1u) * 4 - 1);
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(x, y, 0.0, 1.0);
+    out.tex_coords = vec2<f32>((x + 1.0) * 0.5, (1.0 - y) * 0.5);
+    return out;
+}
+
+// Fragment shader
+
+[[group(0), binding(0)]]
+var t_color: texture_2d<f32>;
+[[group(0), binding(1)]]
+var s_color: sampler;
+
+// Edges with less contrast than this, relative to the brightest neighbour,
+// are left alone
+let EDGE_THRESHOLD: f32 = 0.125;
+// Skips dark areas where the eye doesn't notice aliasing anyway
+let EDGE_THRESHOLD_MIN: f32 = 0.0312;
+let REDUCE_MUL: f32 = 0.125;
+let REDUCE_MIN: f32 = 0.0078125;
+// Furthest the blend reaches along an edge, in pixels
+let SPAN_MAX: f32 = 8.0;
+
+// Perceived brightness. The input is linear, the square root brings it close
+// to how the edge contrast is seen on screen.
+fn luma(color: vec3<f32>) -> f32 {
+    return sqrt(dot(color, vec3<f32>(0.299, 0.587, 0.114)));
+}
+
+fn sample_at(uv: vec2<f32>) -> vec3<f32> {
+    return textureSampleLevel(t_color, s_color, uv, 0.0).rgb;
+}
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_color));
+    let uv = in.tex_coords;
+
+    let color_m = sample_at(uv);
+    let luma_m = luma(color_m);
+    let luma_nw = luma(sample_at(uv + vec2<f32>(-1.0, -1.0) * texel));
+    let luma_ne = luma(sample_at(uv + vec2<f32>(1.0, -1.0) * texel));
+    let luma_sw = luma(sample_at(uv + vec2<f32>(-1.0, 1.0) * texel));
+    let luma_se = luma(sample_at(uv + vec2<f32>(1.0, 1.0) * texel));
+
+    let luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    let luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+    if (luma_max - luma_min < max(EDGE_THRESHOLD_MIN, luma_max * EDGE_THRESHOLD)) {
+        return vec4<f32>(color_m, 1.0);
+    }
+
+    // Points along the edge, perpendicular to the luma gradient
+    var direction = vec2<f32>(
+        -((luma_nw + luma_ne) - (luma_sw + luma_se)),
+        (luma_nw + luma_sw) - (luma_ne + luma_se),
+    );
+    let direction_reduce = max(
+        (luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * REDUCE_MUL,
+        REDUCE_MIN,
+    );
+    let scale = 1.0 / (min(abs(direction.x), abs(direction.y)) + direction_reduce);
+    direction = clamp(direction * scale, vec2<f32>(-SPAN_MAX), vec2<f32>(SPAN_MAX)) * texel;
+
+    let color_a = 0.5 * (
+        sample_at(uv + direction * (1.0 / 3.0 - 0.5)) +
+        sample_at(uv + direction * (2.0 / 3.0 - 0.5))
+    );
+    let color_b = color_a * 0.5 + 0.25 * (
+        sample_at(uv + direction * -0.5) +
+        sample_at(uv + direction * 0.5)
+    );
+
+    // The wider blend overshot into something else, fall back to the
+    // narrow one
+    let luma_b = luma(color_b);
+    if (luma_b < luma_min || luma_b > luma_max) {
+        return vec4<f32>(color_a, 1.0);
+    }
+    return vec4<f32>(color_b, 1.0);
+}
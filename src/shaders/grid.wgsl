@@ -0,0 +1,68 @@
+// Vertex shader
+
+struct Camera {
+    view_pos: vec4<f32>;
+    view_proj: mat4x4<f32>;
+};
+
+[[group(0), binding(0)]]
+var<uniform> camera: Camera;
+
+[[group(0), binding(1)]]
+var<uniform> settings: Settings;
+
+struct Grid {
+    color: vec4<f32>;
+    spacing: f32;
+    height: f32;
+    // Half the width of the quad, the lines have faded out by then
+    extent: f32;
+};
+
+[[group(1), binding(0)]]
+var<uniform> grid: Grid;
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] world_position: vec3<f32>;
+};
+
+// Two triangles facing up, centered under the camera so the grid never ends
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] vertex_index: u32) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(-1.0, 1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(1.0, -1.0),
+    );
+    let corner = corners[vertex_index] * grid.extent;
+    let world_position = vec3<f32>(
+        corner.x + camera.view_pos.x,
+        grid.height,
+        corner.y + camera.view_pos.z,
+    );
+
+    var out: VertexOutput;
+    out.clip_position = apply_depth_mode(camera.view_proj * vec4<f32>(world_position, 1.0), settings.log_depth_coef);
+    out.world_position = world_position;
+    return out;
+}
+
+// Fragment shader
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    // Distance to the nearest line in pixels, so lines stay about a pixel
+    // wide and antialiased at any distance
+    let coord = in.world_position.xz / grid.spacing;
+    let lines = abs(fract(coord - 0.5) - 0.5) / fwidth(coord);
+    let line = 1.0 - min(min(lines.x, lines.y), 1.0);
+
+    let view_distance = distance(in.world_position.xz, camera.view_pos.xz);
+    let fade = 1.0 - smoothStep(grid.extent * 0.5, grid.extent, view_distance);
+
+    return vec4<f32>(grid.color.rgb, grid.color.a * line * fade);
+}
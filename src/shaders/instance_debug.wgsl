@@ -0,0 +1,76 @@
+// Vertex shader
+
+struct Camera {
+    view_pos: vec4<f32>;
+    view_proj: mat4x4<f32>;
+};
+
+[[group(0), binding(0)]]
+var<uniform> camera: Camera;
+
+[[group(0), binding(1)]]
+var<uniform> settings: Settings;
+
+// The parts of instance::InstanceRaw these need
+struct InstanceInput {
+    [[location(5)]] model_matrix_0: vec4<f32>;
+    [[location(6)]] model_matrix_1: vec4<f32>;
+    [[location(7)]] model_matrix_2: vec4<f32>;
+    [[location(8)]] model_matrix_3: vec4<f32>;
+    [[location(14)]] color: vec4<f32>;
+};
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] color: vec4<f32>;
+};
+
+// One point per instance, at its origin
+[[stage(vertex)]]
+fn vs_point(instance: InstanceInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = apply_depth_mode(camera.view_proj * instance.model_matrix_3, settings.log_depth_coef);
+    out.color = instance.color;
+    return out;
+}
+
+// The 12 edges of the world space box around the instance's mesh, which
+// spans -1 to 1 on each axis like the cube model, two vertices each
+[[stage(vertex)]]
+fn vs_bounds(
+    [[builtin(vertex_index)]] vertex_index: u32,
+    instance: InstanceInput,
+) -> VertexOutput {
+    // Each group of 4 edges runs along one axis. The other two axes pick
+    // the edge's corner from the index's low bits.
+    let edge = vertex_index / 2u;
+    let axis = edge / 4u;
+    let along = f32(vertex_index % 2u) * 2.0 - 1.0;
+    let a = f32(edge & 1u) * 2.0 - 1.0;
+    let b = f32((edge >> 1u) & 1u) * 2.0 - 1.0;
+    var corner = vec3<f32>(along, a, b);
+    if (axis == 1u) {
+        corner = vec3<f32>(a, along, b);
+    } else if (axis == 2u) {
+        corner = vec3<f32>(a, b, along);
+    }
+
+    // Rotated and scaled, the box's extent on each world axis is the sum of
+    // the absolute columns
+    let extent = abs(instance.model_matrix_0.xyz)
+        + abs(instance.model_matrix_1.xyz)
+        + abs(instance.model_matrix_2.xyz);
+    let world_position = instance.model_matrix_3.xyz + corner * extent;
+
+    var out: VertexOutput;
+    out.clip_position = apply_depth_mode(camera.view_proj * vec4<f32>(world_position, 1.0), settings.log_depth_coef);
+    out.color = instance.color;
+    return out;
+}
+
+// Fragment shader
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return vec4<f32>(in.color.rgb, 1.0);
+}
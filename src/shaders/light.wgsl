@@ -8,13 +8,27 @@ struct Camera {
 [[group(0), binding(0)]]
 var<uniform> camera: Camera;
 
+[[group(0), binding(1)]]
+var<uniform> settings: Settings;
+
+// See light::LightUniform
 struct Light {
     position: vec3<f32>;
+    light_type: u32;
+    direction: vec3<f32>;
     color: vec3<f32>;
 };
 
+// See light::LightArray. Without storage buffers light::shader_source makes
+// this a uniform with room for MAX_LIGHTS.
+//#define MAX_LIGHTS 32u
+struct Lights {
+    count: u32;
+    lights: array<Light>;
+};
+
 [[group(1), binding(0)]]
-var<uniform> light: Light;
+var<storage, read> lights: Lights;
 
 struct VertexInput {
     [[location(0)]] position: vec3<f32>;
@@ -28,11 +42,22 @@ struct VertexOutput {
 [[stage(vertex)]]
 fn vs_main(
     model: VertexInput,
+    // One instance per light
+    [[builtin(instance_index)]] instance_index: u32,
 ) -> VertexOutput {
+    let light = lights.lights[instance_index];
     let scale = 0.25;
     var out: VertexOutput;
-    out.clip_position = camera.view_proj * vec4<f32>(model.position * scale + light.position, 1.0);
+    out.clip_position = apply_depth_mode(
+        camera.view_proj * vec4<f32>(model.position * scale + light.position, 1.0),
+        settings.log_depth_coef,
+    );
     out.color = light.color;
+    // Directional lights have nowhere to put a marker, so every vertex goes
+    // to the same point and the triangles are dropped
+    if (light.light_type != 0u) {
+        out.clip_position = vec4<f32>(0.0);
+    }
     return out;
 }
 
@@ -0,0 +1,64 @@
+// Vertex shader
+
+struct Camera {
+    view_pos: vec4<f32>;
+    view_proj: mat4x4<f32>;
+};
+
+[[group(1), binding(0)]]
+var<uniform> camera: Camera;
+
+[[group(1), binding(1)]]
+var<uniform> settings: Settings;
+
+struct MeshTransform {
+    model: mat4x4<f32>;
+    normal: mat3x3<f32>;
+};
+
+[[group(3), binding(0)]]
+var<uniform> mesh: MeshTransform;
+
+struct VertexInput {
+    [[location(0)]] position: vec3<f32>;
+};
+
+struct InstanceInput {
+    [[location(5)]] model_matrix_0: vec4<f32>;
+    [[location(6)]] model_matrix_1: vec4<f32>;
+    [[location(7)]] model_matrix_2: vec4<f32>;
+    [[location(8)]] model_matrix_3: vec4<f32>;
+};
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+};
+
+// How much bigger the outline is than the model
+let OUTLINE_SCALE: f32 = 1.06;
+let OUTLINE_COLOR: vec3<f32> = vec3<f32>(1.0, 0.6, 0.1);
+
+[[stage(vertex)]]
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    ) * mesh.model;
+
+    var out: VertexOutput;
+    let clip_position = camera.view_proj * model_matrix * vec4<f32>(model.position * OUTLINE_SCALE, 1.0);
+    out.clip_position = apply_depth_mode(clip_position, settings.log_depth_coef);
+    return out;
+}
+
+// Fragment shader
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return vec4<f32>(OUTLINE_COLOR, 1.0);
+}
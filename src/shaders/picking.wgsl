@@ -0,0 +1,70 @@
+// Vertex shader
+
+struct Camera {
+    view_pos: vec4<f32>;
+    view_proj: mat4x4<f32>;
+};
+
+[[group(1), binding(0)]]
+var<uniform> camera: Camera;
+
+[[group(1), binding(1)]]
+var<uniform> settings: Settings;
+
+struct MeshTransform {
+    model: mat4x4<f32>;
+    normal: mat3x3<f32>;
+};
+
+[[group(3), binding(0)]]
+var<uniform> mesh: MeshTransform;
+
+struct VertexInput {
+    [[location(0)]] position: vec3<f32>;
+};
+
+struct InstanceInput {
+    [[location(5)]] model_matrix_0: vec4<f32>;
+    [[location(6)]] model_matrix_1: vec4<f32>;
+    [[location(7)]] model_matrix_2: vec4<f32>;
+    [[location(8)]] model_matrix_3: vec4<f32>;
+};
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0), interpolate(flat)]] id: u32;
+};
+
+[[stage(vertex)]]
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+    [[builtin(instance_index)]] instance_index: u32,
+) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    ) * mesh.model;
+
+    var out: VertexOutput;
+    out.clip_position = apply_depth_mode(camera.view_proj * model_matrix * vec4<f32>(model.position, 1.0), settings.log_depth_coef);
+    // 0 is reserved for the background
+    out.id = instance_index + 1u;
+    return out;
+}
+
+// Fragment shader
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] u32 {
+    return in.id;
+}
+
+// Hides whatever is behind it without an id of its own, see
+// selection::SelectionOutline
+[[stage(fragment)]]
+fn fs_occluder(in: VertexOutput) -> [[location(0)]] u32 {
+    return 0u;
+}
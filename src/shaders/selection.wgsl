@@ -0,0 +1,64 @@
+// Vertex shader
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+};
+
+// A single triangle that covers the whole screen
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] vertex_index: u32) -> VertexOutput {
+    let x = f32(i32(vertex_index & 1u) * 4 - 1);
+    let y = f32(i32(vertex_index >> 1u) * 4 - 1);
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(x, y, 0.0, 1.0);
+    return out;
+}
+
+// Fragment shader
+
+struct Outline {
+    color: vec4<f32>;
+    // In pixels of the scene target
+    width: i32;
+};
+
+// Ids of the selected instances where they are visible, 0 elsewhere
+[[group(0), binding(0)]]
+var t_ids: texture_2d<u32>;
+[[group(0), binding(1)]]
+var<uniform> outline: Outline;
+
+// Colors the pixels within outline.width of a selected instance they don't
+// belong to, which leaves a band around each silhouette
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    var directions = array<vec2<i32>, 8>(
+        vec2<i32>(1, 0),
+        vec2<i32>(-1, 0),
+        vec2<i32>(0, 1),
+        vec2<i32>(0, -1),
+        vec2<i32>(1, 1),
+        vec2<i32>(-1, 1),
+        vec2<i32>(1, -1),
+        vec2<i32>(-1, -1),
+    );
+    let last = textureDimensions(t_ids) - vec2<i32>(1, 1);
+    let center = vec2<i32>(in.clip_position.xy);
+    let id = textureLoad(t_ids, center, 0).r;
+
+    var edge = false;
+    for (var distance: i32 = 1; distance <= outline.width; distance = distance + 1) {
+        for (var i: i32 = 0; i < 8; i = i + 1) {
+            let position = clamp(center + directions[i] * distance, vec2<i32>(0, 0), last);
+            let other = textureLoad(t_ids, position, 0).r;
+            if (other != 0u && other != id) {
+                edge = true;
+            }
+        }
+    }
+    if (!edge) {
+        discard;
+    }
+    return outline.color;
+}
@@ -0,0 +1,30 @@
+// Shared by the shaders that draw into the depth buffer, see
+// settings::shader_source. Each binds its own settings of this type.
+
+// See settings::SettingsUniform
+struct Settings {
+    fade_start: f32;
+    fade_end: f32;
+    log_depth_coef: f32;
+    clip_enabled: u32;
+    // Fragments where dot(clip_plane.xyz, world_position) + clip_plane.w < 0
+    // are discarded
+    clip_plane: vec4<f32>;
+    // See settings::DebugView
+    debug_view: u32;
+    debug_depth_coef: f32;
+    // See State::near_fade, off when both are 0
+    near_fade_start: f32;
+    near_fade_end: f32;
+};
+
+// Remaps clip space depth when logarithmic depth is enabled, which it is
+// when log_depth_coef is above 0, see settings::DepthMode::log_depth_coef.
+// The result is multiplied by w so it survives the perspective divide.
+fn apply_depth_mode(clip_position: vec4<f32>, log_depth_coef: f32) -> vec4<f32> {
+    if (log_depth_coef <= 0.0) {
+        return clip_position;
+    }
+    let depth = log2(max(1e-6, 1.0 + clip_position.w)) * log_depth_coef;
+    return vec4<f32>(clip_position.xy, depth * clip_position.w, clip_position.w);
+}
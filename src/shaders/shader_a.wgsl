@@ -8,20 +8,53 @@ struct Camera {
 [[group(1), binding(0)]]
 var<uniform> camera: Camera;
 
+[[group(1), binding(1)]]
+var<uniform> settings: Settings;
+
+// See settings::GlobalsUniform
+struct Globals {
+    time: f32;
+    delta_time: f32;
+    resolution: vec2<f32>;
+};
+
+[[group(1), binding(2)]]
+var<uniform> globals: Globals;
+
+// See light::LightUniform
 struct Light {
     position: vec3<f32>;
+    // light::LightType, 0 for point and 1 for directional
+    light_type: u32;
+    direction: vec3<f32>;
     color: vec3<f32>;
 };
 
+// See light::LightArray. Without storage buffers light::shader_source makes
+// this a uniform with room for MAX_LIGHTS.
+//#define MAX_LIGHTS 32u
+struct Lights {
+    count: u32;
+    lights: array<Light>;
+};
+
 [[group(2), binding(0)]]
-var<uniform> light: Light;
+var<storage, read> lights: Lights;
+
+struct MeshTransform {
+    model: mat4x4<f32>;
+    normal: mat3x3<f32>;
+};
+
+[[group(3), binding(0)]]
+var<uniform> mesh: MeshTransform;
 
 struct VertexInput {
     [[location(0)]] position: vec3<f32>;
     [[location(1)]] tex_coords: vec2<f32>;
     [[location(2)]] normal: vec3<f32>;
-    [[location(3)]] tangent: vec3<f32>;
-    [[location(4)]] bitangent: vec3<f32>;
+    // w is the handedness, see model::ModelVertex
+    [[location(3)]] tangent: vec4<f32>;
 };
 
 struct InstanceInput {
@@ -32,53 +65,73 @@ struct InstanceInput {
     [[location(9)]] normal_matrix_0: vec3<f32>;
     [[location(10)]] normal_matrix_1: vec3<f32>;
     [[location(11)]] normal_matrix_2: vec3<f32>;
+    [[location(14)]] color: vec4<f32>;
 };
 
 struct VertexOutput {
     [[builtin(position)]] clip_position: vec4<f32>;
     [[location(0)]] tex_coords: vec2<f32>;
-    [[location(1)]] tangent_position: vec3<f32>;
-    [[location(2)]] tangent_light_position: vec3<f32>;
-    [[location(3)]] tangent_view_position: vec3<f32>;
+    [[location(4)]] world_position: vec3<f32>;
+    [[location(5)]] world_normal: vec3<f32>;
+    [[location(6)]] world_tangent: vec3<f32>;
+    [[location(7)]] world_bitangent: vec3<f32>;
+    [[location(8)]] color: vec4<f32>;
 };
 
-[[stage(vertex)]]
-fn vs_main(
+// Everything the fragment shaders need from a vertex, shared with
+// vs_skinned in skinning.wgsl
+fn transform_vertex(
     model: VertexInput,
-    instance: InstanceInput,
+    model_matrix: mat4x4<f32>,
+    normal_matrix: mat3x3<f32>,
+    color: vec4<f32>,
 ) -> VertexOutput {
-let model_matrix = mat4x4<f32>(
+    // The tangent frame in world space
+    let world_normal = normalize(normal_matrix * model.normal);
+    let world_tangent = normalize(normal_matrix * model.tangent.xyz);
+    // A mirroring transform flips the cross product, so the handedness is
+    // flipped back to keep the bitangent where the matrix puts it
+    let mirrored = sign(determinant(normal_matrix));
+    let world_bitangent = normalize(cross(world_normal, world_tangent)) * model.tangent.w * mirrored;
+
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+
+    var out: VertexOutput;
+    out.clip_position = apply_depth_mode(camera.view_proj * world_position, settings.log_depth_coef);
+    out.tex_coords = model.tex_coords;
+    out.world_position = world_position.xyz;
+    out.world_normal = world_normal;
+    out.world_tangent = world_tangent;
+    out.world_bitangent = world_bitangent;
+    out.color = color;
+    return out;
+}
+
+fn instance_model_matrix(instance: InstanceInput) -> mat4x4<f32> {
+    return mat4x4<f32>(
         instance.model_matrix_0,
         instance.model_matrix_1,
         instance.model_matrix_2,
         instance.model_matrix_3,
     );
-    
-    let normal_matrix = mat3x3<f32>(
+}
+
+fn instance_normal_matrix(instance: InstanceInput) -> mat3x3<f32> {
+    return mat3x3<f32>(
         instance.normal_matrix_0,
         instance.normal_matrix_1,
         instance.normal_matrix_2,
     );
+}
 
-    // Construct the tangent matrix
-    let world_normal = normalize(normal_matrix * model.normal);
-    let world_tangent = normalize(normal_matrix * model.tangent);
-    let world_bitangent = normalize(normal_matrix * model.bitangent);
-    let tangent_matrix = transpose(mat3x3<f32>(
-        world_tangent,
-        world_bitangent,
-        world_normal,
-    ));
-
-    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
-
-    var out: VertexOutput;
-    out.clip_position = camera.view_proj * world_position;
-    out.tex_coords = model.tex_coords;
-    out.tangent_position = tangent_matrix * world_position.xyz;
-    out.tangent_view_position = tangent_matrix * camera.view_pos.xyz;
-    out.tangent_light_position = tangent_matrix * light.position;
-    return out;
+[[stage(vertex)]]
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let model_matrix = instance_model_matrix(instance) * mesh.model;
+    let normal_matrix = instance_normal_matrix(instance) * mesh.normal;
+    return transform_vertex(model, model_matrix, normal_matrix, instance.color);
 }
 
 // Fragment shader
@@ -92,28 +145,202 @@ var t_normal: texture_2d<f32>;
 [[group(0), binding(3)]]
 var s_normal: sampler;
 
-[[stage(fragment)]]
-fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
-    let object_color: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.tex_coords);
-    let object_normal: vec4<f32> = textureSample(t_normal, s_normal, in.tex_coords);
+// See model::Material's setters
+struct MaterialUniform {
+    two_sided: u32;
+    shininess: f32;
+    specular_intensity: f32;
+    flip_green: u32;
+    uv_scale: vec2<f32>;
+    uv_offset: vec2<f32>;
+};
+[[group(0), binding(4)]]
+var<uniform> material: MaterialUniform;
+
+// Where the material's textures are sampled for the mesh's UVs
+fn material_uv(tex_coords: vec2<f32>) -> vec2<f32> {
+    return tex_coords * material.uv_scale + material.uv_offset;
+}
+
+// The diffuse texture times the instance color, without any lighting
+fn unlit(in: VertexOutput) -> vec4<f32> {
+    return textureSample(t_diffuse, s_diffuse, material_uv(in.tex_coords)) * in.color;
+}
+
+// Lit color of the fragment, with the texture's alpha
+fn shade(in: VertexOutput, front_facing: bool) -> vec4<f32> {
+    let uv = material_uv(in.tex_coords);
+    let object_color: vec4<f32> = textureSample(t_diffuse, s_diffuse, uv) * in.color;
+    let object_normal: vec4<f32> = textureSample(t_normal, s_normal, uv);
     
     // We don't need (or want) much ambient light, so 0.1 is fine
     let ambient_strength = 0.1;
-    let ambient_color = light.color * ambient_strength;
 
-    // Create the lighting vectors
-    let tangent_normal = object_normal.xyz * 2.0 - 1.0;
-    let light_dir = normalize(in.tangent_light_position - in.tangent_position);
-    let view_dir = normalize(in.tangent_view_position - in.tangent_position);
-    let half_dir = normalize(view_dir + light_dir);
+    var tangent_normal = object_normal.xyz * 2.0 - 1.0;
+    if (material.flip_green != 0u) {
+        tangent_normal.y = -tangent_normal.y;
+    }
+    // The back of a two sided surface faces the other way, which in tangent
+    // space only flips z. Back faces only get here with culling off.
+    if (material.two_sided != 0u && !front_facing) {
+        tangent_normal.z = -tangent_normal.z;
+    }
+
+    // The lights are in world space, so the normal map is brought out of
+    // tangent space for them
+    let tangent_to_world = mat3x3<f32>(
+        normalize(in.world_tangent),
+        normalize(in.world_bitangent),
+        normalize(in.world_normal),
+    );
+    let world_normal = normalize(tangent_to_world * tangent_normal);
 
-    let diffuse_strength = max(dot(tangent_normal, light_dir), 0.0);
-    let diffuse_color = light.color * diffuse_strength;
+    switch (settings.debug_view) {
+        case 1u: {
+            return vec4<f32>(world_normal * 0.5 + 0.5, object_color.a);
+        }
+        case 2u: {
+            return vec4<f32>(fract(uv), 0.0, object_color.a);
+        }
+        case 3u: {
+            return vec4<f32>(normalize(in.world_tangent) * 0.5 + 0.5, object_color.a);
+        }
+        case 4u: {
+            let view_distance = distance(in.world_position, camera.view_pos.xyz);
+            let depth = clamp(log2(1.0 + view_distance) * settings.debug_depth_coef, 0.0, 1.0);
+            return vec4<f32>(vec3<f32>(depth), object_color.a);
+        }
+        default: {}
+    }
 
-    let specular_strength = pow(max(dot(tangent_normal, half_dir), 0.0), 32.0);
-    let specular_color = specular_strength * light.color;
+    let world_view_dir = normalize(camera.view_pos.xyz - in.world_position);
+    var lit = vec3<f32>(ambient_strength);
+    for (var i: u32 = 0u; i < lights.count; i = i + 1u) {
+        let light = lights.lights[i];
+        var to_light = -light.direction;
+        if (light.light_type == 0u) {
+            to_light = normalize(light.position - in.world_position);
+        }
+        let diffuse = max(dot(world_normal, to_light), 0.0);
+        let specular = pow(max(dot(world_normal, normalize(world_view_dir + to_light)), 0.0), material.shininess) * material.specular_intensity;
+        lit = lit + light.color * (diffuse + specular);
+    }
 
-    let result = (ambient_color + diffuse_color + specular_color) * object_color.xyz;
+    let result = lit * object_color.xyz;
 
     return vec4<f32>(result, object_color.a);
-}
\ No newline at end of file
+}
+
+// Fade out with distance from the camera
+fn distance_fade(world_position: vec3<f32>) -> f32 {
+    let view_distance = distance(world_position, camera.view_pos.xyz);
+    return 1.0 - smoothStep(settings.fade_start, settings.fade_end, view_distance);
+}
+
+// Whether the clip plane cuts this point away. The fragment entry points do
+// the discard themselves, since the GLSL naga writes for WebGL keeps every
+// function in every stage, and GLSL only allows discard in fragment shaders.
+fn clipped(world_position: vec3<f32>) -> bool {
+    return settings.clip_enabled != 0u && dot(settings.clip_plane.xyz, world_position) + settings.clip_plane.w < 0.0;
+}
+
+// Dissolves geometry close to the near plane with an ordered dither, which
+// works for opaque draws too and needs no sorting. Uses the distance to the
+// eye, which is a little more than the depth away from the screen's center.
+// Returns whether the fragment is dissolved, see clipped for why it doesn't
+// discard itself.
+fn near_faded(frag_position: vec4<f32>, world_position: vec3<f32>) -> bool {
+    if (settings.near_fade_end <= 0.0) {
+        return false;
+    }
+    let view_distance = distance(world_position, camera.view_pos.xyz);
+    let fade = smoothStep(settings.near_fade_start, settings.near_fade_end, view_distance);
+    let x = u32(frag_position.x) % 4u;
+    let y = u32(frag_position.y) % 4u;
+    // 4x4 Bayer matrix, a var since only those can be indexed dynamically
+    var bayer = array<f32, 16>(
+        0.0, 8.0, 2.0, 10.0,
+        12.0, 4.0, 14.0, 6.0,
+        3.0, 11.0, 1.0, 9.0,
+        15.0, 7.0, 13.0, 5.0,
+    );
+    return fade <= (bayer[y * 4u + x] + 0.5) / 16.0;
+}
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput, [[builtin(front_facing)]] front_facing: bool) -> [[location(0)]] vec4<f32> {
+    if (clipped(in.world_position)) {
+        discard;
+    }
+    if (near_faded(in.clip_position, in.world_position)) {
+        discard;
+    }
+    let color = shade(in, front_facing);
+    return vec4<f32>(color.rgb, color.a * distance_fade(in.world_position));
+}
+
+// For premultiplied alpha textures, the fade has to scale the color too
+[[stage(fragment)]]
+fn fs_premultiplied(in: VertexOutput, [[builtin(front_facing)]] front_facing: bool) -> [[location(0)]] vec4<f32> {
+    if (clipped(in.world_position)) {
+        discard;
+    }
+    if (near_faded(in.clip_position, in.world_position)) {
+        discard;
+    }
+    return shade(in, front_facing) * distance_fade(in.world_position);
+}
+
+// For the depth prepass, which writes no color. Still clips and fades, or the
+// discarded parts would hide what's behind them.
+[[stage(fragment)]]
+fn fs_depth(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    if (clipped(in.world_position)) {
+        discard;
+    }
+    if (near_faded(in.clip_position, in.world_position)) {
+        discard;
+    }
+    return vec4<f32>(0.0);
+}
+
+// Debug variants, see renderer::SHADER_VARIANTS
+
+// The diffuse texture and instance color without any lighting
+[[stage(fragment)]]
+fn fs_unlit(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    if (clipped(in.world_position)) {
+        discard;
+    }
+    if (near_faded(in.clip_position, in.world_position)) {
+        discard;
+    }
+    let color = unlit(in);
+    return vec4<f32>(color.rgb, color.a * distance_fade(in.world_position));
+}
+
+// World space vertex normals mapped from [-1, 1] to [0, 1]
+[[stage(fragment)]]
+fn fs_normals(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    if (clipped(in.world_position)) {
+        discard;
+    }
+    if (near_faded(in.clip_position, in.world_position)) {
+        discard;
+    }
+    let normal = normalize(in.world_normal) * 0.5 + 0.5;
+    return vec4<f32>(normal, distance_fade(in.world_position));
+}
+
+// Texture coordinates as red and green, wrapped to [0, 1], after the
+// material's scale and offset
+[[stage(fragment)]]
+fn fs_uvs(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    if (clipped(in.world_position)) {
+        discard;
+    }
+    if (near_faded(in.clip_position, in.world_position)) {
+        discard;
+    }
+    return vec4<f32>(fract(material_uv(in.tex_coords)), 0.0, distance_fade(in.world_position));
+}
@@ -0,0 +1,63 @@
+// Compute shader
+
+struct Params {
+    time: f32;
+    spacing: f32;
+    instances_per_row: u32;
+    instance_count: u32;
+};
+
+[[group(0), binding(0)]]
+var<uniform> params: Params;
+
+// Matches instance::InstanceRaw, a mat4x4 model matrix followed by a
+// mat3x3 normal matrix and a vec4 color. Storage buffer matrices would be
+// padded, so the entries are written as plain floats.
+let INSTANCE_FLOATS: u32 = 29u;
+
+struct Instances {
+    data: array<f32>;
+};
+
+[[group(0), binding(1)]]
+var<storage, read_write> instances: Instances;
+
+[[stage(compute), workgroup_size(64)]]
+fn cs_main([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let index = id.x;
+    if (index >= params.instance_count) {
+        return;
+    }
+
+    let half_row = f32(params.instances_per_row) / 2.0;
+    let x = params.spacing * (f32(index % params.instances_per_row) - half_row);
+    let z = params.spacing * (f32(index / params.instances_per_row) - half_row);
+    let y = 2.0 * sin(x * 0.2 + params.time) * cos(z * 0.2 + params.time);
+
+    let base = index * INSTANCE_FLOATS;
+    // Model matrix, column major with the translation in the last column
+    for (var i: u32 = 0u; i < 16u; i = i + 1u) {
+        instances.data[base + i] = 0.0;
+    }
+    instances.data[base + 0u] = 1.0;
+    instances.data[base + 5u] = 1.0;
+    instances.data[base + 10u] = 1.0;
+    instances.data[base + 12u] = x;
+    instances.data[base + 13u] = y;
+    instances.data[base + 14u] = z;
+    instances.data[base + 15u] = 1.0;
+
+    // Instances aren't rotated, so the normal matrix is the identity
+    for (var i: u32 = 0u; i < 9u; i = i + 1u) {
+        instances.data[base + 16u + i] = 0.0;
+    }
+    instances.data[base + 16u] = 1.0;
+    instances.data[base + 20u] = 1.0;
+    instances.data[base + 24u] = 1.0;
+
+    // The buffer's slots are in draw order rather than grid order, so the
+    // grid's gradient can't be kept and the instances are left white
+    for (var i: u32 = 0u; i < 4u; i = i + 1u) {
+        instances.data[base + 25u + i] = 1.0;
+    }
+}
@@ -0,0 +1,38 @@
+// Appended to shader_a.wgsl for skinning::Skinning, which draws with
+// vs_skinned and the fragment shaders from there
+
+struct SkinInput {
+    [[location(12)]] joints: vec4<u32>;
+    [[location(13)]] weights: vec4<f32>;
+};
+
+// See skinning::Skeleton::joint_matrices
+struct JointMatrices {
+    matrices: array<mat4x4<f32>>;
+};
+
+[[group(3), binding(1)]]
+var<storage, read> joints: JointMatrices;
+
+[[stage(vertex)]]
+fn vs_skinned(
+    model: VertexInput,
+    instance: InstanceInput,
+    skin: SkinInput,
+) -> VertexOutput {
+    let skin_matrix = joints.matrices[skin.joints.x] * skin.weights.x
+        + joints.matrices[skin.joints.y] * skin.weights.y
+        + joints.matrices[skin.joints.z] * skin.weights.z
+        + joints.matrices[skin.joints.w] * skin.weights.w;
+    // Joints are rotated, translated and uniformly scaled in practice, so
+    // the upper 3x3 transforms normals well enough without an inverse
+    let skin_normal = mat3x3<f32>(
+        skin_matrix.x.xyz,
+        skin_matrix.y.xyz,
+        skin_matrix.z.xyz,
+    );
+
+    let model_matrix = instance_model_matrix(instance) * mesh.model * skin_matrix;
+    let normal_matrix = instance_normal_matrix(instance) * mesh.normal * skin_normal;
+    return transform_vertex(model, model_matrix, normal_matrix, instance.color);
+}
@@ -0,0 +1,69 @@
+// Vertex shader
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] tex_coords: vec2<f32>;
+};
+
+// A single triangle that covers the whole screen
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] vertex_index: u32) -> VertexOutput {
+    let x = f32(i32(vertex_index & 1u) * 4 - 1);
+    let y = f32(i32(vertex_index >> 1u) * 4 - 1);
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(x, y, 0.0, 1.0);
+    out.tex_coords = vec2<f32>((x + 1.0) * 0.5, (1.0 - y) * 0.5);
+    return out;
+}
+
+// Fragment shader
+
+struct ToneMap {
+    exposure: f32;
+    // 0 = none, 1 = Reinhard, 2 = ACES
+    operator: u32;
+};
+
+[[group(0), binding(0)]]
+var t_hdr: texture_2d<f32>;
+[[group(0), binding(1)]]
+var s_hdr: sampler;
+[[group(0), binding(2)]]
+var<uniform> tone_map: ToneMap;
+
+fn reinhard(color: vec3<f32>) -> vec3<f32> {
+    return color / (color + vec3<f32>(1.0));
+}
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve
+fn aces(color: vec3<f32>) -> vec3<f32> {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return clamp((color * (a * color + b)) / (color * (c * color + d) + e), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let hdr = textureSample(t_hdr, s_hdr, in.tex_coords);
+    let color = hdr.rgb * tone_map.exposure;
+
+    var mapped: vec3<f32>;
+    switch (tone_map.operator) {
+        case 1u: {
+            mapped = reinhard(color);
+        }
+        case 2u: {
+            mapped = aces(color);
+        }
+        default: {
+            mapped = color;
+        }
+    }
+
+    // The swapchain is sRGB, so the conversion from linear happens on write
+    return vec4<f32>(mapped, hdr.a);
+}
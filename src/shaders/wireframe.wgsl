@@ -0,0 +1,116 @@
+// Vertex shader
+
+struct Camera {
+    view_pos: vec4<f32>;
+    view_proj: mat4x4<f32>;
+};
+
+[[group(0), binding(0)]]
+var<uniform> camera: Camera;
+
+[[group(0), binding(1)]]
+var<uniform> settings: Settings;
+
+struct Wireframe {
+    // The object's transform
+    model: mat4x4<f32>;
+    color: vec4<f32>;
+    // Size of the target in pixels
+    viewport: vec2<f32>;
+    // Line width in pixels
+    thickness: f32;
+};
+
+[[group(1), binding(0)]]
+var<uniform> wireframe: Wireframe;
+
+struct MeshTransform {
+    model: mat4x4<f32>;
+    normal: mat3x3<f32>;
+};
+
+[[group(2), binding(0)]]
+var<uniform> mesh: MeshTransform;
+
+// Pulls the lines in front of the faces they lie on, in clip space depth
+let DEPTH_BIAS: f32 = 0.0001;
+
+fn to_clip(position: vec3<f32>) -> vec4<f32> {
+    return camera.view_proj * wireframe.model * mesh.model * vec4<f32>(position, 1.0);
+}
+
+fn finish(clip_position: vec4<f32>) -> vec4<f32> {
+    let out = apply_depth_mode(clip_position, settings.log_depth_coef);
+    return vec4<f32>(out.xy, out.z - DEPTH_BIAS * out.w, out.w);
+}
+
+struct EdgeInput {
+    [[location(0)]] start: vec3<f32>;
+    [[location(1)]] end: vec3<f32>;
+};
+
+// One instance per edge, expanded into a quad of six vertices facing the
+// screen. Each end is pushed out by half the thickness too, so the lines
+// meet without gaps at corners.
+[[stage(vertex)]]
+fn vs_main(
+    [[builtin(vertex_index)]] vertex_index: u32,
+    edge: EdgeInput,
+) -> [[builtin(position)]] vec4<f32> {
+    var start = to_clip(edge.start);
+    var end = to_clip(edge.end);
+
+    // Cut the edge at the near plane, past it the perspective divide flips
+    // the direction of the line
+    if (start.z < 0.0 && end.z < 0.0) {
+        return vec4<f32>(0.0, 0.0, -1.0, 1.0);
+    }
+    if (start.z < 0.0) {
+        start = mix(start, end, start.z / (start.z - end.z));
+    }
+    if (end.z < 0.0) {
+        end = mix(end, start, end.z / (end.z - start.z));
+    }
+
+    let delta = (end.xy / end.w - start.xy / start.w) * wireframe.viewport;
+    let pixels = length(delta);
+    var direction = vec2<f32>(1.0, 0.0);
+    if (pixels > 1e-6) {
+        direction = delta / pixels;
+    }
+    let normal = vec2<f32>(-direction.y, direction.x);
+
+    // (end, side) of each corner, 0 or 1 for the end, -1 or 1 for the side
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+    );
+    let corner = corners[vertex_index];
+    var position = start;
+    if (corner.x > 0.5) {
+        position = end;
+    }
+
+    // Half the thickness in pixels is thickness / viewport in NDC, which
+    // spans 2 units across the viewport
+    let along = direction * (corner.x * 2.0 - 1.0);
+    let offset = (normal * corner.y + along) * wireframe.thickness / wireframe.viewport;
+    return finish(vec4<f32>(position.xy + offset * position.w, position.zw));
+}
+
+// With wgpu::PolygonMode::Line, for lines one pixel wide
+[[stage(vertex)]]
+fn vs_line([[location(0)]] position: vec3<f32>) -> [[builtin(position)]] vec4<f32> {
+    return finish(to_clip(position));
+}
+
+// Fragment shader
+
+[[stage(fragment)]]
+fn fs_main() -> [[location(0)]] vec4<f32> {
+    return wireframe.color;
+}
@@ -0,0 +1,538 @@
+use cgmath::{InnerSpace, Matrix4, Quaternion, SquareMatrix, Vector3, VectorSpace};
+
+use crate::model::{self, DrawModel, Vertex};
+use crate::{instance, memory, renderer, settings, texture};
+
+// Joints and weights of a skinned vertex, in a vertex buffer of their own
+// next to the mesh's ModelVertex buffer, so everything else that draws
+// meshes keeps working on skinned ones
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SkinVertex {
+    // Indices into Skeleton::joints
+    pub joints: [u32; 4],
+    // Sum to 1
+    pub weights: [f32; 4],
+}
+
+impl Vertex for SkinVertex {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<SkinVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                // After the instance attributes, see instance::InstanceRaw
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 12,
+                    format: wgpu::VertexFormat::Uint32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[u32; 4]>() as wgpu::BufferAddress,
+                    shader_location: 13,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+}
+
+// A joint's transform relative to its parent
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct JointTransform {
+    pub translation: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl JointTransform {
+    pub fn matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.translation)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+
+    pub fn lerp(&self, other: &Self, t: f32) -> Self {
+        Self {
+            translation: self.translation.lerp(other.translation, t),
+            rotation: slerp(self.rotation, other.rotation, t),
+            scale: self.scale.lerp(other.scale, t),
+        }
+    }
+}
+
+// Along the shorter way around
+fn slerp(a: Quaternion<f32>, b: Quaternion<f32>, t: f32) -> Quaternion<f32> {
+    let b = if a.dot(b) < 0.0 { -b } else { b };
+    a.slerp(b, t)
+}
+
+pub struct Joint {
+    pub name: String,
+    // Index into Skeleton::joints
+    pub parent: Option<usize>,
+    // The transform the joint has without an animation, see
+    // SkinnedModel::set_blend
+    pub bind_pose: JointTransform,
+    // Takes a vertex from model space into the joint's space
+    pub inverse_bind: Matrix4<f32>,
+    // What's above a root joint that isn't part of the skeleton, identity
+    // for the others
+    pub base: Matrix4<f32>,
+}
+
+pub struct Skeleton {
+    pub joints: Vec<Joint>,
+    // Indices into joints, parents before their children
+    order: Vec<usize>,
+}
+
+impl Skeleton {
+    pub fn new(joints: Vec<Joint>) -> Self {
+        let depth = |mut joint: usize| {
+            let mut depth = 0;
+            while let Some(parent) = joints[joint].parent {
+                joint = parent;
+                depth += 1;
+            }
+            depth
+        };
+        let mut order = (0..joints.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&joint| depth(joint));
+        Self { joints, order }
+    }
+
+    pub fn bind_pose(&self) -> Vec<JointTransform> {
+        self.joints.iter().map(|joint| joint.bind_pose).collect()
+    }
+
+    // What the vertex shader multiplies the vertices by, one per joint, for
+    // a pose with a transform per joint
+    pub fn joint_matrices(&self, pose: &[JointTransform]) -> Vec<[[f32; 4]; 4]> {
+        let mut globals = vec![Matrix4::identity(); self.joints.len()];
+        for &i in &self.order {
+            let joint = &self.joints[i];
+            let parent = match joint.parent {
+                Some(parent) => globals[parent],
+                None => joint.base,
+            };
+            globals[i] = parent * pose[i].matrix();
+        }
+        globals
+            .iter()
+            .zip(&self.joints)
+            .map(|(global, joint)| (global * joint.inverse_bind).into())
+            .collect()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Interpolation {
+    Step,
+    // Cubic spline channels are loaded as linear, keeping only their values
+    Linear,
+}
+
+pub enum ChannelValues {
+    Translation(Vec<Vector3<f32>>),
+    Rotation(Vec<Quaternion<f32>>),
+    Scale(Vec<Vector3<f32>>),
+}
+
+// Key frames for one property of one joint
+pub struct Channel {
+    pub joint: usize,
+    pub interpolation: Interpolation,
+    // Ascending, in seconds. There's a value for each.
+    pub times: Vec<f32>,
+    pub values: ChannelValues,
+}
+
+impl Channel {
+    // The keys around time and how far between them it is
+    fn keys(&self, time: f32) -> (usize, usize, f32) {
+        let next = self.times.partition_point(|&t| t <= time);
+        if next == 0 {
+            return (0, 0, 0.0);
+        }
+        if next == self.times.len() {
+            return (next - 1, next - 1, 0.0);
+        }
+        let (start, end) = (self.times[next - 1], self.times[next]);
+        let t = match self.interpolation {
+            Interpolation::Step => 0.0,
+            Interpolation::Linear => (time - start) / (end - start),
+        };
+        (next - 1, next, t)
+    }
+
+    fn apply(&self, time: f32, transform: &mut JointTransform) {
+        if self.times.is_empty() {
+            return;
+        }
+        let (a, b, t) = self.keys(time);
+        match &self.values {
+            ChannelValues::Translation(values) => {
+                transform.translation = values[a].lerp(values[b], t)
+            }
+            ChannelValues::Rotation(values) => {
+                transform.rotation = slerp(values[a], values[b], t).normalize()
+            }
+            ChannelValues::Scale(values) => transform.scale = values[a].lerp(values[b], t),
+        }
+    }
+}
+
+pub struct AnimationClip {
+    pub name: String,
+    // Seconds, the time of the last key
+    pub duration: f32,
+    pub channels: Vec<Channel>,
+}
+
+impl AnimationClip {
+    // Overwrites what the clip animates at time in pose and leaves the rest
+    pub fn sample(&self, time: f32, pose: &mut [JointTransform]) {
+        for channel in &self.channels {
+            channel.apply(time, &mut pose[channel.joint]);
+        }
+    }
+}
+
+struct Playback {
+    clip: usize,
+    time: f32,
+}
+
+// A model whose meshes are deformed by a skeleton, loaded with
+// gltf::load_skinned_model and drawn by Skinning. Meshes are drawn once, with
+// the transform set through set_transform.
+pub struct SkinnedModel {
+    pub model: model::Model,
+    // SkinVertex buffers, parallel to model.meshes
+    skin_buffers: Vec<memory::Tracked<wgpu::Buffer>>,
+    pub skeleton: Skeleton,
+    pub clips: Vec<AnimationClip>,
+    // The joint matrices, bound at group 3 with each mesh's transform
+    joint_buffer: memory::Tracked<wgpu::Buffer>,
+    playback: Option<Playback>,
+    blend: f32,
+    // Multiplies the time an animation advances by
+    pub speed: f32,
+    // Starts an animation over once it ends rather than holding the last
+    // frame
+    pub looping: bool,
+    transform: Matrix4<f32>,
+}
+
+impl SkinnedModel {
+    // The meshes' transform bind groups have to be built with
+    // Skinning::create_bind_group on joint_buffer.
+    pub fn new(
+        model: model::Model,
+        skin_buffers: Vec<memory::Tracked<wgpu::Buffer>>,
+        skeleton: Skeleton,
+        clips: Vec<AnimationClip>,
+        joint_buffer: memory::Tracked<wgpu::Buffer>,
+    ) -> Self {
+        Self {
+            model,
+            skin_buffers,
+            skeleton,
+            clips,
+            joint_buffer,
+            playback: None,
+            blend: 1.0,
+            speed: 1.0,
+            looping: true,
+            transform: Matrix4::identity(),
+        }
+    }
+
+    // A buffer the joint matrices of the skeleton fit into, starting out in
+    // the bind pose
+    pub fn create_joint_buffer(
+        device: &wgpu::Device,
+        skeleton: &Skeleton,
+    ) -> memory::Tracked<wgpu::Buffer> {
+        let mut matrices = skeleton.joint_matrices(&skeleton.bind_pose());
+        // Empty buffers can't be bound
+        if matrices.is_empty() {
+            matrices.push(Matrix4::identity().into());
+        }
+        memory::create_buffer_init(
+            device,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Joint Matrix Buffer"),
+                contents: bytemuck::cast_slice(&matrices),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            },
+        )
+    }
+
+    pub fn transform(&self) -> Matrix4<f32> {
+        self.transform
+    }
+
+    pub fn set_transform(&mut self, queue: &wgpu::Queue, transform: Matrix4<f32>) {
+        self.transform = transform;
+        for mesh in &mut self.model.meshes {
+            mesh.set_transform(queue, transform);
+        }
+    }
+
+    pub fn animation_names(&self) -> impl Iterator<Item = &str> {
+        self.clips.iter().map(|clip| clip.name.as_str())
+    }
+
+    // Starts the clip with the name from its beginning
+    pub fn play_animation(&mut self, name: &str) -> anyhow::Result<()> {
+        let clip = self
+            .clips
+            .iter()
+            .position(|clip| clip.name == name)
+            .ok_or_else(|| anyhow::anyhow!("No animation named {:?}", name))?;
+        self.playback = Some(Playback { clip, time: 0.0 });
+        Ok(())
+    }
+
+    // Puts the playing clip back at its beginning, keeping it playing
+    pub fn rewind_animation(&mut self) {
+        if let Some(playback) = &mut self.playback {
+            playback.time = 0.0;
+        }
+    }
+
+    // Goes back to the bind pose
+    pub fn stop_animation(&mut self) {
+        self.playback = None;
+    }
+
+    // The name of the clip playing, if any
+    pub fn animation(&self) -> Option<&str> {
+        self.playback
+            .as_ref()
+            .map(|playback| self.clips[playback.clip].name.as_str())
+    }
+
+    pub fn blend(&self) -> f32 {
+        self.blend
+    }
+
+    // How much of the animation shows, from 0 for the bind pose to 1 for
+    // just the clip. Changing it over a few frames eases an animation in or
+    // out.
+    pub fn set_blend(&mut self, blend: f32) {
+        self.blend = blend.clamp(0.0, 1.0);
+    }
+
+    // Advances the animation and uploads the pose it ends up in
+    pub fn update(&mut self, queue: &wgpu::Queue, dt: std::time::Duration) {
+        let bind_pose = self.skeleton.bind_pose();
+        let mut pose = bind_pose.clone();
+        if let Some(playback) = &mut self.playback {
+            let clip = &self.clips[playback.clip];
+            playback.time += dt.as_secs_f32() * self.speed;
+            if self.looping && clip.duration > 0.0 {
+                playback.time = playback.time.rem_euclid(clip.duration);
+            } else {
+                playback.time = playback.time.clamp(0.0, clip.duration);
+            }
+            clip.sample(playback.time, &mut pose);
+            for (transform, bind) in pose.iter_mut().zip(&bind_pose) {
+                *transform = bind.lerp(transform, self.blend);
+            }
+        }
+
+        let matrices = self.skeleton.joint_matrices(&pose);
+        queue.write_buffer(&self.joint_buffer, 0, bytemuck::cast_slice(&matrices));
+    }
+}
+
+// Draws SkinnedModels with vs_skinned from skinning.wgsl, which needs the
+// joint matrices in a storage buffer readable from vertex shaders. That
+// rules out WebGL2, see is_supported.
+pub struct Skinning {
+    // Group 3 of the skinned pipelines, the mesh transform and the joints
+    layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    pipeline: wgpu::RenderPipeline,
+    // For two sided materials, see model::Material::set_two_sided
+    two_sided_pipeline: wgpu::RenderPipeline,
+}
+
+impl Skinning {
+    pub fn is_supported(adapter: &wgpu::Adapter, device: &wgpu::Device) -> bool {
+        adapter
+            .get_downlevel_properties()
+            .flags
+            .contains(wgpu::DownlevelFlags::VERTEX_STORAGE)
+            && device.limits().max_storage_buffers_per_shader_stage > 0
+    }
+
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        texture_bind_group_layout: &wgpu::BindGroupLayout,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        light_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("skinned_mesh_bind_group_layout"),
+        });
+        let pipeline_layout = renderer::RenderPipeline::create_pipeline_layout(
+            device,
+            &[
+                texture_bind_group_layout,
+                camera_bind_group_layout,
+                light_bind_group_layout,
+                &layout,
+            ],
+        );
+        let (pipeline, two_sided_pipeline) =
+            Self::create_pipelines(device, &pipeline_layout, color_format, sample_count);
+
+        Self {
+            layout,
+            pipeline_layout,
+            pipeline,
+            two_sided_pipeline,
+        }
+    }
+
+    // For when the target the models are drawn into changes
+    pub fn recreate_pipelines(
+        &mut self,
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) {
+        let (pipeline, two_sided_pipeline) =
+            Self::create_pipelines(device, &self.pipeline_layout, color_format, sample_count);
+        self.pipeline = pipeline;
+        self.two_sided_pipeline = two_sided_pipeline;
+    }
+
+    fn create_pipelines(
+        device: &wgpu::Device,
+        pipeline_layout: &wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> (wgpu::RenderPipeline, wgpu::RenderPipeline) {
+        // WGSL has no includes, so the skinning vertex shader is appended to
+        // the main shader it shares everything else with
+        let source = settings::shader_source(&format!(
+            "{}\n{}",
+            include_str!("shaders/shader_a.wgsl"),
+            include_str!("shaders/skinning.wgsl")
+        ));
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Skinned Shader"),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+        let vertex_layouts = [
+            model::ModelVertex::desc(),
+            instance::InstanceRaw::desc(),
+            SkinVertex::desc(),
+        ];
+        let pipeline = |options: renderer::PipelineOptions| {
+            renderer::RenderPipeline::from_module(
+                device,
+                pipeline_layout,
+                color_format,
+                Some(texture::Texture::DEPTH_FORMAT),
+                &vertex_layouts,
+                &shader,
+                renderer::PipelineOptions {
+                    vertex_entry_point: "vs_skinned",
+                    sample_count,
+                    ..options
+                },
+            )
+            .render_pipeline
+        };
+
+        (
+            pipeline(renderer::PipelineOptions::default()),
+            pipeline(renderer::PipelineOptions::default().two_sided()),
+        )
+    }
+
+    // The group 3 bind group for a mesh of a skinned model
+    pub fn create_bind_group(
+        &self,
+        device: &wgpu::Device,
+        transform_buffer: &wgpu::Buffer,
+        joint_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: transform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: joint_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("skinned_mesh_bind_group"),
+        })
+    }
+
+    // Draws the models as opaque. instance_buffer needs a single identity
+    // instance.
+    pub fn render<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        models: &'a [SkinnedModel],
+        instance_buffer: &'a wgpu::Buffer,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    ) {
+        render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+        for skinned in models {
+            for (mesh, skin_buffer) in skinned.model.meshes.iter().zip(&skinned.skin_buffers) {
+                let material = &skinned.model.materials[mesh.material];
+                render_pass.set_pipeline(if material.two_sided() {
+                    &self.two_sided_pipeline
+                } else {
+                    &self.pipeline
+                });
+                render_pass.set_vertex_buffer(2, skin_buffer.slice(..));
+                render_pass.draw_mesh_instanced(
+                    mesh,
+                    material,
+                    0..1,
+                    camera_bind_group,
+                    light_bind_group,
+                );
+            }
+        }
+    }
+}
@@ -3,10 +3,64 @@ use std::num::NonZeroU32;
 use anyhow::*;
 use image::GenericImageView;
 
+use crate::memory;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum AlphaMode {
+    // Color is stored independently of alpha, as most PNGs are
+    #[default]
+    Straight,
+    // Color is multiplied by alpha on upload. Filtering premultiplied texels
+    // doesn't bleed the color of fully transparent texels into the edges, so
+    // cutouts don't get dark fringes.
+    Premultiplied,
+}
+
+// What the sampler does with texture coordinates outside [0, 1] on each axis
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AddressModes {
+    pub u: wgpu::AddressMode,
+    pub v: wgpu::AddressMode,
+    pub w: wgpu::AddressMode,
+}
+
+impl AddressModes {
+    pub fn all(mode: wgpu::AddressMode) -> Self {
+        Self {
+            u: mode,
+            v: mode,
+            w: mode,
+        }
+    }
+
+    // Tiles the texture, for things like ground textures on UVs past 1
+    pub fn repeat() -> Self {
+        Self::all(wgpu::AddressMode::Repeat)
+    }
+
+    // Stretches the edge texels, so nothing from the opposite edge bleeds in
+    pub fn clamp() -> Self {
+        Self::all(wgpu::AddressMode::ClampToEdge)
+    }
+}
+
+impl Default for AddressModes {
+    fn default() -> Self {
+        Self::clamp()
+    }
+}
+
 pub struct Texture {
-    pub texture: wgpu::Texture,
+    pub texture: memory::Tracked<wgpu::Texture>,
+    // Array layers count as depth, six for a cubemap
+    pub size: wgpu::Extent3d,
+    // wgpu can't be asked for these, and generate_mipmaps needs them
+    pub format: wgpu::TextureFormat,
+    pub mip_level_count: u32,
     pub view: wgpu::TextureView,
     pub sampler: wgpu::Sampler,
+    pub alpha_mode: AlphaMode,
+    pub address_modes: AddressModes,
 }
 
 impl Texture {
@@ -16,9 +70,19 @@ impl Texture {
         bytes: &[u8],
         label: &str,
         is_normal_map: bool,
+        alpha_mode: AlphaMode,
+        address_modes: AddressModes,
     ) -> Result<Self> {
         let img = image::load_from_memory(bytes)?;
-        Self::from_image(device, queue, &img, Some(label), is_normal_map)
+        Self::from_image(
+            device,
+            queue,
+            &img,
+            Some(label),
+            is_normal_map,
+            alpha_mode,
+            address_modes,
+        )
     }
 
     pub fn from_image(
@@ -27,27 +91,45 @@ impl Texture {
         img: &image::DynamicImage,
         label: Option<&str>,
         is_normal_map: bool,
+        alpha_mode: AlphaMode,
+        address_modes: AddressModes,
     ) -> Result<Self> {
-        let rgba = img.to_rgba8();
+        let mut rgba = img.to_rgba8();
         let dimensions = img.dimensions();
 
+        if alpha_mode == AlphaMode::Premultiplied {
+            // This is done on the stored (sRGB encoded) values, which is
+            // what the blend hardware expects to undo
+            for pixel in rgba.pixels_mut() {
+                let a = pixel[3] as u32;
+                for c in &mut pixel.0[..3] {
+                    *c = ((*c as u32 * a + 127) / 255) as u8;
+                }
+            }
+        }
+
         let size = wgpu::Extent3d {
             width: dimensions.0,
             height: dimensions.1,
             depth_or_array_layers: 1,
         };
-        let texture = device.create_texture(&wgpu::TextureDescriptor {
+        let format = if is_normal_map {
+            wgpu::TextureFormat::Rgba8Unorm
+        } else {
+            wgpu::TextureFormat::Rgba8UnormSrgb
+        };
+        let mip_level_count = size.max_mips();
+        let texture = memory::create_texture(device, &wgpu::TextureDescriptor {
             label,
             size,
-            mip_level_count: 1,
+            mip_level_count,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: if is_normal_map {
-                wgpu::TextureFormat::Rgba8Unorm
-            } else {
-                wgpu::TextureFormat::Rgba8UnormSrgb
-            },
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            format,
+            // Copied from and to by generate_mipmaps
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::COPY_SRC,
         });
 
         queue.write_texture(
@@ -68,30 +150,163 @@ impl Texture {
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            address_mode_u: address_modes.u,
+            address_mode_v: address_modes.v,
+            address_mode_w: address_modes.w,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let texture = Self {
+            texture,
+            size,
+            format,
+            mip_level_count,
+            view,
+            sampler,
+            alpha_mode,
+            address_modes,
+        };
+        generate_mipmaps(device, queue, &texture)?;
+        Ok(texture)
+    }
+
+    // Format of textures from from_hdr_bytes. Half floats are filterable
+    // everywhere, unlike Rgba32Float.
+    pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+    pub fn load_hdr<P: AsRef<std::path::Path>>(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        path: P,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::from_hdr_bytes(device, queue, &bytes, &path.to_string_lossy())
+    }
+
+    // Loads a Radiance .hdr or OpenEXR image, keeping the values above 1.
+    // Anything else is refused rather than treated as linear.
+    pub fn from_hdr_bytes(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bytes: &[u8],
+        label: &str,
+    ) -> Result<Self> {
+        let format = image::guess_format(bytes)
+            .with_context(|| format!("{} isn't an image format we can recognize", label))?;
+        if !matches!(format, image::ImageFormat::Hdr | image::ImageFormat::OpenExr) {
+            bail!("{} is {:?}, only .hdr and .exr images can be loaded as HDR", label, format);
+        }
+        let img = image::load_from_memory_with_format(bytes, format)
+            .with_context(|| format!("Failed to decode {}", label))?;
+
+        let (width, height) = img.dimensions();
+        let max_size = device.limits().max_texture_dimension_2d;
+        if width == 0 || height == 0 {
+            bail!("{} is empty", label);
+        }
+        if width > max_size || height > max_size {
+            bail!(
+                "{} is {}x{}, larger than the {} pixels textures can be on this device",
+                label,
+                width,
+                height,
+                max_size
+            );
+        }
+
+        let texels = img
+            .to_rgba32f()
+            .into_raw()
+            .into_iter()
+            .map(|c| half::f16::from_f32(c).to_bits())
+            .collect::<Vec<_>>();
+
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let texture = memory::create_texture(device, &wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::HDR_FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        });
+
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            bytemuck::cast_slice(&texels),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                // Four channels of two bytes each
+                bytes_per_row: NonZeroU32::new(8 * width),
+                rows_per_image: NonZeroU32::new(height),
+            },
+            size,
+        );
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let address_modes = AddressModes {
+            // Equirectangular images wrap around horizontally
+            u: wgpu::AddressMode::Repeat,
+            ..AddressModes::clamp()
+        };
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: address_modes.u,
+            address_mode_v: address_modes.v,
+            address_mode_w: address_modes.w,
             mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Linear,
             mipmap_filter: wgpu::FilterMode::Nearest,
             ..Default::default()
         });
 
         Ok(Self {
             texture,
+            size,
+            format: Self::HDR_FORMAT,
+            mip_level_count: 1,
             view,
             sampler,
+            alpha_mode: AlphaMode::Straight,
+            address_modes,
         })
     }
 }
 
 impl Texture {
-    pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float; // 1.
+    // The stencil aspect is there for pipelines built with
+    // renderer::PipelineOptions::stencil_mask and outline
+    pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth24PlusStencil8; // 1.
 
     pub fn create_depth_texture(
         device: &wgpu::Device,
         config: &wgpu::SurfaceConfiguration,
         label: &str,
+    ) -> Self {
+        Self::create_multisampled_depth_texture(device, config, 1, label)
+    }
+
+    // For passes with a multisampled color target, which needs a depth
+    // texture with the same sample count
+    pub fn create_multisampled_depth_texture(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
+        label: &str,
     ) -> Self {
         let size = wgpu::Extent3d {
             // 2.
@@ -103,13 +318,13 @@ impl Texture {
             label: Some(label),
             size,
             mip_level_count: 1,
-            sample_count: 1,
+            sample_count,
             dimension: wgpu::TextureDimension::D2,
             format: Self::DEPTH_FORMAT,
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT // 3.
                 | wgpu::TextureUsages::TEXTURE_BINDING,
         };
-        let texture = device.create_texture(&desc);
+        let texture = memory::create_texture(device, &desc);
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
@@ -128,8 +343,13 @@ impl Texture {
 
         Self {
             texture,
+            size,
+            format: Self::DEPTH_FORMAT,
+            mip_level_count: 1,
             view,
             sampler,
+            alpha_mode: AlphaMode::Straight,
+            address_modes: AddressModes::clamp(),
         }
     }
 
@@ -168,8 +388,190 @@ impl Texture {
                     ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                     count: None,
                 },
+                // model::MaterialUniform
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
             ],
             label: Some("texture_bind_group_layout"),
         })
     }
 }
+
+// Fills in every mip level past the first from the one above it, for each
+// array layer, once level 0 has been uploaded. Each level is a half size
+// blit in the texture's own format, so sRGB texels are decoded, filtered in
+// linear and encoded again on write. The blits go through single level
+// scratch textures that are copied back into the texture, since the GL
+// backend can only bind whole textures and would sample the level being
+// written. The texture needs COPY_SRC and COPY_DST usage and a format that
+// can be rendered to and filtered.
+pub fn generate_mipmaps(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &Texture,
+) -> Result<()> {
+    if texture.mip_level_count <= 1 {
+        return Ok(());
+    }
+    let features = texture.format.describe().guaranteed_format_features;
+    if !features.filterable
+        || !features
+            .allowed_usages
+            .contains(wgpu::TextureUsages::RENDER_ATTACHMENT)
+    {
+        bail!(
+            "Can't generate mipmaps for {:?}, which has to be filterable and renderable",
+            texture.format
+        );
+    }
+
+    let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+        label: Some("Mipmap Shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("shaders/blit.wgsl").into()),
+    });
+    let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+        ],
+        label: Some("mipmap_bind_group_layout"),
+    });
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Mipmap Pipeline Layout"),
+        bind_group_layouts: &[&bind_group_layout],
+        push_constant_ranges: &[],
+    });
+    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Mipmap Pipeline"),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[texture.format.into()],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    });
+    let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+        label: Some("mipmap_sampler"),
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Linear,
+        ..Default::default()
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Mipmap Encoder"),
+    });
+    let level_size = |mip: u32| wgpu::Extent3d {
+        width: (texture.size.width >> mip).max(1),
+        height: (texture.size.height >> mip).max(1),
+        depth_or_array_layers: 1,
+    };
+    // One per level, reused for every layer
+    let scratch = (0..texture.mip_level_count)
+        .map(|mip| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("Mipmap Scratch Texture"),
+                size: level_size(mip),
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: texture.format,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::COPY_SRC
+                    | wgpu::TextureUsages::COPY_DST,
+            })
+        })
+        .collect::<Vec<_>>();
+    let views = scratch
+        .iter()
+        .map(|scratch| scratch.create_view(&wgpu::TextureViewDescriptor::default()))
+        .collect::<Vec<_>>();
+    let level = |mip: u32, layer: u32| wgpu::ImageCopyTexture {
+        texture: &texture.texture,
+        mip_level: mip,
+        origin: wgpu::Origin3d {
+            x: 0,
+            y: 0,
+            z: layer,
+        },
+        aspect: wgpu::TextureAspect::All,
+    };
+    for layer in 0..texture.size.depth_or_array_layers {
+        encoder.copy_texture_to_texture(
+            level(0, layer),
+            scratch[0].as_image_copy(),
+            level_size(0),
+        );
+        for mip in 1..texture.mip_level_count as usize {
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&views[mip - 1]),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&sampler),
+                    },
+                ],
+                label: Some("mipmap_bind_group"),
+            });
+            {
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Mipmap Pass"),
+                    color_attachments: &[wgpu::RenderPassColorAttachment {
+                        view: &views[mip],
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                            store: true,
+                        },
+                    }],
+                    depth_stencil_attachment: None,
+                });
+                render_pass.set_pipeline(&pipeline);
+                render_pass.set_bind_group(0, &bind_group, &[]);
+                render_pass.draw(0..3, 0..1);
+            }
+            encoder.copy_texture_to_texture(
+                scratch[mip].as_image_copy(),
+                level(mip as u32, layer),
+                level_size(mip as u32),
+            );
+        }
+    }
+    queue.submit(std::iter::once(encoder.finish()));
+    Ok(())
+}
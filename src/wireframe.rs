@@ -0,0 +1,271 @@
+use std::collections::HashMap;
+
+use crate::{batch, memory, model, renderer, settings, texture};
+use model::Vertex;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct WireframeStyle {
+    pub color: [f32; 4],
+    // Line width in physical pixels
+    pub thickness: f32,
+}
+
+impl Default for WireframeStyle {
+    fn default() -> Self {
+        Self {
+            color: [1.0, 1.0, 1.0, 1.0],
+            thickness: 1.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct WireframeUniform {
+    model: [[f32; 4]; 4],
+    color: [f32; 4],
+    viewport: [f32; 2],
+    thickness: f32,
+    _padding: f32,
+}
+
+struct ObjectWireframe {
+    style: WireframeStyle,
+    uniform_buffer: memory::Tracked<wgpu::Buffer>,
+    bind_group: wgpu::BindGroup,
+}
+
+// Draws the triangle edges of batched objects on top of them. Lines are
+// quads expanded in the vertex shader, one instance per edge from
+// model::Mesh::edge_buffer, since PolygonMode::Line only ever gives one
+// pixel wide lines. Those are still used for thickness 1 where the adapter
+// supports them.
+pub struct Wireframe {
+    layout: wgpu::BindGroupLayout,
+    thick_pipeline: wgpu::RenderPipeline,
+    // None without Features::POLYGON_MODE_LINE
+    line_pipeline: Option<wgpu::RenderPipeline>,
+    objects: HashMap<batch::ObjectId, ObjectWireframe>,
+}
+
+impl Wireframe {
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        mesh_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("wireframe_bind_group_layout"),
+        });
+        let (thick_pipeline, line_pipeline) = Self::create_pipelines(
+            device,
+            color_format,
+            sample_count,
+            &[camera_bind_group_layout, &layout, mesh_bind_group_layout],
+        );
+
+        Self {
+            layout,
+            thick_pipeline,
+            line_pipeline,
+            objects: HashMap::new(),
+        }
+    }
+
+    // For when the target the wireframes are drawn into changes
+    pub fn recreate_pipelines(
+        &mut self,
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        mesh_bind_group_layout: &wgpu::BindGroupLayout,
+    ) {
+        let (thick_pipeline, line_pipeline) = Self::create_pipelines(
+            device,
+            color_format,
+            sample_count,
+            &[
+                camera_bind_group_layout,
+                &self.layout,
+                mesh_bind_group_layout,
+            ],
+        );
+        self.thick_pipeline = thick_pipeline;
+        self.line_pipeline = line_pipeline;
+    }
+
+    fn create_pipelines(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+    ) -> (wgpu::RenderPipeline, Option<wgpu::RenderPipeline>) {
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Wireframe Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                settings::shader_source(include_str!("shaders/wireframe.wgsl")).into(),
+            ),
+        });
+        let pipeline_layout =
+            renderer::RenderPipeline::create_pipeline_layout(device, bind_group_layouts);
+        // Tested against the scene's depth but not written, so lines never
+        // hide each other
+        let options = renderer::PipelineOptions {
+            depth_compare: wgpu::CompareFunction::LessEqual,
+            cull_mode: None,
+            sample_count,
+            ..renderer::PipelineOptions::transparent()
+        };
+
+        let thick_pipeline = renderer::RenderPipeline::from_module(
+            device,
+            &pipeline_layout,
+            color_format,
+            Some(texture::Texture::DEPTH_FORMAT),
+            &[model::EdgeInstance::desc()],
+            &shader,
+            options.clone(),
+        )
+        .render_pipeline;
+        let line_pipeline = device
+            .features()
+            .contains(wgpu::Features::POLYGON_MODE_LINE)
+            .then(|| {
+                renderer::RenderPipeline::from_module(
+                    device,
+                    &pipeline_layout,
+                    color_format,
+                    Some(texture::Texture::DEPTH_FORMAT),
+                    &[model::ModelVertex::desc()],
+                    &shader,
+                    renderer::PipelineOptions {
+                        polygon_mode: wgpu::PolygonMode::Line,
+                        vertex_entry_point: "vs_line",
+                        ..options
+                    },
+                )
+                .render_pipeline
+            });
+
+        (thick_pipeline, line_pipeline)
+    }
+
+    // None stops drawing the object's wireframe
+    pub fn set(
+        &mut self,
+        device: &wgpu::Device,
+        id: batch::ObjectId,
+        style: Option<WireframeStyle>,
+    ) {
+        let style = match style {
+            Some(style) => style,
+            None => {
+                self.objects.remove(&id);
+                return;
+            }
+        };
+        if let Some(object) = self.objects.get_mut(&id) {
+            object.style = style;
+            return;
+        }
+
+        let uniform_buffer = memory::create_buffer(
+            device,
+            &wgpu::BufferDescriptor {
+                label: Some("Wireframe Buffer"),
+                size: std::mem::size_of::<WireframeUniform>() as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            },
+        );
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+            label: Some("wireframe_bind_group"),
+        });
+        self.objects.insert(
+            id,
+            ObjectWireframe {
+                style,
+                uniform_buffer,
+                bind_group,
+            },
+        );
+    }
+
+    pub fn style(&self, id: batch::ObjectId) -> Option<WireframeStyle> {
+        self.objects.get(&id).map(|object| object.style)
+    }
+
+    // Picks up the objects' current transforms. viewport is the size of the
+    // target in pixels.
+    pub fn update(&self, queue: &wgpu::Queue, batcher: &batch::Batcher, viewport: (u32, u32)) {
+        for (id, object) in &self.objects {
+            let uniform = WireframeUniform {
+                model: batcher.transform(*id).model_matrix().into(),
+                color: object.style.color,
+                viewport: [viewport.0 as f32, viewport.1 as f32],
+                thickness: object.style.thickness,
+                _padding: 0.0,
+            };
+            queue.write_buffer(&object.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+        }
+    }
+
+    // Draws the wireframes of the objects in the layer
+    pub fn render<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        batcher: &'a batch::Batcher,
+        camera_bind_group: &'a wgpu::BindGroup,
+        layer: i32,
+    ) {
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        for (id, object) in self
+            .objects
+            .iter()
+            .filter(|(id, _)| batcher.layer(**id) == layer)
+        {
+            render_pass.set_bind_group(1, &object.bind_group, &[]);
+            let meshes = &batcher.model(id.model).meshes;
+            match &self.line_pipeline {
+                Some(line_pipeline) if object.style.thickness <= 1.0 => {
+                    render_pass.set_pipeline(line_pipeline);
+                    for mesh in meshes {
+                        render_pass.set_bind_group(2, &mesh.transform_bind_group, &[]);
+                        render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                        render_pass.set_index_buffer(
+                            mesh.index_buffer.slice(..),
+                            wgpu::IndexFormat::Uint32,
+                        );
+                        render_pass.draw_indexed(0..mesh.num_elements, 0, 0..1);
+                    }
+                }
+                _ => {
+                    render_pass.set_pipeline(&self.thick_pipeline);
+                    for mesh in meshes.iter().filter(|mesh| mesh.num_edges > 0) {
+                        render_pass.set_bind_group(2, &mesh.transform_bind_group, &[]);
+                        render_pass.set_vertex_buffer(0, mesh.edge_buffer.slice(..));
+                        render_pass.draw(0..6, 0..mesh.num_edges);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,61 @@
+// Renders the default scene headless and compares it against the reference
+// in tests/golden, which was rendered with Mesa's llvmpipe. Other adapters
+// differ slightly, hence the tolerance. Needs an adapter, so it's ignored by
+// default; run it with `cargo test --test golden -- --ignored`. After an
+// intended rendering change, set UPDATE_GOLDEN=1 to write a new reference.
+
+use std::path::PathBuf;
+
+const WIDTH: u32 = 320;
+const HEIGHT: u32 = 240;
+// Per channel difference still counted as a match
+const CHANNEL_TOLERANCE: u8 = 8;
+// Fraction of pixels allowed to differ by more than CHANNEL_TOLERANCE
+const MAX_MISMATCHED: f32 = 0.01;
+
+fn reference_path() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden/default_scene.png")
+}
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn default_scene_matches_reference() {
+    let mut state = pollster::block_on(aqua::State::new_headless(
+        WIDTH,
+        HEIGHT,
+        aqua::camera::CameraConfig::default(),
+    ))
+    .unwrap();
+    // A fixed step so anything animated lands in the same place every run
+    state.update(std::time::Duration::from_millis(16));
+    let image = state.render_to_image().unwrap();
+
+    let path = reference_path();
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        image.save(&path).unwrap();
+        eprintln!("wrote reference image {}", path.display());
+        return;
+    }
+
+    let reference = image::open(&path)
+        .unwrap_or_else(|e| panic!("No reference image {}: {}", path.display(), e))
+        .to_rgba8();
+    assert_eq!(reference.dimensions(), image.dimensions());
+    let mismatched = reference
+        .pixels()
+        .zip(image.pixels())
+        .filter(|(a, b)| {
+            a.0.iter()
+                .zip(b.0.iter())
+                .any(|(a, b)| a.abs_diff(*b) > CHANNEL_TOLERANCE)
+        })
+        .count();
+    let fraction = mismatched as f32 / (WIDTH * HEIGHT) as f32;
+    assert!(
+        fraction <= MAX_MISMATCHED,
+        "{:.2}% of pixels differ from {}",
+        fraction * 100.0,
+        path.display()
+    );
+}
@@ -0,0 +1,295 @@
+// Checks texture::generate_mipmaps on a one texel checkerboard, the worst case
+// for aliasing: every level past the first should come out as flat grey, and
+// so should the texture sampled at a fraction of its size. Needs a GPU
+// adapter, so it's ignored by default.
+
+use std::num::NonZeroU32;
+
+use aqua::{context, memory, texture};
+
+const SIZE: u32 = 64;
+const TARGET_SIZE: u32 = 4;
+
+fn checkerboard(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    format: wgpu::TextureFormat,
+) -> texture::Texture {
+    let size = wgpu::Extent3d {
+        width: SIZE,
+        height: SIZE,
+        depth_or_array_layers: 1,
+    };
+    let mip_level_count = size.max_mips();
+    let texture = memory::create_texture(
+        device,
+        &wgpu::TextureDescriptor {
+            label: Some("Checkerboard"),
+            size,
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::COPY_SRC
+                | wgpu::TextureUsages::RENDER_ATTACHMENT,
+        },
+    );
+    let texels = (0..SIZE * SIZE)
+        .flat_map(|i| {
+            let value = if (i % SIZE + i / SIZE).is_multiple_of(2) {
+                0
+            } else {
+                255
+            };
+            [value, value, value, 255]
+        })
+        .collect::<Vec<u8>>();
+    queue.write_texture(
+        wgpu::ImageCopyTexture {
+            aspect: wgpu::TextureAspect::All,
+            texture: &texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+        },
+        &texels,
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: NonZeroU32::new(4 * SIZE),
+            rows_per_image: NonZeroU32::new(SIZE),
+        },
+        size,
+    );
+
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Linear,
+        mipmap_filter: wgpu::FilterMode::Linear,
+        ..Default::default()
+    });
+    texture::Texture {
+        texture,
+        size,
+        format,
+        mip_level_count,
+        view,
+        sampler,
+        alpha_mode: texture::AlphaMode::default(),
+        address_modes: texture::AddressModes::default(),
+    }
+}
+
+// The RGBA bytes of one mip level, without the row padding of the copy
+fn read_level(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    mip_level: u32,
+    size: u32,
+) -> Vec<u8> {
+    let unpadded_bytes_per_row = 4 * size;
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(align) * align;
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Readback Buffer"),
+        size: (padded_bytes_per_row * size) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Readback Encoder"),
+    });
+    encoder.copy_texture_to_buffer(
+        wgpu::ImageCopyTexture {
+            aspect: wgpu::TextureAspect::All,
+            texture,
+            mip_level,
+            origin: wgpu::Origin3d::ZERO,
+        },
+        wgpu::ImageCopyBuffer {
+            buffer: &buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(padded_bytes_per_row),
+                rows_per_image: NonZeroU32::new(size),
+            },
+        },
+        wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 1,
+        },
+    );
+    queue.submit(std::iter::once(encoder.finish()));
+
+    let slice = buffer.slice(..);
+    let mapping = slice.map_async(wgpu::MapMode::Read);
+    device.poll(wgpu::Maintain::Wait);
+    pollster::block_on(mapping).unwrap();
+    let data = slice.get_mapped_range();
+    data.chunks(padded_bytes_per_row as usize)
+        .flat_map(|row| row[..unpadded_bytes_per_row as usize].to_vec())
+        .collect()
+}
+
+// Draws the whole texture into a TARGET_SIZE square through its own sampler,
+// which has the GPU pick a mip level SIZE / TARGET_SIZE times smaller
+fn sample_minified(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    source: &texture::Texture,
+) -> Vec<u8> {
+    let format = wgpu::TextureFormat::Rgba8Unorm;
+    let target = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Minified Target"),
+        size: wgpu::Extent3d {
+            width: TARGET_SIZE,
+            height: TARGET_SIZE,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+    });
+    let target_view = target.create_view(&wgpu::TextureViewDescriptor::default());
+
+    let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+        label: Some("Blit Shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("../src/shaders/blit.wgsl").into()),
+    });
+    let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+        ],
+        label: Some("blit_bind_group_layout"),
+    });
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout: &bind_group_layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&source.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&source.sampler),
+            },
+        ],
+        label: Some("blit_bind_group"),
+    });
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Blit Pipeline Layout"),
+        bind_group_layouts: &[&bind_group_layout],
+        push_constant_ranges: &[],
+    });
+    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Blit Pipeline"),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[format.into()],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Blit Encoder"),
+    });
+    {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Blit Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: &target_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+    queue.submit(std::iter::once(encoder.finish()));
+    read_level(device, queue, &target, 0, TARGET_SIZE)
+}
+
+fn assert_grey(texels: &[u8], expected: u8, what: &str) {
+    for texel in texels.chunks(4) {
+        for &channel in &texel[..3] {
+            assert!(
+                channel.abs_diff(expected) <= 4,
+                "{} has {:?} where {} was expected",
+                what,
+                texel,
+                expected
+            );
+        }
+    }
+}
+
+fn check_format(format: wgpu::TextureFormat, grey: u8) {
+    let context = pollster::block_on(context::Context::new_headless(1, 1)).unwrap();
+    let (device, queue) = (&context.device, &context.queue);
+
+    let checkerboard = checkerboard(device, queue, format);
+    // 64, 32, 16, 8, 4, 2 and 1
+    assert_eq!(checkerboard.mip_level_count, 7);
+    texture::generate_mipmaps(device, queue, &checkerboard).unwrap();
+
+    for level in 1..checkerboard.mip_level_count {
+        let texels = read_level(device, queue, &checkerboard.texture, level, SIZE >> level);
+        assert_eq!(
+            texels.len(),
+            4 * ((SIZE >> level) * (SIZE >> level)) as usize
+        );
+        assert_grey(&texels, grey, &format!("{:?} mip level {}", format, level));
+    }
+    // Without mipmaps this would land on single black or white texels. The
+    // target is linear, so it's half of white for either format.
+    let minified = sample_minified(device, queue, &checkerboard);
+    assert_grey(&minified, 128, &format!("Minified {:?}", format));
+}
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn mipmaps_of_linear_texture() {
+    check_format(wgpu::TextureFormat::Rgba8Unorm, 128);
+}
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn mipmaps_of_srgb_texture() {
+    // Averaged in linear, half of white is 188 once encoded again
+    check_format(wgpu::TextureFormat::Rgba8UnormSrgb, 188);
+}